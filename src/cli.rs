@@ -0,0 +1,215 @@
+use std::error::Error;
+use std::path::PathBuf;
+
+use crate::http_client::HostOverride;
+use crate::tls::{ClientCert, TlsConfig};
+
+#[derive(Debug, Default)]
+pub struct Args {
+    pub url: Option<String>,
+    pub tls: TlsConfig,
+    /// Write every request and response to this file as HAR when the browser exits.
+    pub har: Option<PathBuf>,
+    /// Save every response into this directory so it can be replayed later.
+    pub record: Option<PathBuf>,
+    /// Serve responses from a directory made with `--record` instead of the network.
+    pub replay: Option<PathBuf>,
+    /// Where files that aren't pages are saved.
+    pub downloads: Option<PathBuf>,
+    /// Hosts to connect to at fixed addresses instead of looking them up.
+    pub resolve: Vec<HostOverride>,
+    /// Hosts that are always loaded over https, one per line.
+    pub hsts_preload: Option<PathBuf>,
+    /// Remembers the certificates of gemini servers between runs.
+    pub known_hosts: Option<PathBuf>,
+    /// Print the page's document as HTML and exit instead of opening a window.
+    pub dump_dom: bool,
+    /// Print the `--dump-dom` output indented, an element per line.
+    pub pretty: bool,
+    /// Print where the page's HTML breaks the spec and exit instead of opening a window.
+    pub parse_errors: bool,
+}
+
+const USAGE: &str = "usage: browser-engineering [options] <url>
+
+options:
+  --ca-cert <file>      trust the root certificates in this PEM file (repeatable)
+  --system-roots        trust the operating system's root certificates
+  --client-cert <file>  PEM certificate chain to present for mutual TLS
+  --client-key <file>   PEM private key belonging to --client-cert
+  --insecure            accept invalid certificates (local testing only)
+  --har <file>          record all requests and write them as HAR on exit
+  --record <dir>        save every response into <dir> as a fixture
+  --replay <dir>        serve responses from fixtures in <dir>, offline
+  --downloads <dir>     save files here instead of ~/Downloads
+  --resolve <host:port:addr[,addr]>
+                        connect to host:port at these addresses (repeatable)
+  --hsts-preload <file> always use https for the hosts listed in <file>
+  --known-hosts <file>  remember gemini server certificates in <file>
+  --dump-dom            print the parsed page as HTML and exit
+  --pretty              indent the --dump-dom output
+  --parse-errors        print the page's HTML parse errors and exit,
+                        with status 1 if there are any";
+
+impl Args {
+    pub fn parse(args: &[String]) -> Result<Args, Box<dyn Error>> {
+        let mut parsed = Args::default();
+        let mut client_cert: Option<PathBuf> = None;
+        let mut client_key: Option<PathBuf> = None;
+
+        let mut args = args.iter().skip(1);
+
+        while let Some(arg) = args.next() {
+            let mut value = |flag: &str| {
+                args.next()
+                    .map(PathBuf::from)
+                    .ok_or(format!("{flag} needs a value\n\n{USAGE}"))
+            };
+
+            match arg.as_str() {
+                "--ca-cert" => parsed.tls.extra_root_certs.push(value(arg)?),
+                "--system-roots" => parsed.tls.use_system_roots = true,
+                "--client-cert" => client_cert = Some(value(arg)?),
+                "--client-key" => client_key = Some(value(arg)?),
+                "--insecure" => parsed.tls.accept_invalid_certs = true,
+                "--har" => parsed.har = Some(value(arg)?),
+                "--record" => parsed.record = Some(value(arg)?),
+                "--replay" => parsed.replay = Some(value(arg)?),
+                "--downloads" => parsed.downloads = Some(value(arg)?),
+                "--hsts-preload" => parsed.hsts_preload = Some(value(arg)?),
+                "--known-hosts" => parsed.known_hosts = Some(value(arg)?),
+                "--dump-dom" => parsed.dump_dom = true,
+                "--pretty" => parsed.pretty = true,
+                "--parse-errors" => parsed.parse_errors = true,
+                "--resolve" => {
+                    let pinned = value(arg)?;
+                    parsed
+                        .resolve
+                        .push(HostOverride::parse(&pinned.to_string_lossy())?);
+                }
+                "--help" | "-h" => return Err(USAGE.into()),
+                flag if flag.starts_with("--") => {
+                    return Err(format!("unknown option {flag}\n\n{USAGE}").into());
+                }
+                url => parsed.url = Some(url.to_string()),
+            }
+        }
+
+        parsed.tls.client_cert = match (client_cert, client_key) {
+            (Some(cert_chain), Some(private_key)) => Some(ClientCert {
+                cert_chain,
+                private_key,
+            }),
+            (None, None) => None,
+            _ => return Err("--client-cert and --client-key must be given together".into()),
+        };
+
+        if parsed.record.is_some() && parsed.replay.is_some() {
+            return Err("--record and --replay can not be used together".into());
+        }
+
+        if parsed.pretty && !parsed.dump_dom {
+            return Err("--pretty only works with --dump-dom".into());
+        }
+
+        if parsed.dump_dom && parsed.parse_errors {
+            return Err("--dump-dom and --parse-errors can not be used together".into());
+        }
+
+        Ok(parsed)
+    }
+}
+
+#[test]
+fn parse_tls_options() {
+    let args: Vec<String> = [
+        "browser",
+        "--ca-cert",
+        "internal-ca.pem",
+        "--client-cert",
+        "me.pem",
+        "--client-key",
+        "me.key",
+        "https://staging.example/",
+    ]
+    .iter()
+    .map(|s| s.to_string())
+    .collect();
+
+    let parsed = Args::parse(&args).expect("should parse");
+
+    assert_eq!(parsed.url, Some("https://staging.example/".to_string()));
+    assert_eq!(
+        parsed.tls.extra_root_certs,
+        vec![PathBuf::from("internal-ca.pem")]
+    );
+    assert!(parsed.tls.client_cert.is_some());
+    assert!(!parsed.tls.accept_invalid_certs);
+}
+
+#[test]
+fn parse_resolve() {
+    let args: Vec<String> = [
+        "browser",
+        "--resolve",
+        "intranet:443:10.0.0.5",
+        "https://intranet/",
+    ]
+    .iter()
+    .map(|s| s.to_string())
+    .collect();
+
+    let parsed = Args::parse(&args).expect("should parse");
+
+    assert_eq!(parsed.resolve[0].host, "intranet");
+    assert_eq!(parsed.resolve[0].port, 443);
+}
+
+#[test]
+fn client_key_without_cert_is_an_error() {
+    let args: Vec<String> = ["browser", "--client-key", "me.key", "https://example.com/"]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+
+    assert!(Args::parse(&args).is_err());
+}
+
+#[test]
+fn parse_dump_dom() {
+    let args = |args: &[&str]| -> Vec<String> { args.iter().map(|s| s.to_string()).collect() };
+
+    let parsed = Args::parse(&args(&[
+        "browser",
+        "--dump-dom",
+        "--pretty",
+        "https://example.com/",
+    ]))
+    .expect("should parse");
+    assert!(parsed.dump_dom && parsed.pretty);
+
+    assert!(Args::parse(&args(&["browser", "--pretty", "https://example.com/"])).is_err());
+}
+
+#[test]
+fn parse_parse_errors() {
+    let args = |args: &[&str]| -> Vec<String> { args.iter().map(|s| s.to_string()).collect() };
+
+    let parsed = Args::parse(&args(&[
+        "browser",
+        "--parse-errors",
+        "https://example.com/",
+    ]))
+    .expect("should parse");
+    assert!(parsed.parse_errors && !parsed.dump_dom);
+
+    assert!(
+        Args::parse(&args(&[
+            "browser",
+            "--parse-errors",
+            "--dump-dom",
+            "https://example.com/"
+        ]))
+        .is_err()
+    );
+}
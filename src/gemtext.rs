@@ -0,0 +1,84 @@
+//! Turns `text/gemini` documents into the markup the layout understands,
+//! https://geminiprotocol.net/docs/gemtext-specification.gmi. Every gemtext
+//! line is a line of its own on screen, so each one ends with `<br>`.
+use browser_engineering::html::escape;
+
+use crate::url::URL;
+
+pub fn to_html(document: &str, base: &URL) -> String {
+    let mut html = String::new();
+    let mut preformatted = false;
+
+    for line in document.lines() {
+        if line.starts_with("```") {
+            // the rest of an opening line is alt text for screen readers
+            html.push_str(if preformatted { "</pre>" } else { "<pre>" });
+            preformatted = !preformatted;
+            continue;
+        }
+        if preformatted {
+            html.push_str(&escape(line, false));
+            html.push_str("<br>");
+            continue;
+        }
+
+        if let Some(link) = line.strip_prefix("=>") {
+            let link = link.trim();
+            let (target, label) = link
+                .split_once(char::is_whitespace)
+                .map(|(target, label)| (target, label.trim()))
+                .unwrap_or((link, link));
+            let href = match base.resolve(target) {
+                Ok(url) => url.to_string(),
+                Err(_) => target.to_string(),
+            };
+            html.push_str(&format!(
+                "=> <a href=\"{}\"><i>{}</i></a>",
+                escape(&href, true),
+                escape(label, false)
+            ));
+        } else if let Some(heading) = ["###", "##", "#"]
+            .iter()
+            .find_map(|prefix| line.strip_prefix(prefix))
+        {
+            html.push_str(&format!("<b>{}</b>", escape(heading.trim(), false)));
+        } else if let Some(item) = line.strip_prefix("* ") {
+            html.push_str(&format!("• {}", escape(item, false)));
+        } else if let Some(quote) = line.strip_prefix('>') {
+            html.push_str(&format!("<i>{}</i>", escape(quote.trim(), false)));
+        } else {
+            html.push_str(&escape(line, false));
+        }
+        html.push_str("<br>");
+    }
+
+    if preformatted {
+        html.push_str("</pre>");
+    }
+    html
+}
+
+#[test]
+fn gemtext_lines() {
+    let base = URL::parse("gemini://example.org/blog/").expect("hej");
+    let document = "# Blog\n\
+                    Some <text>\n\
+                    => post.gmi First post\n\
+                    =>gemini://other.net/\n\
+                    * one\n\
+                    > quoted\n\
+                    ``` ascii art\n\
+                    ## not a heading\n\
+                    ```\n";
+
+    assert_eq!(
+        to_html(document, &base),
+        "<b>Blog</b><br>\
+         Some &lt;text&gt;<br>\
+         => <a href=\"gemini://example.org/blog/post.gmi\"><i>First post</i></a><br>\
+         => <a href=\"gemini://other.net/\"><i>gemini://other.net/</i></a><br>\
+         • one<br>\
+         <i>quoted</i><br>\
+         <pre>## not a heading<br></pre>"
+    );
+}
@@ -0,0 +1,100 @@
+//! Shows gopher items, https://www.rfc-editor.org/rfc/rfc1436. Menus become a
+//! list of links, one per line, and text files are kept as they are. Both end
+//! at a line with a single `.` if the server sends one.
+use browser_engineering::html::escape;
+
+use crate::url::percent_encode;
+
+pub fn menu_to_html(menu: &str) -> String {
+    let mut html = String::new();
+
+    for line in lines(menu) {
+        let mut chars = line.chars();
+        let Some(item_type) = chars.next() else {
+            continue;
+        };
+        let mut fields = chars.as_str().split('\t');
+        let display = escape(fields.next().unwrap_or(""), false);
+        let (selector, host, port) = (
+            fields.next().unwrap_or(""),
+            fields.next().unwrap_or(""),
+            fields.next().unwrap_or("70").trim(),
+        );
+
+        match item_type {
+            'i' => html.push_str(&display),
+            '3' => html.push_str(&format!("<i>{display}</i>")),
+            // links to the web, https://en.wikipedia.org/wiki/Gopher_(protocol)#URL_links
+            'h' if selector.starts_with("URL:") => html.push_str(&format!(
+                "=> <a href=\"{}\"><i>{display}</i></a>",
+                escape(&selector["URL:".len()..], true)
+            )),
+            item_type => {
+                let port = match port {
+                    "70" | "" => String::new(),
+                    port => format!(":{port}"),
+                };
+                let selector = percent_encode(selector).replace("%2F", "/");
+                let search = if item_type == '7' { " (search)" } else { "" };
+                html.push_str(&format!(
+                    "=> <a href=\"gopher://{}{port}/{item_type}{}\"><i>{display}</i></a>{search}",
+                    escape(host, true),
+                    escape(&selector, true),
+                ));
+            }
+        }
+        html.push_str("<br>");
+    }
+
+    html
+}
+
+pub fn text_to_html(text: &str) -> String {
+    let mut html = String::from("<pre>");
+    for line in lines(text) {
+        html.push_str(&escape(line, false));
+        html.push_str("<br>");
+    }
+    html.push_str("</pre>");
+    html
+}
+
+/// Lines up to the terminating `.`, with a `..` at the start of a line
+/// unescaped to `.`.
+fn lines(item: &str) -> impl Iterator<Item = &str> {
+    item.lines().take_while(|line| *line != ".").map(|line| {
+        line.strip_prefix('.')
+            .filter(|rest| rest.starts_with('.'))
+            .unwrap_or(line)
+    })
+}
+
+#[test]
+fn menus_become_links() {
+    let menu = "iWelcome\t\tnull.host\t1\r\n\
+                1Docs & more\t/docs\texample.org\t70\r\n\
+                0About me\t/about me.txt\texample.org\t7070\r\n\
+                7Search\t/search\texample.org\t70\r\n\
+                hWeb\tURL:https://example.com/\texample.org\t70\r\n\
+                3Gone\t\terror.host\t1\r\n\
+                .\r\n\
+                iafter the end\t\tnull.host\t1\r\n";
+
+    assert_eq!(
+        menu_to_html(menu),
+        "Welcome<br>\
+         => <a href=\"gopher://example.org/1/docs\"><i>Docs &amp; more</i></a><br>\
+         => <a href=\"gopher://example.org:7070/0/about%20me.txt\"><i>About me</i></a><br>\
+         => <a href=\"gopher://example.org/7/search\"><i>Search</i></a> (search)<br>\
+         => <a href=\"https://example.com/\"><i>Web</i></a><br>\
+         <i>Gone</i><br>"
+    );
+}
+
+#[test]
+fn text_is_preformatted() {
+    assert_eq!(
+        text_to_html("a <b>\r\n..dots\r\n.\r\n"),
+        "<pre>a &lt;b&gt;<br>.dots<br></pre>"
+    );
+}
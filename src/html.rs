@@ -0,0 +1,342 @@
+//! Turns HTML into a document tree the way browsers do: the `tokenizer` splits
+//! it into tags, text and comments and the `tree_builder` puts those together,
+//! both following https://html.spec.whatwg.org/multipage/parsing.html.
+mod dom;
+mod entities;
+mod entity_table;
+mod selector;
+mod serializer;
+pub mod tokenizer;
+mod tree_builder;
+
+use std::{
+    fmt,
+    sync::{Arc, Mutex},
+};
+
+pub use dom::{Document, Element, Namespace, NodeData, NodeId};
+pub use selector::SelectorList;
+pub use serializer::escape;
+use tokenizer::Tokenizer;
+pub use tree_builder::QuirksMode;
+use tree_builder::TreeBuilder;
+
+/// Where a page breaks the rules of the spec. The parser goes on the way the
+/// spec says browsers do, so these only matter to whoever wrote the page.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    /// The code the spec gives the error, like `eof-in-tag`, see
+    /// https://html.spec.whatwg.org/multipage/parsing.html#parse-errors. The
+    /// spec does not name errors in tree construction, those have codes of our
+    /// own in the same style, like `unexpected-end-tag`.
+    pub code: &'static str,
+    /// From 1, where the tokenizer was in the input when it found the error,
+    /// for tree construction errors the end of the token.
+    pub line: usize,
+    pub column: usize,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.code)
+    }
+}
+
+/// Collects the parse errors of a page, see `Parser::with_diagnostics`.
+#[derive(Debug, Default)]
+pub struct Diagnostics {
+    errors: Mutex<Vec<ParseError>>,
+}
+
+impl Diagnostics {
+    pub fn record(&self, error: ParseError) {
+        log::debug!("html parse error: {error}");
+        self.errors.lock().unwrap().push(error);
+    }
+
+    /// The errors so far, in the order they were found.
+    pub fn errors(&self) -> Vec<ParseError> {
+        self.errors.lock().unwrap().clone()
+    }
+}
+
+pub fn parse(html: &str) -> Document {
+    let mut parser = Parser::default();
+    parser.feed(html);
+    parser.finish()
+}
+
+/// Runs the tokenizer and tree builder together, the tree builder tells the
+/// tokenizer which state to go on in after tags like `<title>`. Both stop
+/// wherever the input runs out and go on from there when more is fed, so a
+/// page can be parsed while it downloads.
+#[derive(Default)]
+pub struct Parser {
+    tokenizer: Tokenizer,
+    builder: TreeBuilder,
+    /// The start of a UTF-8 character whose other bytes are still to come.
+    undecoded: Vec<u8>,
+    diagnostics: Option<Arc<Diagnostics>>,
+}
+
+impl Parser {
+    /// Records the parse errors in `diagnostics`, they are dropped otherwise.
+    pub fn with_diagnostics(mut self, diagnostics: Arc<Diagnostics>) -> Parser {
+        self.diagnostics = Some(diagnostics);
+        self
+    }
+
+    pub fn feed(&mut self, html: &str) {
+        self.tokenizer.feed(html);
+        self.run();
+    }
+
+    /// Feeds UTF-8 as it comes off the network, invalid bytes become U+FFFD.
+    pub fn feed_bytes(&mut self, bytes: &[u8]) {
+        self.undecoded.extend_from_slice(bytes);
+        let mut html = String::new();
+        let mut rest = &self.undecoded[..];
+        loop {
+            match str::from_utf8(rest) {
+                Ok(valid) => {
+                    html.push_str(valid);
+                    rest = &[];
+                    break;
+                }
+                Err(error) => {
+                    let (valid, invalid) = rest.split_at(error.valid_up_to());
+                    html.push_str(str::from_utf8(valid).unwrap());
+                    match error.error_len() {
+                        Some(length) => {
+                            html.push('\u{FFFD}');
+                            rest = &invalid[length..];
+                        }
+                        // cut off at the end of the chunk
+                        None => {
+                            rest = invalid;
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+        self.undecoded = rest.to_vec();
+        self.feed(&html);
+    }
+
+    /// The document as far as it is parsed, to show the page before all of
+    /// it is there.
+    pub fn document(&self) -> &Document {
+        self.builder.document()
+    }
+
+    pub fn finish(mut self) -> Document {
+        if !self.undecoded.is_empty() {
+            self.tokenizer.feed("\u{FFFD}");
+        }
+        self.tokenizer.end();
+        self.run();
+        self.builder.finish()
+    }
+
+    fn run(&mut self) {
+        while let Some(token) = self.tokenizer.next_token() {
+            self.builder.process(token);
+            if let Some(state) = self.builder.tokenizer_state.take() {
+                self.tokenizer.state = state;
+            }
+            self.tokenizer.cdata_allowed = self.builder.in_foreign_content();
+
+            let tree_errors = self.builder.take_errors();
+            let tokenizer_errors = self.tokenizer.take_errors();
+            if let Some(diagnostics) = &self.diagnostics {
+                for error in tokenizer_errors {
+                    diagnostics.record(error);
+                }
+                if !tree_errors.is_empty() {
+                    let (line, column) = self.tokenizer.location();
+                    for code in tree_errors {
+                        diagnostics.record(ParseError { code, line, column });
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+fn tree(html: &str) -> String {
+    parse(html).dump()
+}
+
+#[test]
+fn comments_and_doctype() {
+    assert_eq!(
+        tree("<!DOCTYPE html><!-- a > b --><!--><?xml version=\"1.0\"?><!x>a < b<![CDATA[<i>]]>"),
+        "| <!DOCTYPE html>\n\
+         | <!--  a > b  -->\n\
+         | <!--  -->\n\
+         | <!-- ?xml version=\"1.0\"? -->\n\
+         | <!-- x -->\n\
+         | <html>\n\
+         |   <head>\n\
+         |   <body>\n\
+         |     \"a < b\"\n\
+         |     <!-- [CDATA[<i -->\n\
+         |     \"]]>\"\n"
+    );
+    assert_eq!(
+        tree("hi<!-- open"),
+        "| <html>\n|   <head>\n|   <body>\n|     \"hi\"\n|     <!--  open -->\n"
+    );
+    assert_eq!(
+        tree("hi<a href='x"),
+        "| <html>\n|   <head>\n|   <body>\n|     \"hi\"\n"
+    );
+}
+
+#[test]
+fn raw_text_elements() {
+    assert_eq!(
+        tree("<script>if (a < b && c) { x = '</p>' }</script ><style>p > b {}</STYLE>"),
+        "| <html>\n\
+         |   <head>\n\
+         |     <script>\n\
+         |       \"if (a < b && c) { x = '</p>' }\"\n\
+         |     <style>\n\
+         |       \"p > b {}\"\n\
+         |   <body>\n"
+    );
+    assert_eq!(
+        tree("<title>a &amp; <b></title><textarea></textarea>"),
+        "| <html>\n\
+         |   <head>\n\
+         |     <title>\n\
+         |       \"a & <b>\"\n\
+         |   <body>\n\
+         |     <textarea>\n"
+    );
+    // not closed, the rest of the page is script
+    assert_eq!(
+        tree("<script>a</scripts>"),
+        "| <html>\n|   <head>\n|     <script>\n|       \"a</scripts>\"\n|   <body>\n"
+    );
+}
+
+#[test]
+fn references_in_text_and_attributes() {
+    assert_eq!(
+        tree("<a href=\"?a=1&amp;copy=2&copy=3\">&lt;&copy;&gt;</a>"),
+        "| <html>\n\
+         |   <head>\n\
+         |   <body>\n\
+         |     <a>\n\
+         |       href=\"?a=1&copy=2&copy=3\"\n\
+         |       \"<©>\"\n"
+    );
+}
+
+#[test]
+fn tag_attributes() {
+    assert_eq!(
+        tree(r#"<A HREF="/a b>c" title='it"s' class=x disabled href=no><br/><img src = x.png />"#),
+        "| <html>\n\
+         |   <head>\n\
+         |   <body>\n\
+         |     <a>\n\
+         |       class=\"x\"\n\
+         |       disabled=\"\"\n\
+         |       href=\"/a b>c\"\n\
+         |       title=\"it\"s\"\n\
+         |       <br>\n\
+         |       <img>\n\
+         |         src=\"x.png\"\n"
+    );
+    // a slash in an unquoted value belongs to the value
+    assert_eq!(
+        tree("<a href=/docs/>"),
+        "| <html>\n|   <head>\n|   <body>\n|     <a>\n|       href=\"/docs/\"\n"
+    );
+}
+
+#[test]
+fn fed_in_pieces() {
+    let html = "<!DOCTYPE html><title>a &amp; b</title><p class=x>one\r\ntwo</p><!-- c -->";
+    let mut parser = Parser::default();
+    for c in html.chars() {
+        parser.feed(&c.to_string());
+    }
+    assert_eq!(parser.finish().dump(), tree(html));
+}
+
+#[test]
+fn fed_bytes_split_inside_characters() {
+    let html = "<p title=\"é\">日本 😀</p>";
+    let mut parser = Parser::default();
+    for byte in html.as_bytes() {
+        parser.feed_bytes(&[*byte]);
+    }
+    assert_eq!(parser.finish().dump(), tree(html));
+
+    let mut parser = Parser::default();
+    parser.feed_bytes(b"<p>a\xFFb\xE6\x97");
+    assert_eq!(
+        parser.document().text_content(parser.document().root()),
+        "a\u{FFFD}b"
+    );
+    assert_eq!(parser.finish().dump(), tree("<p>a\u{FFFD}b\u{FFFD}"));
+}
+
+#[test]
+fn partial_documents() {
+    let mut parser = Parser::default();
+    parser.feed("<title>Page</title><p>first <b>bold");
+    assert_eq!(
+        parser.document().dump(),
+        "| <html>\n\
+         |   <head>\n\
+         |     <title>\n\
+         |       \"Page\"\n\
+         |   <body>\n\
+         |     <p>\n\
+         |       \"first \"\n\
+         |       <b>\n\
+         |         \"bold\"\n"
+    );
+    parser.feed("</b> then</p>");
+    assert_eq!(
+        parser.finish().dump(),
+        tree("<title>Page</title><p>first <b>bold</b> then</p>")
+    );
+}
+
+#[test]
+fn diagnostics() {
+    let diagnostics = Arc::new(Diagnostics::default());
+    let mut parser = Parser::default().with_diagnostics(diagnostics.clone());
+    parser.feed("<!DOCTYPE html>\n<p id=a id=b>x &copy y</i>\n<table><tr>z\n<div");
+    parser.finish();
+
+    let errors: Vec<String> = diagnostics
+        .errors()
+        .iter()
+        .map(ToString::to_string)
+        .collect();
+    assert_eq!(
+        errors,
+        [
+            "2:13: duplicate-attribute",
+            "2:16: missing-semicolon-after-character-reference",
+            "2:26: end-tag-without-start-tag",
+            "4:5: eof-in-tag",
+            // text in a table is looked at when something else comes
+            "4:5: content-in-table",
+            "4:5: end-of-file-with-open-elements",
+        ]
+    );
+
+    // without a collector nothing piles up
+    let mut parser = Parser::default();
+    parser.feed("</x></y>");
+    assert!(parser.tokenizer.take_errors().is_empty() && parser.builder.take_errors().is_empty());
+}
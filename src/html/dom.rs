@@ -0,0 +1,354 @@
+//! The document as a tree. Nodes live in one `Vec` owned by the `Document` and
+//! refer to each other by index, so parents, children and siblings are all
+//! cheap to get to and moving a node around is just changing indices.
+use std::{error::Error, fmt::Write};
+
+use super::selector::SelectorList;
+
+/// Attributes of SVG and MathML elements that are in the XLink, XML or XMLNS
+/// namespace.
+const FOREIGN_ATTRIBUTES: [&str; 11] = [
+    "xlink:actuate",
+    "xlink:arcrole",
+    "xlink:href",
+    "xlink:role",
+    "xlink:show",
+    "xlink:title",
+    "xlink:type",
+    "xml:lang",
+    "xml:space",
+    "xmlns",
+    "xmlns:xlink",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct NodeId(usize);
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum NodeData {
+    Document,
+    Doctype {
+        name: String,
+        public_id: Option<String>,
+        system_id: Option<String>,
+    },
+    Element(Element),
+    Text(String),
+    Comment(String),
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Namespace {
+    #[default]
+    Html,
+    Svg,
+    MathMl,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Element {
+    /// Lowercase for HTML, SVG has some camel case names like `foreignObject`.
+    pub name: String,
+    /// Namespaced attributes of SVG and MathML keep their prefix, `xlink:href`.
+    pub attributes: Vec<(String, String)>,
+    pub namespace: Namespace,
+}
+
+impl Element {
+    pub fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|(attribute, _)| attribute == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DomNode {
+    pub data: NodeData,
+    pub parent: Option<NodeId>,
+    pub children: Vec<NodeId>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Document {
+    nodes: Vec<DomNode>,
+}
+
+impl Default for Document {
+    fn default() -> Document {
+        Document {
+            nodes: vec![DomNode {
+                data: NodeData::Document,
+                parent: None,
+                children: Vec::new(),
+            }],
+        }
+    }
+}
+
+impl Document {
+    /// The document node itself, parent of the doctype and `html`.
+    pub fn root(&self) -> NodeId {
+        NodeId(0)
+    }
+
+    pub fn data(&self, id: NodeId) -> &NodeData {
+        &self.nodes[id.0].data
+    }
+
+    pub fn children(&self, id: NodeId) -> &[NodeId] {
+        &self.nodes[id.0].children
+    }
+
+    pub fn parent(&self, id: NodeId) -> Option<NodeId> {
+        self.nodes[id.0].parent
+    }
+
+    pub fn element(&self, id: NodeId) -> Option<&Element> {
+        match &self.nodes[id.0].data {
+            NodeData::Element(element) => Some(element),
+            _ => None,
+        }
+    }
+
+    pub fn element_mut(&mut self, id: NodeId) -> Option<&mut Element> {
+        match &mut self.nodes[id.0].data {
+            NodeData::Element(element) => Some(element),
+            _ => None,
+        }
+    }
+
+    /// The tag name if `id` is an element.
+    pub fn name(&self, id: NodeId) -> Option<&str> {
+        self.element(id).map(|element| element.name.as_str())
+    }
+
+    /// A node not in the tree yet.
+    pub fn create(&mut self, data: NodeData) -> NodeId {
+        self.nodes.push(DomNode {
+            data,
+            parent: None,
+            children: Vec::new(),
+        });
+        NodeId(self.nodes.len() - 1)
+    }
+
+    /// Makes `child` the last child of `parent`, taking it out of where it was.
+    /// Text next to text is merged, so `child` may end up unused.
+    pub fn append(&mut self, parent: NodeId, child: NodeId) {
+        let index = self.children(parent).len();
+        self.insert(parent, index, child);
+    }
+
+    /// Adds `text` at `index` in `parent`, to the text node before it if there
+    /// is one.
+    pub fn insert_text(&mut self, parent: NodeId, index: usize, text: &str) {
+        let before = index.checked_sub(1).map(|i| self.children(parent)[i]);
+        if let Some(before) = before
+            && let NodeData::Text(existing) = &mut self.nodes[before.0].data
+        {
+            existing.push_str(text);
+            return;
+        }
+
+        let node = self.create(NodeData::Text(text.to_string()));
+        self.nodes[node.0].parent = Some(parent);
+        self.nodes[parent.0].children.insert(index, node);
+    }
+
+    /// Makes `child` the `index`th child of `parent`.
+    pub fn insert(&mut self, parent: NodeId, index: usize, child: NodeId) {
+        self.detach(child);
+
+        let text = match self.data(child) {
+            NodeData::Text(text) => Some(text.clone()),
+            _ => None,
+        };
+        let before = index.checked_sub(1).map(|i| self.children(parent)[i]);
+        if let Some(text) = text
+            && let Some(before) = before
+            && let NodeData::Text(existing) = &mut self.nodes[before.0].data
+        {
+            existing.push_str(&text);
+            return;
+        }
+
+        self.nodes[child.0].parent = Some(parent);
+        self.nodes[parent.0].children.insert(index, child);
+    }
+
+    /// Takes `id` out of its parent, it keeps its own children.
+    pub fn detach(&mut self, id: NodeId) {
+        if let Some(parent) = self.nodes[id.0].parent.take() {
+            self.nodes[parent.0].children.retain(|child| *child != id);
+        }
+    }
+
+    /// `id` and everything below it, in document order.
+    pub fn descendants(&self, id: NodeId) -> Vec<NodeId> {
+        let mut found = Vec::new();
+        let mut stack = vec![id];
+        while let Some(id) = stack.pop() {
+            found.push(id);
+            stack.extend(self.children(id).iter().rev());
+        }
+        found
+    }
+
+    /// All the text below `id`, without any markup.
+    pub fn text_content(&self, id: NodeId) -> String {
+        self.descendants(id)
+            .into_iter()
+            .filter_map(|id| match self.data(id) {
+                NodeData::Text(text) => Some(text.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// The first element in document order that `selectors` matches, an
+    /// error if they are not valid.
+    pub fn query_selector(&self, selectors: &str) -> Result<Option<NodeId>, Box<dyn Error>> {
+        Ok(self.query_selector_all(selectors)?.into_iter().next())
+    }
+
+    /// Every element that `selectors` matches, in document order.
+    pub fn query_selector_all(&self, selectors: &str) -> Result<Vec<NodeId>, Box<dyn Error>> {
+        let selectors = SelectorList::parse(selectors)?;
+        Ok(self
+            .descendants(self.root())
+            .into_iter()
+            .filter(|id| selectors.matches(self, *id))
+            .collect())
+    }
+
+    /// Whether the element `id` matches `selectors`. Elements do not know
+    /// where they are in the tree, so this is on the document.
+    pub fn matches(&self, id: NodeId, selectors: &str) -> Result<bool, Box<dyn Error>> {
+        Ok(SelectorList::parse(selectors)?.matches(self, id))
+    }
+
+    /// The first element with the `id` attribute `element_id`.
+    pub fn get_element_by_id(&self, element_id: &str) -> Option<NodeId> {
+        self.descendants(self.root())
+            .into_iter()
+            .find(|id| self.element(*id).and_then(|e| e.attribute("id")) == Some(element_id))
+    }
+
+    /// Elements named `name`, or all of them for `*`, in document order.
+    pub fn get_elements_by_tag_name(&self, name: &str) -> Vec<NodeId> {
+        self.descendants(self.root())
+            .into_iter()
+            .filter(|id| {
+                self.element(*id)
+                    .is_some_and(|element| match element.namespace {
+                        _ if name == "*" => true,
+                        Namespace::Html => element.name.eq_ignore_ascii_case(name),
+                        _ => element.name == name,
+                    })
+            })
+            .collect()
+    }
+
+    /// The tree in the format of the html5lib tests, a line per node indented
+    /// by depth, handy to compare trees in tests.
+    pub fn dump(&self) -> String {
+        let mut out = String::new();
+        self.dump_children(self.root(), 0, &mut out);
+        out
+    }
+
+    fn dump_children(&self, id: NodeId, depth: usize, out: &mut String) {
+        for &child in self.children(id) {
+            let indent = "  ".repeat(depth);
+            let _ = match self.data(child) {
+                NodeData::Document => Ok(()),
+                NodeData::Doctype {
+                    name,
+                    public_id,
+                    system_id,
+                } => {
+                    let public_id = public_id.as_deref().unwrap_or_default();
+                    let system_id = system_id.as_deref().unwrap_or_default();
+                    match public_id.is_empty() && system_id.is_empty() {
+                        true => writeln!(out, "| {indent}<!DOCTYPE {name}>"),
+                        false => writeln!(
+                            out,
+                            "| {indent}<!DOCTYPE {name} \"{public_id}\" \"{system_id}\">"
+                        ),
+                    }
+                }
+                NodeData::Text(text) => writeln!(out, "| {indent}\"{text}\""),
+                NodeData::Comment(comment) => writeln!(out, "| {indent}<!-- {comment} -->"),
+                NodeData::Element(element) => {
+                    let prefix = match element.namespace {
+                        Namespace::Html => "",
+                        Namespace::Svg => "svg ",
+                        Namespace::MathMl => "math ",
+                    };
+                    let _ = writeln!(out, "| {indent}<{prefix}{}>", element.name);
+
+                    let mut attributes: Vec<_> = element
+                        .attributes
+                        .iter()
+                        .map(|(name, value)| match element.namespace {
+                            // the tests write the namespace of `xlink:href` as `xlink href`
+                            Namespace::Svg | Namespace::MathMl
+                                if FOREIGN_ATTRIBUTES.contains(&name.as_str()) =>
+                            {
+                                match name.split_once(':') {
+                                    Some((prefix, local)) => (format!("{prefix} {local}"), value),
+                                    None => (format!("{name} {name}"), value),
+                                }
+                            }
+                            _ => (name.clone(), value),
+                        })
+                        .collect();
+                    attributes.sort();
+                    for (name, value) in attributes {
+                        let _ = writeln!(out, "| {indent}  {name}=\"{value}\"");
+                    }
+
+                    // what is in a template is its content
+                    if element.name == "template" && element.namespace == Namespace::Html {
+                        let _ = writeln!(out, "| {indent}  content");
+                        self.dump_children(child, depth + 2, out);
+                        continue;
+                    }
+                    Ok(())
+                }
+            };
+            self.dump_children(child, depth + 1, out);
+        }
+    }
+}
+
+#[test]
+fn tree_operations() {
+    let mut document = Document::default();
+    let root = document.root();
+    let div = document.create(NodeData::Element(Element {
+        name: "div".to_string(),
+        attributes: vec![("id".to_string(), "main".to_string())],
+        ..Element::default()
+    }));
+    let hello = document.create(NodeData::Text("hello ".to_string()));
+    let world = document.create(NodeData::Text("world".to_string()));
+    let comment = document.create(NodeData::Comment("c".to_string()));
+
+    document.append(root, div);
+    document.append(div, hello);
+    document.append(div, world);
+    document.insert(div, 0, comment);
+    assert_eq!(document.children(div), [comment, hello]);
+    assert_eq!(document.text_content(root), "hello world");
+    assert_eq!(document.parent(hello), Some(div));
+    assert_eq!(
+        document.dump(),
+        "| <div>\n|   id=\"main\"\n|   <!-- c -->\n|   \"hello world\"\n"
+    );
+
+    document.detach(comment);
+    assert_eq!(document.descendants(root), [root, div, hello]);
+}
@@ -0,0 +1,166 @@
+//! Character references, `&amp;`, `&#233;` and `&#x1F600;`, following
+//! https://html.spec.whatwg.org/multipage/parsing.html#character-reference-state.
+//! Anything that does not turn out to be a reference stays as it was written.
+use super::entity_table::NAMED;
+
+/// Longest name in the table, `&CounterClockwiseContourIntegral;`.
+const LONGEST_NAME: usize = 32;
+
+/// What numeric references to the C1 controls mean, pages using them were
+/// written in windows-1252.
+const WINDOWS_1252: [char; 32] = [
+    '\u{20AC}', '\u{81}', '\u{201A}', '\u{0192}', '\u{201E}', '\u{2026}', '\u{2020}', '\u{2021}',
+    '\u{02C6}', '\u{2030}', '\u{0160}', '\u{2039}', '\u{0152}', '\u{8D}', '\u{017D}', '\u{8F}',
+    '\u{90}', '\u{2018}', '\u{2019}', '\u{201C}', '\u{201D}', '\u{2022}', '\u{2013}', '\u{2014}',
+    '\u{02DC}', '\u{2122}', '\u{0161}', '\u{203A}', '\u{0153}', '\u{9D}', '\u{017E}', '\u{0178}',
+];
+
+/// The replacement and how much of `text`, which starts after the `&`, it
+/// replaces.
+pub(super) fn named(text: &str, in_attribute: bool) -> Option<(String, usize)> {
+    let candidate: String = text
+        .chars()
+        .take_while(|c| c.is_ascii_alphanumeric() || *c == ';')
+        .take(LONGEST_NAME)
+        .collect();
+
+    // the longest name that matches wins, `&notit;` is `¬it;`
+    let (name, replacement) = (1..=candidate.len())
+        .rev()
+        .map(|length| &candidate[..length])
+        .find_map(|name| {
+            let index = NAMED.binary_search_by_key(&name, |(name, _)| name).ok()?;
+            Some((name, NAMED[index].1))
+        })?;
+
+    if in_attribute && !name.ends_with(';') {
+        let next = text[name.len()..].chars().next();
+        if next.is_some_and(|c| c == '=' || c.is_ascii_alphanumeric()) {
+            return None;
+        }
+    }
+    Some((replacement.to_string(), name.len()))
+}
+
+/// Same as `named`, for what follows `&#`.
+pub(super) fn numeric(text: &str) -> Option<(String, usize)> {
+    let (code, end) = code_point(text)?;
+    let replacement = match code {
+        0x80..=0x9F => WINDOWS_1252[code as usize - 0x80],
+        0 => char::REPLACEMENT_CHARACTER,
+        code => char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER),
+    };
+
+    let semicolon = text[end..].starts_with(';') as usize;
+    Some((replacement.to_string(), 1 + end + semicolon))
+}
+
+/// The parse error for a numeric reference to a code point that has no
+/// business in a page, like `&#0;` or `&#x7F;`.
+pub(super) fn numeric_error(text: &str) -> Option<&'static str> {
+    let (code, _) = code_point(text)?;
+    match code {
+        0 => Some("null-character-reference"),
+        0x110000.. => Some("character-reference-outside-unicode-range"),
+        0xD800..=0xDFFF => Some("surrogate-character-reference"),
+        0xFDD0..=0xFDEF => Some("noncharacter-character-reference"),
+        code if code & 0xFFFE == 0xFFFE => Some("noncharacter-character-reference"),
+        0x0D | 0x01..=0x08 | 0x0B | 0x0E..=0x1F | 0x7F..=0x9F => {
+            Some("control-character-reference")
+        }
+        _ => None,
+    }
+}
+
+/// The code point of a numeric reference and where its digits end in `text`.
+fn code_point(text: &str) -> Option<(u32, usize)> {
+    let (digits, radix, prefix) = match text.strip_prefix(['x', 'X']) {
+        Some(hex) => (hex, 16, 1),
+        None => (text, 10, 0),
+    };
+    let length = digits
+        .find(|c: char| !c.is_digit(radix))
+        .unwrap_or(digits.len());
+    if length == 0 {
+        return None;
+    }
+
+    // anything too big is out of range however big it is
+    let code = u32::from_str_radix(&digits[..length], radix).unwrap_or(u32::MAX);
+    Some((code, prefix + length))
+}
+
+/// What the tokenizer makes of `html` as text, references decoded.
+#[cfg(test)]
+fn text(html: &str) -> String {
+    let mut tokenizer = super::tokenizer::Tokenizer::default();
+    tokenizer.feed(html);
+    tokenizer.end();
+
+    std::iter::from_fn(|| tokenizer.next_token())
+        .filter_map(|token| match token {
+            super::tokenizer::Token::Character(c) => Some(c),
+            _ => None,
+        })
+        .collect()
+}
+
+/// The same for `value` in an attribute.
+#[cfg(test)]
+fn attribute(value: &str) -> String {
+    let mut tokenizer = super::tokenizer::Tokenizer::default();
+    tokenizer.feed(&format!(r#"<a href="{value}">"#));
+    tokenizer.end();
+
+    match tokenizer.next_token() {
+        Some(super::tokenizer::Token::StartTag(tag)) => tag.attribute("href").unwrap().to_string(),
+        token => panic!("expected the start tag, got {token:?}"),
+    }
+}
+
+#[test]
+fn named_references() {
+    assert_eq!(text("&lt;p&gt; &amp; &nbsp;"), "<p> & \u{a0}");
+    assert_eq!(text("caf&eacute; &Eacute;"), "café É");
+    // legacy names work without the semicolon, others do not
+    assert_eq!(text("&copy 2024 &hellip"), "© 2024 &hellip");
+    assert_eq!(text("&notit; &notin; &notin"), "¬it; ∉ ¬in");
+    assert_eq!(text("&NotEqualTilde;"), "\u{2242}\u{338}");
+    assert_eq!(text("a & b &; &unknown;"), "a & b &; &unknown;");
+
+    // legacy names without `;` before `=` or a letter are left alone in attributes
+    assert_eq!(attribute("?a=1&copy=2&amp=3"), "?a=1&copy=2&amp=3");
+    assert_eq!(attribute("?a=1&amp;b=2&copy"), "?a=1&b=2©");
+    assert_eq!(attribute("&not &notin"), "¬ &notin");
+}
+
+#[test]
+fn numeric_references() {
+    assert_eq!(text("&#233;&#xE9;&#XE9"), "ééé");
+    assert_eq!(text("&#x1F600;"), "😀");
+    assert_eq!(
+        text("&#0; &#xD800; &#x110000; &#99999999999;"),
+        "\u{FFFD} \u{FFFD} \u{FFFD} \u{FFFD}"
+    );
+    assert_eq!(text("&#128;&#x9d;"), "€\u{9d}");
+    assert_eq!(text("&# &#x; &#xg"), "&# &#x; &#xg");
+    assert_eq!(attribute("&#233;&#x1F600"), "é😀");
+
+    assert_eq!(numeric_error("233;"), None);
+    assert_eq!(numeric_error("0;"), Some("null-character-reference"));
+    assert_eq!(
+        numeric_error("x110000"),
+        Some("character-reference-outside-unicode-range")
+    );
+    assert_eq!(
+        numeric_error("xDFFF;"),
+        Some("surrogate-character-reference")
+    );
+    assert_eq!(
+        numeric_error("x1FFFF;"),
+        Some("noncharacter-character-reference")
+    );
+    assert_eq!(numeric_error("13;"), Some("control-character-reference"));
+    assert_eq!(numeric_error("x80;"), Some("control-character-reference"));
+    assert_eq!(numeric_error("10;"), None);
+}
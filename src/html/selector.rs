@@ -0,0 +1,669 @@
+//! CSS selectors, https://drafts.csswg.org/selectors/, as `query_selector` and
+//! `matches` take them: type, class, id and attribute selectors, the four
+//! combinators and the common pseudo-classes. A selector is matched from its
+//! rightmost part, going up and back through the tree for the combinators.
+use std::error::Error;
+
+use super::dom::{Document, Namespace, NodeData, NodeId};
+
+/// Selectors separated by commas, matching what any of them matches.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SelectorList(Vec<Selector>);
+
+/// Compound selectors with the combinator to their left, which the first one
+/// does not have. The last one is what the selector matches.
+#[derive(Debug, Clone, PartialEq)]
+struct Selector(Vec<(Combinator, Compound)>);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Combinator {
+    /// `a b`
+    Descendant,
+    /// `a > b`
+    Child,
+    /// `a + b`
+    NextSibling,
+    /// `a ~ b`
+    SubsequentSibling,
+}
+
+/// Simple selectors without anything between them, `p.note[title]`.
+#[derive(Debug, Clone, Default, PartialEq)]
+struct Compound {
+    /// `None` for `*` or no type selector at all.
+    name: Option<String>,
+    simple: Vec<Simple>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Simple {
+    Id(String),
+    Class(String),
+    Attribute {
+        name: String,
+        /// The operator and value, `None` for `[name]`.
+        value: Option<(Operator, String)>,
+        /// `[type=a i]`
+        ignore_case: bool,
+    },
+    Pseudo(Pseudo),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Operator {
+    /// `=`
+    Equals,
+    /// `~=`, one of the whitespace separated words.
+    Includes,
+    /// `|=`, the value or the value followed by `-`.
+    DashMatch,
+    /// `^=`
+    Prefix,
+    /// `$=`
+    Suffix,
+    /// `*=`
+    Substring,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Pseudo {
+    Root,
+    Empty,
+    /// `:nth-child(an+b)` and the like with `a` and `b`. `:first-child` is
+    /// `(0, 1)`, the `last` ones count from the end.
+    Nth {
+        a: i32,
+        b: i32,
+        of_type: bool,
+        last: bool,
+    },
+    /// `:only-child` and `:only-of-type`.
+    Only {
+        of_type: bool,
+    },
+    Not(SelectorList),
+    /// `:is()` and `:where()`, which are the same without specificity.
+    Is(SelectorList),
+    Link,
+    Checked,
+    Disabled,
+    Enabled,
+}
+
+/// Form elements that can be disabled.
+const FORM_ELEMENTS: [&str; 7] = [
+    "button", "fieldset", "input", "optgroup", "option", "select", "textarea",
+];
+
+impl SelectorList {
+    pub fn parse(source: &str) -> Result<SelectorList, Box<dyn Error>> {
+        let mut parser = Parser {
+            source,
+            chars: source.chars().collect(),
+            position: 0,
+        };
+        let list = parser.list()?;
+        match parser.peek() {
+            None => Ok(list),
+            Some(c) => Err(parser.error(&format!("unexpected {c:?}"))),
+        }
+    }
+
+    /// Whether the element `id` in `document` matches.
+    pub fn matches(&self, document: &Document, id: NodeId) -> bool {
+        document.element(id).is_some()
+            && self
+                .0
+                .iter()
+                .any(|selector| matches_from(document, &selector.0, id))
+    }
+}
+
+/// Whether `id` matches the last of `parts` and the ones before it match
+/// where their combinators say.
+fn matches_from(document: &Document, parts: &[(Combinator, Compound)], id: NodeId) -> bool {
+    let Some(((combinator, compound), rest)) = parts.split_last() else {
+        return true;
+    };
+    if !compound.matches(document, id) {
+        return false;
+    }
+    if rest.is_empty() {
+        return true;
+    }
+
+    match combinator {
+        Combinator::Child => {
+            parent_element(document, id).is_some_and(|parent| matches_from(document, rest, parent))
+        }
+        Combinator::Descendant => {
+            let mut ancestor = parent_element(document, id);
+            while let Some(id) = ancestor {
+                if matches_from(document, rest, id) {
+                    return true;
+                }
+                ancestor = parent_element(document, id);
+            }
+            false
+        }
+        Combinator::NextSibling => previous_siblings(document, id)
+            .first()
+            .is_some_and(|sibling| matches_from(document, rest, *sibling)),
+        Combinator::SubsequentSibling => previous_siblings(document, id)
+            .iter()
+            .any(|sibling| matches_from(document, rest, *sibling)),
+    }
+}
+
+fn parent_element(document: &Document, id: NodeId) -> Option<NodeId> {
+    document
+        .parent(id)
+        .filter(|parent| document.element(*parent).is_some())
+}
+
+/// The element siblings of `id` and its position among them.
+fn element_siblings(document: &Document, id: NodeId) -> (Vec<NodeId>, usize) {
+    let siblings: Vec<NodeId> = match document.parent(id) {
+        Some(parent) => document
+            .children(parent)
+            .iter()
+            .copied()
+            .filter(|sibling| document.element(*sibling).is_some())
+            .collect(),
+        None => vec![id],
+    };
+    let position = siblings
+        .iter()
+        .position(|sibling| *sibling == id)
+        .unwrap_or(0);
+    (siblings, position)
+}
+
+/// The element siblings before `id`, closest first.
+fn previous_siblings(document: &Document, id: NodeId) -> Vec<NodeId> {
+    let (mut siblings, position) = element_siblings(document, id);
+    siblings.truncate(position);
+    siblings.reverse();
+    siblings
+}
+
+impl Compound {
+    fn matches(&self, document: &Document, id: NodeId) -> bool {
+        let Some(element) = document.element(id) else {
+            return false;
+        };
+        if let Some(name) = &self.name {
+            let same = match element.namespace {
+                Namespace::Html => element.name.eq_ignore_ascii_case(name),
+                _ => element.name == *name,
+            };
+            if !same {
+                return false;
+            }
+        }
+
+        self.simple.iter().all(|simple| match simple {
+            Simple::Id(id) => element.attribute("id") == Some(id),
+            Simple::Class(class) => element
+                .attribute("class")
+                .is_some_and(|classes| classes.split_ascii_whitespace().any(|c| c == class)),
+            Simple::Attribute {
+                name,
+                value,
+                ignore_case,
+            } => {
+                let attribute = match element.namespace {
+                    Namespace::Html => element.attribute(&name.to_ascii_lowercase()),
+                    _ => element.attribute(name),
+                };
+                match (attribute, value) {
+                    (None, _) => false,
+                    (Some(_), None) => true,
+                    (Some(actual), Some((operator, expected))) => {
+                        let (actual, expected) = match ignore_case {
+                            true => (actual.to_lowercase(), expected.to_lowercase()),
+                            false => (actual.to_string(), expected.clone()),
+                        };
+                        match operator {
+                            Operator::Equals => actual == expected,
+                            Operator::Includes => {
+                                actual.split_ascii_whitespace().any(|word| word == expected)
+                            }
+                            Operator::DashMatch => {
+                                actual == expected
+                                    || actual
+                                        .strip_prefix(&expected)
+                                        .is_some_and(|rest| rest.starts_with('-'))
+                            }
+                            // an empty value matches nothing with these
+                            Operator::Prefix => {
+                                !expected.is_empty() && actual.starts_with(&expected)
+                            }
+                            Operator::Suffix => !expected.is_empty() && actual.ends_with(&expected),
+                            Operator::Substring => {
+                                !expected.is_empty() && actual.contains(&expected)
+                            }
+                        }
+                    }
+                }
+            }
+            Simple::Pseudo(pseudo) => pseudo.matches(document, id),
+        })
+    }
+}
+
+impl Pseudo {
+    fn matches(&self, document: &Document, id: NodeId) -> bool {
+        let element = document.element(id).unwrap();
+        let html = element.namespace == Namespace::Html;
+        let of_type_siblings = |of_type: bool| {
+            let (siblings, _) = element_siblings(document, id);
+            let siblings: Vec<NodeId> = siblings
+                .into_iter()
+                .filter(|sibling| {
+                    !of_type
+                        || document.element(*sibling).is_some_and(|e| {
+                            e.name == element.name && e.namespace == element.namespace
+                        })
+                })
+                .collect();
+            let position = siblings
+                .iter()
+                .position(|sibling| *sibling == id)
+                .unwrap_or(0);
+            (siblings, position)
+        };
+
+        match self {
+            Pseudo::Root => document.parent(id) == Some(document.root()),
+            Pseudo::Empty => {
+                document
+                    .children(id)
+                    .iter()
+                    .all(|child| match document.data(*child) {
+                        NodeData::Text(text) => text.is_empty(),
+                        NodeData::Comment(_) => true,
+                        _ => false,
+                    })
+            }
+            Pseudo::Nth {
+                a,
+                b,
+                of_type,
+                last,
+            } => {
+                let (siblings, position) = of_type_siblings(*of_type);
+                // counted from 1
+                let index = match last {
+                    true => siblings.len() - position,
+                    false => position + 1,
+                } as i32;
+                match a {
+                    0 => index == *b,
+                    a => (index - b) % a == 0 && (index - b) / a >= 0,
+                }
+            }
+            Pseudo::Only { of_type } => of_type_siblings(*of_type).0.len() == 1,
+            Pseudo::Not(list) => !list.matches(document, id),
+            Pseudo::Is(list) => list.matches(document, id),
+            Pseudo::Link => {
+                html && matches!(element.name.as_str(), "a" | "area")
+                    && element.attribute("href").is_some()
+            }
+            Pseudo::Checked => {
+                html && match element.name.as_str() {
+                    "input" => {
+                        element.attribute("checked").is_some()
+                            && element.attribute("type").is_some_and(|kind| {
+                                kind.eq_ignore_ascii_case("checkbox")
+                                    || kind.eq_ignore_ascii_case("radio")
+                            })
+                    }
+                    "option" => element.attribute("selected").is_some(),
+                    _ => false,
+                }
+            }
+            Pseudo::Disabled => {
+                html && FORM_ELEMENTS.contains(&element.name.as_str())
+                    && element.attribute("disabled").is_some()
+            }
+            Pseudo::Enabled => {
+                html && FORM_ELEMENTS.contains(&element.name.as_str())
+                    && element.attribute("disabled").is_none()
+            }
+        }
+    }
+}
+
+struct Parser<'a> {
+    source: &'a str,
+    chars: Vec<char>,
+    position: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self, message: &str) -> Box<dyn Error> {
+        format!("invalid selector {:?}: {message}", self.source).into()
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.position).copied()
+    }
+
+    fn next_if(&mut self, expected: char) -> bool {
+        let found = self.peek() == Some(expected);
+        if found {
+            self.position += 1;
+        }
+        found
+    }
+
+    /// Whether there was any whitespace.
+    fn skip_whitespace(&mut self) -> bool {
+        let start = self.position;
+        while self.peek().is_some_and(|c| c.is_ascii_whitespace()) {
+            self.position += 1;
+        }
+        self.position > start
+    }
+
+    fn list(&mut self) -> Result<SelectorList, Box<dyn Error>> {
+        let mut selectors = vec![self.selector()?];
+        while self.next_if(',') {
+            selectors.push(self.selector()?);
+        }
+        Ok(SelectorList(selectors))
+    }
+
+    fn selector(&mut self) -> Result<Selector, Box<dyn Error>> {
+        self.skip_whitespace();
+        let mut parts = vec![(Combinator::Descendant, self.compound()?)];
+        loop {
+            let whitespace = self.skip_whitespace();
+            let combinator = match self.peek() {
+                Some('>') => Combinator::Child,
+                Some('+') => Combinator::NextSibling,
+                Some('~') => Combinator::SubsequentSibling,
+                Some(',' | ')') | None => break,
+                Some(_) if whitespace => Combinator::Descendant,
+                Some(c) => return Err(self.error(&format!("unexpected {c:?}"))),
+            };
+            if combinator != Combinator::Descendant {
+                self.position += 1;
+                self.skip_whitespace();
+            }
+            parts.push((combinator, self.compound()?));
+        }
+        Ok(Selector(parts))
+    }
+
+    fn compound(&mut self) -> Result<Compound, Box<dyn Error>> {
+        let mut compound = Compound::default();
+        let universal = self.next_if('*');
+        if !universal && self.peek().is_some_and(is_name_start) {
+            compound.name = Some(self.identifier()?);
+        }
+
+        loop {
+            let simple = match self.peek() {
+                Some('#') => {
+                    self.position += 1;
+                    Simple::Id(self.identifier()?)
+                }
+                Some('.') => {
+                    self.position += 1;
+                    Simple::Class(self.identifier()?)
+                }
+                Some('[') => {
+                    self.position += 1;
+                    self.attribute()?
+                }
+                Some(':') => {
+                    self.position += 1;
+                    Simple::Pseudo(self.pseudo()?)
+                }
+                _ => break,
+            };
+            compound.simple.push(simple);
+        }
+
+        if !universal && compound.name.is_none() && compound.simple.is_empty() {
+            return Err(self.error("expected a selector"));
+        }
+        Ok(compound)
+    }
+
+    /// What follows `[`, up to and including `]`.
+    fn attribute(&mut self) -> Result<Simple, Box<dyn Error>> {
+        self.skip_whitespace();
+        let name = self.identifier()?;
+        self.skip_whitespace();
+
+        let operator = match self.peek() {
+            Some('=') => Some(Operator::Equals),
+            Some('~') => Some(Operator::Includes),
+            Some('|') => Some(Operator::DashMatch),
+            Some('^') => Some(Operator::Prefix),
+            Some('$') => Some(Operator::Suffix),
+            Some('*') => Some(Operator::Substring),
+            _ => None,
+        };
+        let mut value = None;
+        let mut ignore_case = false;
+        if let Some(operator) = operator {
+            self.position += 1;
+            if operator != Operator::Equals && !self.next_if('=') {
+                return Err(self.error("expected `=`"));
+            }
+            self.skip_whitespace();
+            let text = match self.peek() {
+                Some(quote @ ('"' | '\'')) => {
+                    self.position += 1;
+                    let mut text = String::new();
+                    loop {
+                        match self.peek() {
+                            None => return Err(self.error("unterminated string")),
+                            Some(c) if c == quote => break,
+                            Some('\\') => {
+                                self.position += 1;
+                                text.extend(self.peek());
+                            }
+                            Some(c) => text.push(c),
+                        }
+                        self.position += 1;
+                    }
+                    self.position += 1;
+                    text
+                }
+                _ => self.identifier()?,
+            };
+            value = Some((operator, text));
+
+            self.skip_whitespace();
+            if self
+                .peek()
+                .is_some_and(|c| matches!(c, 'i' | 'I' | 's' | 'S'))
+            {
+                ignore_case = matches!(self.peek(), Some('i' | 'I'));
+                self.position += 1;
+            }
+        }
+
+        self.skip_whitespace();
+        if !self.next_if(']') {
+            return Err(self.error("expected `]`"));
+        }
+        Ok(Simple::Attribute {
+            name,
+            value,
+            ignore_case,
+        })
+    }
+
+    /// What follows `:`.
+    fn pseudo(&mut self) -> Result<Pseudo, Box<dyn Error>> {
+        if self.peek() == Some(':') {
+            return Err(self.error("pseudo-elements match no elements"));
+        }
+        let name = self.identifier()?.to_ascii_lowercase();
+        let nth = |a, b, of_type, last| Pseudo::Nth {
+            a,
+            b,
+            of_type,
+            last,
+        };
+
+        if !self.next_if('(') {
+            return Ok(match name.as_str() {
+                "root" => Pseudo::Root,
+                "empty" => Pseudo::Empty,
+                "first-child" => nth(0, 1, false, false),
+                "last-child" => nth(0, 1, false, true),
+                "first-of-type" => nth(0, 1, true, false),
+                "last-of-type" => nth(0, 1, true, true),
+                "only-child" => Pseudo::Only { of_type: false },
+                "only-of-type" => Pseudo::Only { of_type: true },
+                "link" | "any-link" => Pseudo::Link,
+                "checked" => Pseudo::Checked,
+                "disabled" => Pseudo::Disabled,
+                "enabled" => Pseudo::Enabled,
+                name => return Err(self.error(&format!("unknown pseudo-class :{name}"))),
+            });
+        }
+
+        let pseudo = match name.as_str() {
+            "not" => Pseudo::Not(self.list()?),
+            "is" | "where" => Pseudo::Is(self.list()?),
+            "nth-child" | "nth-last-child" | "nth-of-type" | "nth-last-of-type" => {
+                let end = self.chars[self.position..]
+                    .iter()
+                    .position(|c| *c == ')')
+                    .ok_or_else(|| self.error("expected `)`"))?;
+                let argument: String = self.chars[self.position..self.position + end]
+                    .iter()
+                    .collect();
+                self.position += end;
+                let (a, b) = parse_nth(&argument)
+                    .ok_or_else(|| self.error(&format!("invalid :{name}({argument})")))?;
+                nth(a, b, name.ends_with("of-type"), name.contains("last"))
+            }
+            name => return Err(self.error(&format!("unknown pseudo-class :{name}()"))),
+        };
+        self.skip_whitespace();
+        if !self.next_if(')') {
+            return Err(self.error("expected `)`"));
+        }
+        Ok(pseudo)
+    }
+
+    fn identifier(&mut self) -> Result<String, Box<dyn Error>> {
+        let mut identifier = String::new();
+        while let Some(c) = self.peek() {
+            match c {
+                '\\' => {
+                    self.position += 1;
+                    match self.peek() {
+                        Some(c) => identifier.push(c),
+                        None => return Err(self.error("escape at the end")),
+                    }
+                }
+                c if is_name_start(c) || c.is_ascii_digit() || c == '-' => identifier.push(c),
+                _ => break,
+            }
+            self.position += 1;
+        }
+        if identifier.is_empty() || identifier.starts_with(|c: char| c.is_ascii_digit()) {
+            return Err(self.error("expected a name"));
+        }
+        Ok(identifier)
+    }
+}
+
+fn is_name_start(c: char) -> bool {
+    c.is_ascii_alphabetic() || c == '_' || c == '-' || c == '\\' || !c.is_ascii()
+}
+
+/// The `a` and `b` of `an+b`, or of `odd`, `even` and a plain number.
+fn parse_nth(argument: &str) -> Option<(i32, i32)> {
+    let argument: String = argument
+        .chars()
+        .filter(|c| !c.is_ascii_whitespace())
+        .collect::<String>()
+        .to_ascii_lowercase();
+    match argument.as_str() {
+        "odd" => return Some((2, 1)),
+        "even" => return Some((2, 0)),
+        _ => {}
+    }
+
+    let Some((a, b)) = argument.split_once('n') else {
+        return Some((0, argument.parse().ok()?));
+    };
+    let a = match a {
+        "" | "+" => 1,
+        "-" => -1,
+        a => a.parse().ok()?,
+    };
+    let b = match b {
+        "" => 0,
+        b if b.starts_with(['+', '-']) => b.parse().ok()?,
+        _ => return None,
+    };
+    Some((a, b))
+}
+
+#[test]
+fn parse_selectors() {
+    assert!(SelectorList::parse("div > p.note + ul ~ *[lang|=en i], #main a:not(.x)").is_ok());
+    assert!(SelectorList::parse("li:nth-child( 2n + 1 ):nth-last-of-type(-n+3)").is_ok());
+    for invalid in [
+        "",
+        "p,",
+        "p >",
+        "[a",
+        "a[b^c]",
+        ":hover",
+        "p::before",
+        ".1x",
+        "a:nth-child(x)",
+    ] {
+        assert!(SelectorList::parse(invalid).is_err(), "{invalid}");
+    }
+    assert_eq!(parse_nth("odd"), Some((2, 1)));
+    assert_eq!(parse_nth("-n+3"), Some((-1, 3)));
+    assert_eq!(parse_nth("5"), Some((0, 5)));
+}
+
+#[test]
+fn query_documents() {
+    let document = super::parse(
+        "<div id=main><p class='a b'>1</p><p lang=en-US>2<a href=x>x</a></p><ul><li>a<li>b<li>c<li>d</ul></div>\
+         <form><input type=checkbox checked><input disabled></form><p></p>",
+    );
+    let names = |selectors: &str| -> Vec<String> {
+        document
+            .query_selector_all(selectors)
+            .unwrap()
+            .into_iter()
+            .map(|id| document.text_content(id) + document.name(id).unwrap())
+            .collect()
+    };
+
+    assert_eq!(names("#main > p.b"), ["1p"]);
+    assert_eq!(names("div p + P[LANG|=en]"), ["2xp"]);
+    assert_eq!(names("p ~ ul li:nth-child(odd)"), ["ali", "cli"]);
+    assert_eq!(names("li:nth-last-child(-n+2):not(:last-child)"), ["cli"]);
+    assert_eq!(names("p:empty, a:link"), ["xa", "p"]);
+    assert_eq!(names(":checked, :disabled"), ["input", "input"]);
+    assert_eq!(names("[class~=a]:first-of-type:is(div *)"), ["1p"]);
+    assert_eq!(names(":root > body > :last-child"), ["p"]);
+
+    let li = document.get_elements_by_tag_name("LI");
+    assert_eq!(li.len(), 4);
+    assert!(document.matches(li[3], "ul > :last-child").unwrap());
+    assert_eq!(
+        document.get_element_by_id("main"),
+        document.query_selector("div").unwrap()
+    );
+    assert!(document.query_selector("p >").is_err());
+}
@@ -0,0 +1,209 @@
+//! Turns the document back into HTML, following
+//! https://html.spec.whatwg.org/multipage/parsing.html#serialising-html-fragments.
+//! Parsing what `to_html` gives builds the same tree again, except for trees
+//! the parser had to fix up, like text moved out of a table.
+use super::dom::{Document, Namespace, NodeData, NodeId};
+
+/// Elements that never have an end tag.
+const VOID_ELEMENTS: [&str; 18] = [
+    "area", "base", "basefont", "bgsound", "br", "col", "embed", "frame", "hr", "img", "input",
+    "keygen", "link", "meta", "param", "source", "track", "wbr",
+];
+
+/// Elements whose text is written as it is, since the tokenizer does not
+/// decode references in them.
+const RAW_TEXT_ELEMENTS: [&str; 8] = [
+    "iframe",
+    "noembed",
+    "noframes",
+    "noscript",
+    "plaintext",
+    "script",
+    "style",
+    "xmp",
+];
+
+/// Elements where whitespace matters, pretty printing leaves what is in them
+/// alone.
+const PREFORMATTED_ELEMENTS: [&str; 3] = ["listing", "pre", "textarea"];
+
+impl Document {
+    /// The whole document as HTML.
+    pub fn to_html(&self) -> String {
+        self.inner_html(self.root())
+    }
+
+    /// The document as HTML with an element or comment per line, indented by
+    /// depth, and whitespace around text dropped. Easier to read, but parsing
+    /// it gives text nodes with different whitespace.
+    pub fn to_pretty_html(&self) -> String {
+        let mut out = String::new();
+        for &child in self.children(self.root()) {
+            self.write_pretty(child, 0, &mut out);
+        }
+        out
+    }
+
+    /// `id` with its tags, attributes and everything in it.
+    pub fn outer_html(&self, id: NodeId) -> String {
+        let mut out = String::new();
+        self.write_node(id, &mut out);
+        out
+    }
+
+    /// What is in `id`, without its own tags.
+    pub fn inner_html(&self, id: NodeId) -> String {
+        let mut out = String::new();
+        for &child in self.children(id) {
+            self.write_node(child, &mut out);
+        }
+        out
+    }
+
+    fn write_node(&self, id: NodeId, out: &mut String) {
+        match self.data(id) {
+            NodeData::Document => out.push_str(&self.inner_html(id)),
+            NodeData::Doctype { name, .. } => {
+                out.push_str(&format!("<!DOCTYPE {name}>"));
+            }
+            NodeData::Comment(comment) => out.push_str(&format!("<!--{comment}-->")),
+            NodeData::Text(text) => match self.raw_text(id) {
+                true => out.push_str(text),
+                false => out.push_str(&escape(text, false)),
+            },
+            NodeData::Element(_) => {
+                out.push_str(&self.start_tag(id));
+                if !self.is_void(id) {
+                    out.push_str(&self.inner_html(id));
+                    out.push_str(&format!("</{}>", self.name(id).unwrap()));
+                }
+            }
+        }
+    }
+
+    fn write_pretty(&self, id: NodeId, depth: usize, out: &mut String) {
+        let indent = "  ".repeat(depth);
+        match self.data(id) {
+            NodeData::Text(text) if self.raw_text(id) => {
+                out.push_str(&format!("{indent}{}\n", text.trim()));
+            }
+            NodeData::Text(text) => {
+                let text = text.split_ascii_whitespace().collect::<Vec<_>>().join(" ");
+                if !text.is_empty() {
+                    out.push_str(&format!("{indent}{}\n", escape(&text, false)));
+                }
+            }
+            NodeData::Element(element)
+                if element.namespace == Namespace::Html
+                    && PREFORMATTED_ELEMENTS.contains(&element.name.as_str()) =>
+            {
+                out.push_str(&format!("{indent}{}\n", self.outer_html(id)));
+            }
+            NodeData::Element(element) => {
+                out.push_str(&format!("{indent}{}\n", self.start_tag(id)));
+                if !self.is_void(id) {
+                    for &child in self.children(id) {
+                        self.write_pretty(child, depth + 1, out);
+                    }
+                    out.push_str(&format!("{indent}</{}>\n", element.name));
+                }
+            }
+            NodeData::Document | NodeData::Doctype { .. } | NodeData::Comment(_) => {
+                out.push_str(&format!("{indent}{}\n", self.outer_html(id)));
+            }
+        }
+    }
+
+    fn start_tag(&self, id: NodeId) -> String {
+        let element = self.element(id).unwrap();
+        let mut tag = format!("<{}", element.name);
+        for (name, value) in &element.attributes {
+            tag.push_str(&format!(" {name}=\"{}\"", escape(value, true)));
+        }
+        tag.push('>');
+        tag
+    }
+
+    fn is_void(&self, id: NodeId) -> bool {
+        self.element(id).is_some_and(|element| {
+            element.namespace == Namespace::Html && VOID_ELEMENTS.contains(&element.name.as_str())
+        })
+    }
+
+    /// Whether the text node `id` is in an element that takes it as it is.
+    fn raw_text(&self, id: NodeId) -> bool {
+        self.parent(id)
+            .and_then(|parent| self.element(parent))
+            .is_some_and(|parent| {
+                parent.namespace == Namespace::Html
+                    && RAW_TEXT_ELEMENTS.contains(&parent.name.as_str())
+            })
+    }
+}
+
+/// Escapes `&`, no-break spaces, `<` and `>`, and `"` in attribute values,
+/// for putting text into markup.
+pub fn escape(text: &str, attribute: bool) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '\u{A0}' => escaped.push_str("&nbsp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' if attribute => escaped.push_str("&quot;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[test]
+fn serialize_documents() {
+    let html = "<!DOCTYPE html><html><head><title>a &amp; b</title>\
+                <script>if (a < b) {}</script></head>\
+                <body><!-- c --><p class=\"x &quot;y&quot;\">1&nbsp;&lt;2<br>\
+                <img src=\"a.png\"></p><svg><circle r=\"1\"></circle></svg></body></html>";
+    let document = super::parse(html);
+    assert_eq!(document.to_html(), html);
+    assert_eq!(super::parse(&document.to_html()).dump(), document.dump());
+
+    let p = document.query_selector("p").unwrap().unwrap();
+    assert_eq!(
+        document.inner_html(p),
+        "1&nbsp;&lt;2<br><img src=\"a.png\">"
+    );
+    assert_eq!(
+        document.outer_html(p),
+        "<p class=\"x &quot;y&quot;\">1&nbsp;&lt;2<br><img src=\"a.png\"></p>"
+    );
+
+    // implied tags are written out
+    assert_eq!(
+        super::parse("<ul><li>a<li>b</ul>").to_html(),
+        "<html><head></head><body><ul><li>a</li><li>b</li></ul></body></html>"
+    );
+}
+
+#[test]
+fn pretty_print() {
+    let document = super::parse("<!DOCTYPE html><p>a  <b>b</b>\n c</p><pre>\n  x\n y</pre>");
+    assert_eq!(
+        document.to_pretty_html(),
+        "<!DOCTYPE html>\n\
+         <html>\n\
+         \x20 <head>\n\
+         \x20 </head>\n\
+         \x20 <body>\n\
+         \x20   <p>\n\
+         \x20     a\n\
+         \x20     <b>\n\
+         \x20       b\n\
+         \x20     </b>\n\
+         \x20     c\n\
+         \x20   </p>\n\
+         \x20   <pre>  x\n y</pre>\n\
+         \x20 </body>\n\
+         </html>\n"
+    );
+}
@@ -0,0 +1,1241 @@
+//! The tokenizer state machine of
+//! https://html.spec.whatwg.org/multipage/parsing.html#tokenization. Input is
+//! fed in as it arrives and tokens come out as soon as they are complete, a
+//! state that needs to look further ahead than what has arrived waits for more.
+//! Which of the text states it is in is up to the tree builder, it knows that
+//! `<title>` is followed by RCDATA and `<script>` by script data.
+use std::collections::VecDeque;
+
+use super::{ParseError, entities};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+    Doctype(Doctype),
+    StartTag(Tag),
+    EndTag(Tag),
+    Comment(String),
+    Character(char),
+    Eof,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Tag {
+    /// Lowercase.
+    pub name: String,
+    /// In source order, only the first of repeated names is kept.
+    pub attributes: Vec<(String, String)>,
+    /// Written as `<br/>`.
+    pub self_closing: bool,
+}
+
+impl Tag {
+    pub fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|(attribute, _)| attribute == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Missing parts are `None`, which is not the same as empty: `<!DOCTYPE html
+/// PUBLIC "">` has an empty public identifier.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Doctype {
+    pub name: Option<String>,
+    pub public_id: Option<String>,
+    pub system_id: Option<String>,
+    pub force_quirks: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum State {
+    Data,
+    Rcdata,
+    Rawtext,
+    ScriptData,
+    Plaintext,
+    TagOpen,
+    EndTagOpen,
+    TagName,
+    /// `<`, `</` and `</name` in the text states, which may end the text.
+    TextLessThanSign(Text),
+    TextEndTagOpen(Text),
+    TextEndTagName(Text),
+    ScriptDataEscapeStart,
+    ScriptDataEscapeStartDash,
+    ScriptDataEscaped,
+    ScriptDataEscapedDash,
+    ScriptDataEscapedDashDash,
+    ScriptDataDoubleEscapeStart,
+    ScriptDataDoubleEscaped,
+    ScriptDataDoubleEscapedDash,
+    ScriptDataDoubleEscapedDashDash,
+    ScriptDataDoubleEscapedLessThanSign,
+    ScriptDataDoubleEscapeEnd,
+    BeforeAttributeName,
+    AttributeName,
+    AfterAttributeName,
+    BeforeAttributeValue,
+    /// The quote, `None` for an unquoted value.
+    AttributeValue(Option<char>),
+    AfterAttributeValueQuoted,
+    SelfClosingStartTag,
+    BogusComment,
+    MarkupDeclarationOpen,
+    CommentStart,
+    CommentStartDash,
+    Comment,
+    CommentLessThanSign,
+    CommentLessThanSignBang,
+    CommentLessThanSignBangDash,
+    CommentLessThanSignBangDashDash,
+    CommentEndDash,
+    CommentEnd,
+    CommentEndBang,
+    Doctype,
+    BeforeDoctypeName,
+    DoctypeName,
+    AfterDoctypeName,
+    AfterDoctypeKeyword(Identifier),
+    BeforeDoctypeIdentifier(Identifier),
+    /// With the quote the identifier is in.
+    DoctypeIdentifier(Identifier, char),
+    AfterDoctypeIdentifier(Identifier),
+    BetweenDoctypePublicAndSystemIdentifiers,
+    BogusDoctype,
+    CdataSection,
+    CdataSectionBracket,
+    CdataSectionEnd,
+    /// After `&`, going back to the state it came from.
+    CharacterReference(Return),
+}
+
+/// The text states that only end at an end tag.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Text {
+    Rcdata,
+    Rawtext,
+    ScriptData,
+    ScriptDataEscaped,
+}
+
+/// The states with character references.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Return {
+    Data,
+    Rcdata,
+    AttributeValue(Option<char>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Identifier {
+    Public,
+    System,
+}
+
+const REPLACEMENT: char = '\u{FFFD}';
+
+fn is_whitespace(c: char) -> bool {
+    matches!(c, '\t' | '\n' | '\x0C' | ' ')
+}
+
+pub struct Tokenizer {
+    pub state: State,
+    /// Whether `<![CDATA[` starts a CDATA section, which it only does in SVG
+    /// and MathML. The tree builder keeps this up to date.
+    pub cdata_allowed: bool,
+    /// Name of the last start tag, the only end tag that ends RCDATA and the
+    /// like. The tree builder sets it for fragments.
+    pub last_start_tag: String,
+    input: Vec<char>,
+    position: usize,
+    /// No more input is coming, running out of it is the end of file.
+    end: bool,
+    /// A `\r` ended the last chunk, a `\n` starting the next belongs to it.
+    carriage_return: bool,
+    tokens: VecDeque<Token>,
+    finished: bool,
+
+    tag: Tag,
+    end_tag: bool,
+    attribute: Option<(String, String)>,
+    comment: String,
+    doctype: Doctype,
+    /// What the current text state has seen of a possible end tag.
+    buffer: String,
+
+    errors: Vec<ParseError>,
+    /// Where the input dropped from the start of `input` ended.
+    drained: Location,
+    /// A position in `input` and where it is, to count lines from.
+    located: (usize, Location),
+}
+
+/// A line and column in the input, from 1.
+#[derive(Debug, Clone, Copy)]
+struct Location {
+    line: usize,
+    /// 0 before the first character.
+    column: usize,
+    /// The last character was a newline, the next one is on the next line.
+    after_newline: bool,
+}
+
+impl Default for Location {
+    fn default() -> Location {
+        Location {
+            line: 1,
+            column: 0,
+            after_newline: false,
+        }
+    }
+}
+
+impl Location {
+    fn advance(mut self, chars: &[char]) -> Location {
+        for c in chars {
+            if self.after_newline {
+                self.line += 1;
+                self.column = 0;
+            }
+            self.column += 1;
+            self.after_newline = *c == '\n';
+        }
+        self
+    }
+}
+
+impl Default for Tokenizer {
+    fn default() -> Tokenizer {
+        Tokenizer {
+            state: State::Data,
+            cdata_allowed: false,
+            last_start_tag: String::new(),
+            input: Vec::new(),
+            position: 0,
+            end: false,
+            carriage_return: false,
+            tokens: VecDeque::new(),
+            finished: false,
+            tag: Tag::default(),
+            end_tag: false,
+            attribute: None,
+            comment: String::new(),
+            doctype: Doctype::default(),
+            buffer: String::new(),
+            errors: Vec::new(),
+            drained: Location::default(),
+            located: (0, Location::default()),
+        }
+    }
+}
+
+impl Tokenizer {
+    /// More input, with newlines normalized to `\n`.
+    pub fn feed(&mut self, chunk: &str) {
+        // what has been consumed is not needed any more
+        if self.position > 4096 {
+            self.drained = self.drained.advance(&self.input[..self.position]);
+            self.located = (0, self.drained);
+            self.input.drain(..self.position);
+            self.position = 0;
+        }
+
+        for c in chunk.chars() {
+            match c {
+                '\n' if self.carriage_return => {}
+                '\r' => self.input.push('\n'),
+                c => self.input.push(c),
+            }
+            self.carriage_return = c == '\r';
+        }
+    }
+
+    /// There is no more input.
+    pub fn end(&mut self) {
+        self.end = true;
+    }
+
+    /// The next token, or `None` until more input is fed. After `Token::Eof`
+    /// there are no more tokens.
+    pub fn next_token(&mut self) -> Option<Token> {
+        loop {
+            if let Some(token) = self.tokens.pop_front() {
+                return Some(token);
+            }
+            if self.finished || !self.step() {
+                return None;
+            }
+        }
+    }
+
+    /// The parse errors since the last call.
+    pub fn take_errors(&mut self) -> Vec<ParseError> {
+        std::mem::take(&mut self.errors)
+    }
+
+    /// The line and column of the character last consumed, or just after the
+    /// last one at the end of the input.
+    pub fn location(&mut self) -> (usize, usize) {
+        // counted on from the last time, the position only goes back a little
+        let (from, location) = match self.located {
+            (from, location) if from <= self.position => (from, location),
+            _ => (0, self.drained),
+        };
+        let mut location = location.advance(&self.input[from..self.position]);
+        self.located = (self.position, location);
+
+        if self.end && self.position == self.input.len() {
+            location = location.advance(&[' ']);
+        }
+        (location.line, location.column.max(1))
+    }
+
+    fn error(&mut self, code: &'static str) {
+        let (line, column) = self.location();
+        self.errors.push(ParseError { code, line, column });
+    }
+
+    fn emit(&mut self, token: Token) {
+        if token == Token::Eof {
+            self.finished = true;
+        }
+        self.tokens.push_back(token);
+    }
+
+    fn emit_str(&mut self, text: &str) {
+        for c in text.chars() {
+            self.emit(Token::Character(c));
+        }
+    }
+
+    fn emit_tag(&mut self) {
+        self.finish_attribute();
+        let tag = std::mem::take(&mut self.tag);
+        if self.end_tag {
+            if !tag.attributes.is_empty() {
+                self.error("end-tag-with-attributes");
+            }
+            if tag.self_closing {
+                self.error("end-tag-with-trailing-solidus");
+            }
+            self.emit(Token::EndTag(tag));
+        } else {
+            self.last_start_tag = tag.name.clone();
+            self.emit(Token::StartTag(tag));
+        }
+    }
+
+    fn emit_comment(&mut self) {
+        let comment = std::mem::take(&mut self.comment);
+        self.emit(Token::Comment(comment));
+    }
+
+    fn emit_doctype(&mut self) {
+        let doctype = std::mem::take(&mut self.doctype);
+        self.emit(Token::Doctype(doctype));
+    }
+
+    fn new_tag(&mut self, end_tag: bool) {
+        self.tag = Tag::default();
+        self.end_tag = end_tag;
+        self.attribute = None;
+    }
+
+    fn new_attribute(&mut self, name: &str) {
+        self.finish_attribute();
+        self.attribute = Some((name.to_string(), String::new()));
+    }
+
+    /// Repeated attributes are dropped once their name is known.
+    fn finish_attribute(&mut self) {
+        if let Some((name, value)) = self.attribute.take() {
+            if self.tag.attribute(&name).is_some() {
+                self.error("duplicate-attribute");
+            } else {
+                self.tag.attributes.push((name, value));
+            }
+        }
+    }
+
+    fn attribute_name(&mut self) -> &mut String {
+        &mut self.attribute.get_or_insert_default().0
+    }
+
+    fn attribute_value(&mut self) -> &mut String {
+        &mut self.attribute.get_or_insert_default().1
+    }
+
+    /// The end tag of the text state is the one that opened it.
+    fn appropriate_end_tag(&self) -> bool {
+        self.end_tag && self.tag.name == self.last_start_tag
+    }
+
+    fn eof_in_doctype(&mut self) {
+        self.error("eof-in-doctype");
+        self.doctype.force_quirks = true;
+        self.emit_doctype();
+        self.emit(Token::Eof);
+    }
+
+    fn doctype_identifier(&mut self, identifier: Identifier) -> &mut String {
+        let id = match identifier {
+            Identifier::Public => &mut self.doctype.public_id,
+            Identifier::System => &mut self.doctype.system_id,
+        };
+        id.get_or_insert_default()
+    }
+
+    /// Whether the input continues with `expected`, `None` if it is too short
+    /// to tell yet.
+    fn lookahead(&self, expected: &str, ignore_case: bool) -> Option<bool> {
+        let mut input = self.input[self.position..].iter();
+        for e in expected.chars() {
+            match input.next() {
+                Some(c) if *c == e || (ignore_case && c.eq_ignore_ascii_case(&e)) => {}
+                Some(_) => return Some(false),
+                None if self.end => return Some(false),
+                None => return None,
+            }
+        }
+        Some(true)
+    }
+
+    /// Runs the state machine for one character, `false` when it needs more
+    /// input first.
+    fn step(&mut self) -> bool {
+        if let State::MarkupDeclarationOpen = self.state {
+            return self.markup_declaration_open();
+        }
+        if let State::CharacterReference(state) = self.state {
+            return self.character_reference(state);
+        }
+        if self.state == State::AfterDoctypeName
+            && self
+                .input
+                .get(self.position)
+                .is_some_and(|c| !is_whitespace(*c) && *c != '>')
+        {
+            return self.after_doctype_name_keyword();
+        }
+
+        let c = match self.input.get(self.position) {
+            Some(&c) => {
+                self.position += 1;
+                Some(c)
+            }
+            None if self.end => None,
+            None => return false,
+        };
+        self.consume(c);
+        true
+    }
+
+    /// Goes to `state` with `c` still to be consumed.
+    fn reconsume(&mut self, state: State, c: Option<char>) {
+        if c.is_some() {
+            self.position -= 1;
+        }
+        self.state = state;
+    }
+
+    fn consume(&mut self, c: Option<char>) {
+        use State::*;
+
+        match self.state {
+            Data => match c {
+                Some('&') => self.state = CharacterReference(Return::Data),
+                Some('<') => self.state = TagOpen,
+                Some('\0') => {
+                    self.error("unexpected-null-character");
+                    self.emit(Token::Character('\0'));
+                }
+                None => self.emit(Token::Eof),
+                Some(c) => self.emit(Token::Character(c)),
+            },
+            Rcdata | Rawtext | ScriptData | Plaintext => match c {
+                Some('&') if self.state == Rcdata => {
+                    self.state = CharacterReference(Return::Rcdata)
+                }
+                Some('<') if self.state != Plaintext => {
+                    self.state = TextLessThanSign(match self.state {
+                        Rcdata => Text::Rcdata,
+                        Rawtext => Text::Rawtext,
+                        _ => Text::ScriptData,
+                    })
+                }
+                Some('\0') => {
+                    self.error("unexpected-null-character");
+                    self.emit(Token::Character(REPLACEMENT));
+                }
+                None => self.emit(Token::Eof),
+                Some(c) => self.emit(Token::Character(c)),
+            },
+            TagOpen => match c {
+                Some('!') => self.state = MarkupDeclarationOpen,
+                Some('/') => self.state = EndTagOpen,
+                Some(c) if c.is_ascii_alphabetic() => {
+                    self.new_tag(false);
+                    self.reconsume(TagName, Some(c));
+                }
+                Some('?') => {
+                    self.error("unexpected-question-mark-instead-of-tag-name");
+                    self.comment.clear();
+                    self.reconsume(BogusComment, c);
+                }
+                None => {
+                    self.error("eof-before-tag-name");
+                    self.emit(Token::Character('<'));
+                    self.emit(Token::Eof);
+                }
+                Some(_) => {
+                    self.error("invalid-first-character-of-tag-name");
+                    self.emit(Token::Character('<'));
+                    self.reconsume(Data, c);
+                }
+            },
+            EndTagOpen => match c {
+                Some(c) if c.is_ascii_alphabetic() => {
+                    self.new_tag(true);
+                    self.reconsume(TagName, Some(c));
+                }
+                Some('>') => {
+                    self.error("missing-end-tag-name");
+                    self.state = Data;
+                }
+                None => {
+                    self.error("eof-before-tag-name");
+                    self.emit_str("</");
+                    self.emit(Token::Eof);
+                }
+                Some(_) => {
+                    self.error("invalid-first-character-of-tag-name");
+                    self.comment.clear();
+                    self.reconsume(BogusComment, c);
+                }
+            },
+            TagName => match c {
+                Some(c) if is_whitespace(c) => self.state = BeforeAttributeName,
+                Some('/') => self.state = SelfClosingStartTag,
+                Some('>') => {
+                    self.state = Data;
+                    self.emit_tag();
+                }
+                Some('\0') => {
+                    self.error("unexpected-null-character");
+                    self.tag.name.push(REPLACEMENT);
+                }
+                None => {
+                    self.error("eof-in-tag");
+                    self.emit(Token::Eof);
+                }
+                Some(c) => self.tag.name.push(c.to_ascii_lowercase()),
+            },
+            TextLessThanSign(text) => match (c, text) {
+                (Some('/'), _) => {
+                    self.buffer.clear();
+                    self.state = TextEndTagOpen(text);
+                }
+                (Some('!'), Text::ScriptData) => {
+                    self.state = ScriptDataEscapeStart;
+                    self.emit_str("<!");
+                }
+                (Some(c), Text::ScriptDataEscaped) if c.is_ascii_alphabetic() => {
+                    self.buffer.clear();
+                    self.emit(Token::Character('<'));
+                    self.reconsume(ScriptDataDoubleEscapeStart, Some(c));
+                }
+                _ => {
+                    self.emit(Token::Character('<'));
+                    self.reconsume(text_state(text), c);
+                }
+            },
+            TextEndTagOpen(text) => match c {
+                Some(c) if c.is_ascii_alphabetic() => {
+                    self.new_tag(true);
+                    self.reconsume(TextEndTagName(text), Some(c));
+                }
+                _ => {
+                    self.emit_str("</");
+                    self.reconsume(text_state(text), c);
+                }
+            },
+            TextEndTagName(text) => match c {
+                Some(c) if is_whitespace(c) && self.appropriate_end_tag() => {
+                    self.state = BeforeAttributeName
+                }
+                Some('/') if self.appropriate_end_tag() => self.state = SelfClosingStartTag,
+                Some('>') if self.appropriate_end_tag() => {
+                    self.state = Data;
+                    self.emit_tag();
+                }
+                Some(c) if c.is_ascii_alphabetic() => {
+                    self.tag.name.push(c.to_ascii_lowercase());
+                    self.buffer.push(c);
+                }
+                _ => {
+                    self.emit_str("</");
+                    let buffer = std::mem::take(&mut self.buffer);
+                    self.emit_str(&buffer);
+                    self.reconsume(text_state(text), c);
+                }
+            },
+            ScriptDataEscapeStart | ScriptDataEscapeStartDash => match c {
+                Some('-') => {
+                    self.state = match self.state {
+                        ScriptDataEscapeStart => ScriptDataEscapeStartDash,
+                        _ => ScriptDataEscapedDashDash,
+                    };
+                    self.emit(Token::Character('-'));
+                }
+                _ => self.reconsume(ScriptData, c),
+            },
+            ScriptDataEscaped | ScriptDataEscapedDash | ScriptDataEscapedDashDash => match c {
+                Some('-') => {
+                    self.state = match self.state {
+                        ScriptDataEscaped => ScriptDataEscapedDash,
+                        _ => ScriptDataEscapedDashDash,
+                    };
+                    self.emit(Token::Character('-'));
+                }
+                Some('<') => self.state = TextLessThanSign(Text::ScriptDataEscaped),
+                Some('>') if self.state == ScriptDataEscapedDashDash => {
+                    self.state = ScriptData;
+                    self.emit(Token::Character('>'));
+                }
+                Some('\0') => {
+                    self.error("unexpected-null-character");
+                    self.state = ScriptDataEscaped;
+                    self.emit(Token::Character(REPLACEMENT));
+                }
+                None => {
+                    self.error("eof-in-script-html-comment-like-text");
+                    self.emit(Token::Eof);
+                }
+                Some(c) => {
+                    self.state = ScriptDataEscaped;
+                    self.emit(Token::Character(c));
+                }
+            },
+            ScriptDataDoubleEscapeStart | ScriptDataDoubleEscapeEnd => match c {
+                Some(c) if is_whitespace(c) || c == '/' || c == '>' => {
+                    let script = self.buffer == "script";
+                    self.state = match (self.state, script) {
+                        (ScriptDataDoubleEscapeStart, true) => ScriptDataDoubleEscaped,
+                        (ScriptDataDoubleEscapeStart, false) => ScriptDataEscaped,
+                        (_, true) => ScriptDataEscaped,
+                        (_, false) => ScriptDataDoubleEscaped,
+                    };
+                    self.emit(Token::Character(c));
+                }
+                Some(c) if c.is_ascii_alphabetic() => {
+                    self.buffer.push(c.to_ascii_lowercase());
+                    self.emit(Token::Character(c));
+                }
+                _ => {
+                    let state = match self.state {
+                        ScriptDataDoubleEscapeStart => ScriptDataEscaped,
+                        _ => ScriptDataDoubleEscaped,
+                    };
+                    self.reconsume(state, c);
+                }
+            },
+            ScriptDataDoubleEscaped
+            | ScriptDataDoubleEscapedDash
+            | ScriptDataDoubleEscapedDashDash => match c {
+                Some('-') => {
+                    self.state = match self.state {
+                        ScriptDataDoubleEscaped => ScriptDataDoubleEscapedDash,
+                        _ => ScriptDataDoubleEscapedDashDash,
+                    };
+                    self.emit(Token::Character('-'));
+                }
+                Some('<') => {
+                    self.state = ScriptDataDoubleEscapedLessThanSign;
+                    self.emit(Token::Character('<'));
+                }
+                Some('>') if self.state == ScriptDataDoubleEscapedDashDash => {
+                    self.state = ScriptData;
+                    self.emit(Token::Character('>'));
+                }
+                Some('\0') => {
+                    self.error("unexpected-null-character");
+                    self.state = ScriptDataDoubleEscaped;
+                    self.emit(Token::Character(REPLACEMENT));
+                }
+                None => {
+                    self.error("eof-in-script-html-comment-like-text");
+                    self.emit(Token::Eof);
+                }
+                Some(c) => {
+                    self.state = ScriptDataDoubleEscaped;
+                    self.emit(Token::Character(c));
+                }
+            },
+            ScriptDataDoubleEscapedLessThanSign => match c {
+                Some('/') => {
+                    self.buffer.clear();
+                    self.state = ScriptDataDoubleEscapeEnd;
+                    self.emit(Token::Character('/'));
+                }
+                _ => self.reconsume(ScriptDataDoubleEscaped, c),
+            },
+            BeforeAttributeName => match c {
+                Some(c) if is_whitespace(c) => {}
+                Some('/' | '>') | None => self.reconsume(AfterAttributeName, c),
+                Some('=') => {
+                    self.error("unexpected-equals-sign-before-attribute-name");
+                    self.new_attribute("=");
+                    self.state = AttributeName;
+                }
+                Some(_) => {
+                    self.new_attribute("");
+                    self.reconsume(AttributeName, c);
+                }
+            },
+            AttributeName => match c {
+                Some(c) if is_whitespace(c) || c == '/' || c == '>' => {
+                    self.reconsume(AfterAttributeName, Some(c))
+                }
+                None => self.reconsume(AfterAttributeName, None),
+                Some('=') => self.state = BeforeAttributeValue,
+                Some('\0') => {
+                    self.error("unexpected-null-character");
+                    self.attribute_name().push(REPLACEMENT);
+                }
+                Some(c) => {
+                    if matches!(c, '"' | '\'' | '<') {
+                        self.error("unexpected-character-in-attribute-name");
+                    }
+                    self.attribute_name().push(c.to_ascii_lowercase());
+                }
+            },
+            AfterAttributeName => match c {
+                Some(c) if is_whitespace(c) => {}
+                Some('/') => self.state = SelfClosingStartTag,
+                Some('=') => self.state = BeforeAttributeValue,
+                Some('>') => {
+                    self.state = Data;
+                    self.emit_tag();
+                }
+                None => {
+                    self.error("eof-in-tag");
+                    self.emit(Token::Eof);
+                }
+                Some(_) => {
+                    self.new_attribute("");
+                    self.reconsume(AttributeName, c);
+                }
+            },
+            BeforeAttributeValue => match c {
+                Some(c) if is_whitespace(c) => {}
+                Some(quote @ ('"' | '\'')) => self.state = AttributeValue(Some(quote)),
+                Some('>') => {
+                    self.error("missing-attribute-value");
+                    self.state = Data;
+                    self.emit_tag();
+                }
+                _ => self.reconsume(AttributeValue(None), c),
+            },
+            AttributeValue(quote) => match c {
+                Some(c) if Some(c) == quote => self.state = AfterAttributeValueQuoted,
+                Some(c) if quote.is_none() && is_whitespace(c) => self.state = BeforeAttributeName,
+                Some('&') => self.state = CharacterReference(Return::AttributeValue(quote)),
+                Some('>') if quote.is_none() => {
+                    self.state = Data;
+                    self.emit_tag();
+                }
+                Some('\0') => {
+                    self.error("unexpected-null-character");
+                    self.attribute_value().push(REPLACEMENT);
+                }
+                None => {
+                    self.error("eof-in-tag");
+                    self.emit(Token::Eof);
+                }
+                Some(c) => {
+                    if quote.is_none() && matches!(c, '"' | '\'' | '<' | '=' | '`') {
+                        self.error("unexpected-character-in-unquoted-attribute-value");
+                    }
+                    self.attribute_value().push(c);
+                }
+            },
+            AfterAttributeValueQuoted => match c {
+                Some(c) if is_whitespace(c) => self.state = BeforeAttributeName,
+                Some('/') => self.state = SelfClosingStartTag,
+                Some('>') => {
+                    self.state = Data;
+                    self.emit_tag();
+                }
+                None => {
+                    self.error("eof-in-tag");
+                    self.emit(Token::Eof);
+                }
+                Some(_) => {
+                    self.error("missing-whitespace-between-attributes");
+                    self.reconsume(BeforeAttributeName, c);
+                }
+            },
+            SelfClosingStartTag => match c {
+                Some('>') => {
+                    self.tag.self_closing = true;
+                    self.state = Data;
+                    self.emit_tag();
+                }
+                None => {
+                    self.error("eof-in-tag");
+                    self.emit(Token::Eof);
+                }
+                Some(_) => {
+                    self.error("unexpected-solidus-in-tag");
+                    self.reconsume(BeforeAttributeName, c);
+                }
+            },
+            BogusComment => match c {
+                Some('>') => {
+                    self.state = Data;
+                    self.emit_comment();
+                }
+                None => {
+                    self.emit_comment();
+                    self.emit(Token::Eof);
+                }
+                Some('\0') => {
+                    self.error("unexpected-null-character");
+                    self.comment.push(REPLACEMENT);
+                }
+                Some(c) => self.comment.push(c),
+            },
+            CommentStart => match c {
+                Some('-') => self.state = CommentStartDash,
+                Some('>') => {
+                    self.error("abrupt-closing-of-empty-comment");
+                    self.state = Data;
+                    self.emit_comment();
+                }
+                _ => self.reconsume(Comment, c),
+            },
+            CommentStartDash => match c {
+                Some('-') => self.state = CommentEnd,
+                Some('>') => {
+                    self.error("abrupt-closing-of-empty-comment");
+                    self.state = Data;
+                    self.emit_comment();
+                }
+                None => self.eof_in_comment(),
+                Some(_) => {
+                    self.comment.push('-');
+                    self.reconsume(Comment, c);
+                }
+            },
+            Comment => match c {
+                Some('<') => {
+                    self.comment.push('<');
+                    self.state = CommentLessThanSign;
+                }
+                Some('-') => self.state = CommentEndDash,
+                Some('\0') => {
+                    self.error("unexpected-null-character");
+                    self.comment.push(REPLACEMENT);
+                }
+                None => self.eof_in_comment(),
+                Some(c) => self.comment.push(c),
+            },
+            CommentLessThanSign => match c {
+                Some('!') => {
+                    self.comment.push('!');
+                    self.state = CommentLessThanSignBang;
+                }
+                Some('<') => self.comment.push('<'),
+                _ => self.reconsume(Comment, c),
+            },
+            CommentLessThanSignBang => match c {
+                Some('-') => self.state = CommentLessThanSignBangDash,
+                _ => self.reconsume(Comment, c),
+            },
+            CommentLessThanSignBangDash => match c {
+                Some('-') => self.state = CommentLessThanSignBangDashDash,
+                _ => self.reconsume(CommentEndDash, c),
+            },
+            CommentLessThanSignBangDashDash => {
+                if !matches!(c, Some('>') | None) {
+                    self.error("nested-comment");
+                }
+                self.reconsume(CommentEnd, c);
+            }
+            CommentEndDash => match c {
+                Some('-') => self.state = CommentEnd,
+                None => self.eof_in_comment(),
+                Some(_) => {
+                    self.comment.push('-');
+                    self.reconsume(Comment, c);
+                }
+            },
+            CommentEnd => match c {
+                Some('>') => {
+                    self.state = Data;
+                    self.emit_comment();
+                }
+                Some('!') => self.state = CommentEndBang,
+                Some('-') => self.comment.push('-'),
+                None => self.eof_in_comment(),
+                Some(_) => {
+                    self.comment.push_str("--");
+                    self.reconsume(Comment, c);
+                }
+            },
+            CommentEndBang => match c {
+                Some('-') => {
+                    self.comment.push_str("--!");
+                    self.state = CommentEndDash;
+                }
+                Some('>') => {
+                    self.error("incorrectly-closed-comment");
+                    self.state = Data;
+                    self.emit_comment();
+                }
+                None => self.eof_in_comment(),
+                Some(_) => {
+                    self.comment.push_str("--!");
+                    self.reconsume(Comment, c);
+                }
+            },
+            Doctype => match c {
+                Some(c) if is_whitespace(c) => self.state = BeforeDoctypeName,
+                Some('>') => self.reconsume(BeforeDoctypeName, c),
+                None => {
+                    self.doctype = Default::default();
+                    self.eof_in_doctype();
+                }
+                Some(_) => {
+                    self.error("missing-whitespace-before-doctype-name");
+                    self.reconsume(BeforeDoctypeName, c);
+                }
+            },
+            BeforeDoctypeName => match c {
+                Some(c) if is_whitespace(c) => {}
+                Some('>') => {
+                    self.error("missing-doctype-name");
+                    self.doctype = Default::default();
+                    self.doctype.force_quirks = true;
+                    self.state = Data;
+                    self.emit_doctype();
+                }
+                None => {
+                    self.doctype = Default::default();
+                    self.eof_in_doctype();
+                }
+                Some(c) => {
+                    self.doctype = Default::default();
+                    let c = match c {
+                        '\0' => {
+                            self.error("unexpected-null-character");
+                            REPLACEMENT
+                        }
+                        c => c.to_ascii_lowercase(),
+                    };
+                    self.doctype.name = Some(c.to_string());
+                    self.state = DoctypeName;
+                }
+            },
+            DoctypeName => match c {
+                Some(c) if is_whitespace(c) => self.state = AfterDoctypeName,
+                Some('>') => {
+                    self.state = Data;
+                    self.emit_doctype();
+                }
+                None => self.eof_in_doctype(),
+                Some(c) => {
+                    let c = match c {
+                        '\0' => {
+                            self.error("unexpected-null-character");
+                            REPLACEMENT
+                        }
+                        c => c.to_ascii_lowercase(),
+                    };
+                    self.doctype.name.get_or_insert_default().push(c);
+                }
+            },
+            // anything else is looked at in `after_doctype_name_keyword`
+            AfterDoctypeName => match c {
+                Some('>') => {
+                    self.state = Data;
+                    self.emit_doctype();
+                }
+                None => self.eof_in_doctype(),
+                Some(_) => {}
+            },
+            AfterDoctypeKeyword(identifier) => match c {
+                Some(c) if is_whitespace(c) => self.state = BeforeDoctypeIdentifier(identifier),
+                Some(quote @ ('"' | '\'')) => {
+                    self.error(match identifier {
+                        Identifier::Public => "missing-whitespace-after-doctype-public-keyword",
+                        Identifier::System => "missing-whitespace-after-doctype-system-keyword",
+                    });
+                    self.doctype_identifier(identifier).clear();
+                    self.state = DoctypeIdentifier(identifier, quote);
+                }
+                _ => self.doctype_identifier_missing(identifier, c),
+            },
+            BeforeDoctypeIdentifier(identifier) => match c {
+                Some(c) if is_whitespace(c) => {}
+                Some(quote @ ('"' | '\'')) => {
+                    self.doctype_identifier(identifier).clear();
+                    self.state = DoctypeIdentifier(identifier, quote);
+                }
+                _ => self.doctype_identifier_missing(identifier, c),
+            },
+            DoctypeIdentifier(identifier, quote) => match c {
+                Some(c) if c == quote => self.state = AfterDoctypeIdentifier(identifier),
+                Some('\0') => {
+                    self.error("unexpected-null-character");
+                    self.doctype_identifier(identifier).push(REPLACEMENT);
+                }
+                Some('>') => {
+                    self.error(match identifier {
+                        Identifier::Public => "abrupt-doctype-public-identifier",
+                        Identifier::System => "abrupt-doctype-system-identifier",
+                    });
+                    self.doctype.force_quirks = true;
+                    self.state = Data;
+                    self.emit_doctype();
+                }
+                None => self.eof_in_doctype(),
+                Some(c) => self.doctype_identifier(identifier).push(c),
+            },
+            AfterDoctypeIdentifier(Identifier::Public)
+            | BetweenDoctypePublicAndSystemIdentifiers => match c {
+                Some(c) if is_whitespace(c) => {
+                    self.state = BetweenDoctypePublicAndSystemIdentifiers
+                }
+                Some('>') => {
+                    self.state = Data;
+                    self.emit_doctype();
+                }
+                Some(quote @ ('"' | '\'')) => {
+                    if self.state != BetweenDoctypePublicAndSystemIdentifiers {
+                        self.error(
+                            "missing-whitespace-between-doctype-public-and-system-identifiers",
+                        );
+                    }
+                    self.doctype.system_id = Some(String::new());
+                    self.state = DoctypeIdentifier(Identifier::System, quote);
+                }
+                None => self.eof_in_doctype(),
+                Some(_) => {
+                    self.error("missing-quote-before-doctype-system-identifier");
+                    self.doctype.force_quirks = true;
+                    self.reconsume(BogusDoctype, c);
+                }
+            },
+            AfterDoctypeIdentifier(Identifier::System) => match c {
+                Some(c) if is_whitespace(c) => {}
+                Some('>') => {
+                    self.state = Data;
+                    self.emit_doctype();
+                }
+                None => self.eof_in_doctype(),
+                Some(_) => {
+                    self.error("unexpected-character-after-doctype-system-identifier");
+                    self.reconsume(BogusDoctype, c);
+                }
+            },
+            BogusDoctype => match c {
+                Some('>') => {
+                    self.state = Data;
+                    self.emit_doctype();
+                }
+                Some('\0') => self.error("unexpected-null-character"),
+                None => {
+                    self.emit_doctype();
+                    self.emit(Token::Eof);
+                }
+                Some(_) => {}
+            },
+            CdataSection => match c {
+                Some(']') => self.state = CdataSectionBracket,
+                None => {
+                    self.error("eof-in-cdata");
+                    self.emit(Token::Eof);
+                }
+                Some(c) => self.emit(Token::Character(c)),
+            },
+            CdataSectionBracket => match c {
+                Some(']') => self.state = CdataSectionEnd,
+                _ => {
+                    self.emit(Token::Character(']'));
+                    self.reconsume(CdataSection, c);
+                }
+            },
+            CdataSectionEnd => match c {
+                Some(']') => self.emit(Token::Character(']')),
+                Some('>') => self.state = Data,
+                _ => {
+                    self.emit_str("]]");
+                    self.reconsume(CdataSection, c);
+                }
+            },
+            MarkupDeclarationOpen | CharacterReference(_) => unreachable!(),
+        }
+    }
+
+    fn eof_in_comment(&mut self) {
+        self.error("eof-in-comment");
+        self.emit_comment();
+        self.emit(Token::Eof);
+    }
+
+    /// Neither a quoted identifier nor the end of the doctype after `PUBLIC`
+    /// or `SYSTEM`.
+    fn doctype_identifier_missing(&mut self, identifier: Identifier, c: Option<char>) {
+        match c {
+            Some('>') => {
+                self.error(match identifier {
+                    Identifier::Public => "missing-doctype-public-identifier",
+                    Identifier::System => "missing-doctype-system-identifier",
+                });
+                self.doctype.force_quirks = true;
+                self.state = State::Data;
+                self.emit_doctype();
+            }
+            None => self.eof_in_doctype(),
+            Some(_) => {
+                self.error(match identifier {
+                    Identifier::Public => "missing-quote-before-doctype-public-identifier",
+                    Identifier::System => "missing-quote-before-doctype-system-identifier",
+                });
+                self.doctype.force_quirks = true;
+                self.reconsume(State::BogusDoctype, c);
+            }
+        }
+    }
+
+    /// `<!` followed by a comment, doctype or CDATA section.
+    fn markup_declaration_open(&mut self) -> bool {
+        let candidates = [
+            ("--", false, State::CommentStart),
+            ("DOCTYPE", true, State::Doctype),
+            ("[CDATA[", false, State::CdataSection),
+        ];
+        for (expected, ignore_case, state) in candidates {
+            match self.lookahead(expected, ignore_case) {
+                None => return false,
+                Some(false) => continue,
+                Some(true) => {
+                    self.position += expected.len();
+                    self.comment.clear();
+                    self.state = state;
+                    if state == State::CdataSection && !self.cdata_allowed {
+                        self.error("cdata-in-html-content");
+                        self.comment.push_str("[CDATA[");
+                        self.state = State::BogusComment;
+                    }
+                    return true;
+                }
+            }
+        }
+
+        self.error("incorrectly-opened-comment");
+        self.comment.clear();
+        self.state = State::BogusComment;
+        true
+    }
+
+    /// `PUBLIC` or `SYSTEM` after the doctype name.
+    fn after_doctype_name_keyword(&mut self) -> bool {
+        for (keyword, identifier) in [
+            ("PUBLIC", Identifier::Public),
+            ("SYSTEM", Identifier::System),
+        ] {
+            match self.lookahead(keyword, true) {
+                None => return false,
+                Some(false) => continue,
+                Some(true) => {
+                    self.position += keyword.len();
+                    self.state = State::AfterDoctypeKeyword(identifier);
+                    return true;
+                }
+            }
+        }
+
+        self.error("invalid-character-sequence-after-doctype-name");
+        self.doctype.force_quirks = true;
+        self.state = State::BogusDoctype;
+        true
+    }
+
+    /// What follows `&`. The longest reference that matches is replaced, a
+    /// name or number that has not fully arrived is waited for.
+    fn character_reference(&mut self, return_state: Return) -> bool {
+        let rest = &self.input[self.position..];
+        let terminated = rest
+            .iter()
+            .position(|c| !c.is_ascii_alphanumeric() && *c != '#')
+            .map(|end| end + 1);
+        let Some(end) = terminated.or(self.end.then_some(rest.len())) else {
+            return false;
+        };
+        let text: String = rest[..end].iter().collect();
+
+        let in_attribute = matches!(return_state, Return::AttributeValue(_));
+        let reference = match text.strip_prefix('#') {
+            Some(number) => match entities::numeric(number) {
+                Some((replacement, length)) => {
+                    if !text[..length].ends_with(';') {
+                        self.error("missing-semicolon-after-character-reference");
+                    }
+                    if let Some(code) = entities::numeric_error(number) {
+                        self.error(code);
+                    }
+                    Some((replacement, length))
+                }
+                None => {
+                    self.error("absence-of-digits-in-numeric-character-reference");
+                    None
+                }
+            },
+            None => match entities::named(&text, in_attribute) {
+                Some((replacement, length)) => {
+                    if !text[..length].ends_with(';') {
+                        self.error("missing-semicolon-after-character-reference");
+                    }
+                    Some((replacement, length))
+                }
+                None => {
+                    let name = text.trim_start_matches(|c: char| c.is_ascii_alphanumeric());
+                    if name.len() < text.len() && name.starts_with(';') {
+                        self.error("unknown-named-character-reference");
+                    }
+                    None
+                }
+            },
+        };
+
+        let replacement = match reference {
+            Some((replacement, length)) => {
+                self.position += length;
+                replacement
+            }
+            None => "&".to_string(),
+        };
+        self.state = match return_state {
+            Return::Data => State::Data,
+            Return::Rcdata => State::Rcdata,
+            Return::AttributeValue(quote) => State::AttributeValue(quote),
+        };
+        match return_state {
+            Return::AttributeValue(_) => self.attribute_value().push_str(&replacement),
+            _ => self.emit_str(&replacement),
+        }
+        true
+    }
+}
+
+fn text_state(text: Text) -> State {
+    match text {
+        Text::Rcdata => State::Rcdata,
+        Text::Rawtext => State::Rawtext,
+        Text::ScriptData => State::ScriptData,
+        Text::ScriptDataEscaped => State::ScriptDataEscaped,
+    }
+}
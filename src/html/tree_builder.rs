@@ -0,0 +1,2574 @@
+//! Tree construction, https://html.spec.whatwg.org/multipage/parsing.html#tree-construction.
+//! Every insertion mode is a method that takes a token. Parse errors do not
+//! stop anything, each one has a prescribed recovery which is what makes every
+//! browser build the same tree from the same broken page.
+use super::dom::{Document, Element, Namespace, NodeData, NodeId};
+use super::tokenizer::{Doctype, State, Tag, Token};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Mode {
+    Initial,
+    BeforeHtml,
+    BeforeHead,
+    InHead,
+    InHeadNoscript,
+    AfterHead,
+    InBody,
+    Text,
+    InTable,
+    InTableText,
+    InCaption,
+    InColumnGroup,
+    InTableBody,
+    InRow,
+    InCell,
+    InSelect,
+    InSelectInTable,
+    InTemplate,
+    AfterBody,
+    InFrameset,
+    AfterFrameset,
+    AfterAfterBody,
+    AfterAfterFrameset,
+}
+
+/// What the doctype says about how old the page is. Only tables care here.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum QuirksMode {
+    #[default]
+    NoQuirks,
+    LimitedQuirks,
+    Quirks,
+}
+
+/// An entry in the list of active formatting elements, with the tag it was
+/// made from so it can be made again.
+#[derive(Debug, Clone)]
+enum Formatting {
+    Marker,
+    Element(NodeId, Tag),
+}
+
+/// Elements with special parsing rules.
+const SPECIAL: &[&str] = &[
+    "address",
+    "applet",
+    "area",
+    "article",
+    "aside",
+    "base",
+    "basefont",
+    "bgsound",
+    "blockquote",
+    "body",
+    "br",
+    "button",
+    "caption",
+    "center",
+    "col",
+    "colgroup",
+    "dd",
+    "details",
+    "dir",
+    "div",
+    "dl",
+    "dt",
+    "embed",
+    "fieldset",
+    "figcaption",
+    "figure",
+    "footer",
+    "form",
+    "frame",
+    "frameset",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "head",
+    "header",
+    "hgroup",
+    "hr",
+    "html",
+    "iframe",
+    "img",
+    "input",
+    "keygen",
+    "li",
+    "link",
+    "listing",
+    "main",
+    "marquee",
+    "menu",
+    "meta",
+    "nav",
+    "noembed",
+    "noframes",
+    "noscript",
+    "object",
+    "ol",
+    "p",
+    "param",
+    "plaintext",
+    "pre",
+    "script",
+    "search",
+    "section",
+    "select",
+    "source",
+    "style",
+    "summary",
+    "table",
+    "tbody",
+    "td",
+    "template",
+    "textarea",
+    "tfoot",
+    "th",
+    "thead",
+    "title",
+    "tr",
+    "track",
+    "ul",
+    "wbr",
+    "xmp",
+];
+
+const HEADINGS: &[&str] = &["h1", "h2", "h3", "h4", "h5", "h6"];
+
+/// Elements whose end tag may be left out.
+const IMPLIED_END_TAGS: &[&str] = &[
+    "dd", "dt", "li", "optgroup", "option", "p", "rb", "rp", "rt", "rtc",
+];
+
+/// Elements that never have content, `<br/>` is fine for these.
+const VOID: &[&str] = &[
+    "area", "base", "basefont", "bgsound", "br", "col", "embed", "frame", "hr", "img", "input",
+    "keygen", "link", "meta", "param", "source", "track", "wbr",
+];
+
+const FORMATTING: &[&str] = &[
+    "a", "b", "big", "code", "em", "font", "i", "nobr", "s", "small", "strike", "strong", "tt", "u",
+];
+
+/// Start tags that end SVG and MathML and go back to HTML.
+const BREAKS_OUT_OF_FOREIGN_CONTENT: &[&str] = &[
+    "b",
+    "big",
+    "blockquote",
+    "body",
+    "br",
+    "center",
+    "code",
+    "dd",
+    "div",
+    "dl",
+    "dt",
+    "em",
+    "embed",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "head",
+    "hr",
+    "i",
+    "img",
+    "li",
+    "listing",
+    "menu",
+    "meta",
+    "nobr",
+    "ol",
+    "p",
+    "pre",
+    "ruby",
+    "s",
+    "small",
+    "span",
+    "strong",
+    "strike",
+    "sub",
+    "sup",
+    "table",
+    "tt",
+    "u",
+    "ul",
+    "var",
+];
+
+/// The tokenizer lowercases everything, SVG has some names in camel case.
+const SVG_TAG_NAMES: &[(&str, &str)] = &[
+    ("altglyph", "altGlyph"),
+    ("altglyphdef", "altGlyphDef"),
+    ("altglyphitem", "altGlyphItem"),
+    ("animatecolor", "animateColor"),
+    ("animatemotion", "animateMotion"),
+    ("animatetransform", "animateTransform"),
+    ("clippath", "clipPath"),
+    ("feblend", "feBlend"),
+    ("fecolormatrix", "feColorMatrix"),
+    ("fecomponenttransfer", "feComponentTransfer"),
+    ("fecomposite", "feComposite"),
+    ("feconvolvematrix", "feConvolveMatrix"),
+    ("fediffuselighting", "feDiffuseLighting"),
+    ("fedisplacementmap", "feDisplacementMap"),
+    ("fedistantlight", "feDistantLight"),
+    ("fedropshadow", "feDropShadow"),
+    ("feflood", "feFlood"),
+    ("fefunca", "feFuncA"),
+    ("fefuncb", "feFuncB"),
+    ("fefuncg", "feFuncG"),
+    ("fefuncr", "feFuncR"),
+    ("fegaussianblur", "feGaussianBlur"),
+    ("feimage", "feImage"),
+    ("femerge", "feMerge"),
+    ("femergenode", "feMergeNode"),
+    ("femorphology", "feMorphology"),
+    ("feoffset", "feOffset"),
+    ("fepointlight", "fePointLight"),
+    ("fespecularlighting", "feSpecularLighting"),
+    ("fespotlight", "feSpotLight"),
+    ("fetile", "feTile"),
+    ("feturbulence", "feTurbulence"),
+    ("foreignobject", "foreignObject"),
+    ("glyphref", "glyphRef"),
+    ("lineargradient", "linearGradient"),
+    ("radialgradient", "radialGradient"),
+    ("textpath", "textPath"),
+];
+
+const SVG_ATTRIBUTES: &[(&str, &str)] = &[
+    ("attributename", "attributeName"),
+    ("attributetype", "attributeType"),
+    ("basefrequency", "baseFrequency"),
+    ("baseprofile", "baseProfile"),
+    ("calcmode", "calcMode"),
+    ("clippathunits", "clipPathUnits"),
+    ("diffuseconstant", "diffuseConstant"),
+    ("edgemode", "edgeMode"),
+    ("filterunits", "filterUnits"),
+    ("glyphref", "glyphRef"),
+    ("gradienttransform", "gradientTransform"),
+    ("gradientunits", "gradientUnits"),
+    ("kernelmatrix", "kernelMatrix"),
+    ("kernelunitlength", "kernelUnitLength"),
+    ("keypoints", "keyPoints"),
+    ("keysplines", "keySplines"),
+    ("keytimes", "keyTimes"),
+    ("lengthadjust", "lengthAdjust"),
+    ("limitingconeangle", "limitingConeAngle"),
+    ("markerheight", "markerHeight"),
+    ("markerunits", "markerUnits"),
+    ("markerwidth", "markerWidth"),
+    ("maskcontentunits", "maskContentUnits"),
+    ("maskunits", "maskUnits"),
+    ("numoctaves", "numOctaves"),
+    ("pathlength", "pathLength"),
+    ("patterncontentunits", "patternContentUnits"),
+    ("patterntransform", "patternTransform"),
+    ("patternunits", "patternUnits"),
+    ("pointsatx", "pointsAtX"),
+    ("pointsaty", "pointsAtY"),
+    ("pointsatz", "pointsAtZ"),
+    ("preservealpha", "preserveAlpha"),
+    ("preserveaspectratio", "preserveAspectRatio"),
+    ("primitiveunits", "primitiveUnits"),
+    ("refx", "refX"),
+    ("refy", "refY"),
+    ("repeatcount", "repeatCount"),
+    ("repeatdur", "repeatDur"),
+    ("requiredextensions", "requiredExtensions"),
+    ("requiredfeatures", "requiredFeatures"),
+    ("specularconstant", "specularConstant"),
+    ("specularexponent", "specularExponent"),
+    ("spreadmethod", "spreadMethod"),
+    ("startoffset", "startOffset"),
+    ("stddeviation", "stdDeviation"),
+    ("stitchtiles", "stitchTiles"),
+    ("surfacescale", "surfaceScale"),
+    ("systemlanguage", "systemLanguage"),
+    ("tablevalues", "tableValues"),
+    ("targetx", "targetX"),
+    ("targety", "targetY"),
+    ("textlength", "textLength"),
+    ("viewbox", "viewBox"),
+    ("viewtarget", "viewTarget"),
+    ("xchannelselector", "xChannelSelector"),
+    ("ychannelselector", "yChannelSelector"),
+    ("zoomandpan", "zoomAndPan"),
+];
+
+/// Public identifiers of doctypes that mean quirks mode, compared by prefix.
+const QUIRKS_PUBLIC_PREFIXES: &[&str] = &[
+    "+//silmaril//dtd html pro v0r11 19970101//",
+    "-//as//dtd html 3.0 aswedit + extensions//",
+    "-//advasoft ltd//dtd html 3.0 aswedit + extensions//",
+    "-//ietf//dtd html 2.0 level 1//",
+    "-//ietf//dtd html 2.0 level 2//",
+    "-//ietf//dtd html 2.0 strict level 1//",
+    "-//ietf//dtd html 2.0 strict level 2//",
+    "-//ietf//dtd html 2.0 strict//",
+    "-//ietf//dtd html 2.0//",
+    "-//ietf//dtd html 2.1e//",
+    "-//ietf//dtd html 3.0//",
+    "-//ietf//dtd html 3.2 final//",
+    "-//ietf//dtd html 3.2//",
+    "-//ietf//dtd html 3//",
+    "-//ietf//dtd html level 0//",
+    "-//ietf//dtd html level 1//",
+    "-//ietf//dtd html level 2//",
+    "-//ietf//dtd html level 3//",
+    "-//ietf//dtd html strict level 0//",
+    "-//ietf//dtd html strict level 1//",
+    "-//ietf//dtd html strict level 2//",
+    "-//ietf//dtd html strict level 3//",
+    "-//ietf//dtd html strict//",
+    "-//ietf//dtd html//",
+    "-//metrius//dtd metrius presentational//",
+    "-//microsoft//dtd internet explorer 2.0 html strict//",
+    "-//microsoft//dtd internet explorer 2.0 html//",
+    "-//microsoft//dtd internet explorer 2.0 tables//",
+    "-//microsoft//dtd internet explorer 3.0 html strict//",
+    "-//microsoft//dtd internet explorer 3.0 html//",
+    "-//microsoft//dtd internet explorer 3.0 tables//",
+    "-//netscape comm. corp.//dtd html//",
+    "-//netscape comm. corp.//dtd strict html//",
+    "-//o'reilly and associates//dtd html 2.0//",
+    "-//o'reilly and associates//dtd html extended 1.0//",
+    "-//o'reilly and associates//dtd html extended relaxed 1.0//",
+    "-//sq//dtd html 2.0 hotmetal + extensions//",
+    "-//softquad software//dtd hotmetal pro 6.0::19990601::extensions to html 4.0//",
+    "-//softquad//dtd hotmetal pro 4.0::19971010::extensions to html 4.0//",
+    "-//spyglass//dtd html 2.0 extended//",
+    "-//sun microsystems corp.//dtd hotjava html//",
+    "-//sun microsystems corp.//dtd hotjava strict html//",
+    "-//w3c//dtd html 3 1995-03-24//",
+    "-//w3c//dtd html 3.2 draft//",
+    "-//w3c//dtd html 3.2 final//",
+    "-//w3c//dtd html 3.2//",
+    "-//w3c//dtd html 3.2s draft//",
+    "-//w3c//dtd html 4.0 frameset//",
+    "-//w3c//dtd html 4.0 transitional//",
+    "-//w3c//dtd html experimental 19960712//",
+    "-//w3c//dtd html experimental 970421//",
+    "-//w3c//dtd w3 html//",
+    "-//w3o//dtd w3 html 3.0//",
+    "-//webtechs//dtd mozilla html 2.0//",
+    "-//webtechs//dtd mozilla html//",
+];
+
+fn is_whitespace(c: char) -> bool {
+    matches!(c, '\t' | '\n' | '\x0C' | '\r' | ' ')
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Scope {
+    Default,
+    ListItem,
+    Button,
+    Table,
+    Select,
+}
+
+pub struct TreeBuilder {
+    document: Document,
+    mode: Mode,
+    /// Where to go back to after `Text` and `InTableText`.
+    original_mode: Mode,
+    template_modes: Vec<Mode>,
+    /// Elements whose end tag has not been seen, innermost last.
+    open: Vec<NodeId>,
+    formatting: Vec<Formatting>,
+    head: Option<NodeId>,
+    form: Option<NodeId>,
+    /// Whether a `<frameset>` can still replace the body.
+    frameset_ok: bool,
+    pub quirks_mode: QuirksMode,
+    /// Misplaced content in tables goes before the table.
+    foster_parenting: bool,
+    pending_table_text: Vec<char>,
+    /// A newline right after `<pre>` is not part of the content.
+    ignore_line_feed: bool,
+    /// The tokenizer state the last token calls for, like RCDATA after `<title>`.
+    pub tokenizer_state: Option<State>,
+    /// Whether the trailing `/` of the start tag being processed is allowed,
+    /// which it is for void elements and in SVG and MathML.
+    self_closing_acknowledged: bool,
+    /// Codes of the parse errors since they were last taken.
+    errors: Vec<&'static str>,
+}
+
+impl Default for TreeBuilder {
+    fn default() -> TreeBuilder {
+        TreeBuilder {
+            document: Document::default(),
+            mode: Mode::Initial,
+            original_mode: Mode::Initial,
+            template_modes: Vec::new(),
+            open: Vec::new(),
+            formatting: Vec::new(),
+            head: None,
+            form: None,
+            frameset_ok: true,
+            quirks_mode: QuirksMode::NoQuirks,
+            foster_parenting: false,
+            pending_table_text: Vec::new(),
+            ignore_line_feed: false,
+            tokenizer_state: None,
+            self_closing_acknowledged: false,
+            errors: Vec::new(),
+        }
+    }
+}
+
+impl TreeBuilder {
+    /// The tree built so far.
+    pub fn document(&self) -> &Document {
+        &self.document
+    }
+
+    pub fn finish(self) -> Document {
+        self.document
+    }
+
+    /// The parse errors since the last call, the tokens have no position so
+    /// the parser adds where the tokenizer is.
+    pub fn take_errors(&mut self) -> Vec<&'static str> {
+        std::mem::take(&mut self.errors)
+    }
+
+    /// Whether the tokenizer should treat `<![CDATA[` as CDATA.
+    pub fn in_foreign_content(&self) -> bool {
+        self.open
+            .last()
+            .and_then(|id| self.document.element(*id))
+            .is_some_and(|element| element.namespace != Namespace::Html)
+    }
+
+    pub fn process(&mut self, token: Token) {
+        if self.ignore_line_feed {
+            self.ignore_line_feed = false;
+            if token == Token::Character('\n') {
+                return;
+            }
+        }
+        let self_closing = matches!(&token, Token::StartTag(tag) if tag.self_closing);
+        self.self_closing_acknowledged = false;
+        self.dispatch(token);
+        if self_closing && !self.self_closing_acknowledged {
+            self.error("non-void-html-element-start-tag-with-trailing-solidus");
+        }
+    }
+
+    fn error(&mut self, code: &'static str) {
+        self.errors.push(code);
+    }
+
+    /// Tokens go through the insertion mode, unless they are in SVG or MathML.
+    fn dispatch(&mut self, token: Token) {
+        if self.is_foreign(&token) {
+            self.foreign_content(token);
+        } else {
+            self.process_in(self.mode, token);
+        }
+    }
+
+    fn reprocess(&mut self, mode: Mode, token: Token) {
+        self.mode = mode;
+        self.dispatch(token);
+    }
+
+    fn process_in(&mut self, mode: Mode, token: Token) {
+        match mode {
+            Mode::Initial => self.initial(token),
+            Mode::BeforeHtml => self.before_html(token),
+            Mode::BeforeHead => self.before_head(token),
+            Mode::InHead => self.in_head(token),
+            Mode::InHeadNoscript => self.in_head_noscript(token),
+            Mode::AfterHead => self.after_head(token),
+            Mode::InBody => self.in_body(token),
+            Mode::Text => self.text(token),
+            Mode::InTable => self.in_table(token),
+            Mode::InTableText => self.in_table_text(token),
+            Mode::InCaption => self.in_caption(token),
+            Mode::InColumnGroup => self.in_column_group(token),
+            Mode::InTableBody => self.in_table_body(token),
+            Mode::InRow => self.in_row(token),
+            Mode::InCell => self.in_cell(token),
+            Mode::InSelect => self.in_select(token),
+            Mode::InSelectInTable => self.in_select_in_table(token),
+            Mode::InTemplate => self.in_template(token),
+            Mode::AfterBody => self.after_body(token),
+            Mode::InFrameset => self.in_frameset(token),
+            Mode::AfterFrameset => self.after_frameset(token),
+            Mode::AfterAfterBody => self.after_after_body(token),
+            Mode::AfterAfterFrameset => self.after_after_frameset(token),
+        }
+    }
+
+    fn initial(&mut self, token: Token) {
+        match token {
+            Token::Character(c) if is_whitespace(c) => {}
+            Token::Comment(comment) => self.insert_comment(comment, Some(self.document.root())),
+            Token::Doctype(doctype) => {
+                let name = doctype.name.clone().unwrap_or_default();
+                if name != "html"
+                    || doctype.public_id.is_some()
+                    || doctype
+                        .system_id
+                        .as_ref()
+                        .is_some_and(|id| id != "about:legacy-compat")
+                {
+                    self.error("unexpected-doctype");
+                }
+                self.quirks_mode = quirks_mode(&doctype);
+
+                let node = self.document.create(NodeData::Doctype {
+                    name,
+                    public_id: doctype.public_id,
+                    system_id: doctype.system_id,
+                });
+                self.document.append(self.document.root(), node);
+                self.mode = Mode::BeforeHtml;
+            }
+            token => {
+                self.error("missing-doctype");
+                self.quirks_mode = QuirksMode::Quirks;
+                self.reprocess(Mode::BeforeHtml, token);
+            }
+        }
+    }
+
+    fn before_html(&mut self, token: Token) {
+        match token {
+            Token::Doctype(_) => self.error("unexpected-doctype"),
+            Token::Comment(comment) => self.insert_comment(comment, Some(self.document.root())),
+            Token::Character(c) if is_whitespace(c) => {}
+            Token::StartTag(tag) if tag.name == "html" => {
+                let html = self.create_element(&tag, Namespace::Html);
+                self.document.append(self.document.root(), html);
+                self.open.push(html);
+                self.mode = Mode::BeforeHead;
+            }
+            Token::EndTag(tag) if !matches!(tag.name.as_str(), "head" | "body" | "html" | "br") => {
+                self.error("unexpected-end-tag")
+            }
+            token => {
+                let html = self.create_element(&synthetic("html"), Namespace::Html);
+                self.document.append(self.document.root(), html);
+                self.open.push(html);
+                self.reprocess(Mode::BeforeHead, token);
+            }
+        }
+    }
+
+    fn before_head(&mut self, token: Token) {
+        match token {
+            Token::Character(c) if is_whitespace(c) => {}
+            Token::Comment(comment) => self.insert_comment(comment, None),
+            Token::Doctype(_) => self.error("unexpected-doctype"),
+            Token::StartTag(ref tag) if tag.name == "html" => self.process_in(Mode::InBody, token),
+            Token::StartTag(tag) if tag.name == "head" => {
+                self.head = Some(self.insert_html(&tag));
+                self.mode = Mode::InHead;
+            }
+            Token::EndTag(tag) if !matches!(tag.name.as_str(), "head" | "body" | "html" | "br") => {
+                self.error("unexpected-end-tag")
+            }
+            token => {
+                self.head = Some(self.insert_html(&synthetic("head")));
+                self.reprocess(Mode::InHead, token);
+            }
+        }
+    }
+
+    fn in_head(&mut self, token: Token) {
+        match token {
+            Token::Character(c) if is_whitespace(c) => self.insert_character(c),
+            Token::Comment(comment) => self.insert_comment(comment, None),
+            Token::Doctype(_) => self.error("unexpected-doctype"),
+            Token::StartTag(tag) => match tag.name.as_str() {
+                "html" => self.in_body(Token::StartTag(tag)),
+                "base" | "basefont" | "bgsound" | "link" | "meta" => {
+                    self.insert_html(&tag);
+                    self.open.pop();
+                }
+                "title" => self.generic_text_element(&tag, State::Rcdata),
+                "noframes" | "style" => self.generic_text_element(&tag, State::Rawtext),
+                "noscript" => {
+                    self.insert_html(&tag);
+                    self.mode = Mode::InHeadNoscript;
+                }
+                "script" => self.generic_text_element(&tag, State::ScriptData),
+                "template" => {
+                    self.insert_html(&tag);
+                    self.formatting.push(Formatting::Marker);
+                    self.frameset_ok = false;
+                    self.mode = Mode::InTemplate;
+                    self.template_modes.push(Mode::InTemplate);
+                }
+                "head" => self.error("unexpected-head"),
+                _ => {
+                    self.open.pop();
+                    self.reprocess(Mode::AfterHead, Token::StartTag(tag));
+                }
+            },
+            Token::EndTag(tag) => match tag.name.as_str() {
+                "head" => {
+                    self.open.pop();
+                    self.mode = Mode::AfterHead;
+                }
+                "template" => {
+                    if !self.open.iter().any(|id| self.is(*id, "template")) {
+                        self.error("unexpected-template-end-tag");
+                        return;
+                    }
+                    self.generate_all_implied_end_tags();
+                    if !self.is(self.current(), "template") {
+                        self.error("template-end-tag-with-open-elements");
+                    }
+                    self.pop_until(&["template"]);
+                    self.clear_formatting_to_marker();
+                    self.template_modes.pop();
+                    self.reset_mode();
+                }
+                "body" | "html" | "br" => {
+                    self.open.pop();
+                    self.reprocess(Mode::AfterHead, Token::EndTag(tag));
+                }
+                _ => self.error("unexpected-end-tag"),
+            },
+            token => {
+                self.open.pop();
+                self.reprocess(Mode::AfterHead, token);
+            }
+        }
+    }
+
+    fn in_head_noscript(&mut self, token: Token) {
+        match token {
+            Token::Doctype(_) => self.error("unexpected-doctype"),
+            Token::StartTag(ref tag) if tag.name == "html" => self.in_body(token),
+            Token::EndTag(tag) if tag.name == "noscript" => {
+                self.open.pop();
+                self.mode = Mode::InHead;
+            }
+            Token::Character(c) if is_whitespace(c) => self.in_head(token),
+            Token::Comment(_) => self.in_head(token),
+            Token::StartTag(ref tag)
+                if matches!(
+                    tag.name.as_str(),
+                    "basefont" | "bgsound" | "link" | "meta" | "noframes" | "style"
+                ) =>
+            {
+                self.in_head(token)
+            }
+            Token::StartTag(ref tag) if matches!(tag.name.as_str(), "head" | "noscript") => {
+                self.error("unexpected-start-tag")
+            }
+            Token::EndTag(ref tag) if tag.name != "br" => self.error("unexpected-end-tag"),
+            token => {
+                self.error("unexpected-token-in-noscript");
+                self.open.pop();
+                self.reprocess(Mode::InHead, token);
+            }
+        }
+    }
+
+    fn after_head(&mut self, token: Token) {
+        match token {
+            Token::Character(c) if is_whitespace(c) => self.insert_character(c),
+            Token::Comment(comment) => self.insert_comment(comment, None),
+            Token::Doctype(_) => self.error("unexpected-doctype"),
+            Token::StartTag(tag) => match tag.name.as_str() {
+                "html" => self.in_body(Token::StartTag(tag)),
+                "body" => {
+                    self.insert_html(&tag);
+                    self.frameset_ok = false;
+                    self.mode = Mode::InBody;
+                }
+                "frameset" => {
+                    self.insert_html(&tag);
+                    self.mode = Mode::InFrameset;
+                }
+                "base" | "basefont" | "bgsound" | "link" | "meta" | "noframes" | "script"
+                | "style" | "template" | "title" => {
+                    self.error("head-element-after-head");
+                    let Some(head) = self.head else { return };
+                    self.open.push(head);
+                    self.in_head(Token::StartTag(tag));
+                    self.open.retain(|id| *id != head);
+                }
+                "head" => self.error("unexpected-head"),
+                _ => {
+                    self.insert_html(&synthetic("body"));
+                    self.reprocess(Mode::InBody, Token::StartTag(tag));
+                }
+            },
+            Token::EndTag(ref tag) if tag.name == "template" => self.in_head(token),
+            Token::EndTag(ref tag) if !matches!(tag.name.as_str(), "body" | "html" | "br") => {
+                self.error("unexpected-end-tag")
+            }
+            token => {
+                self.insert_html(&synthetic("body"));
+                self.reprocess(Mode::InBody, token);
+            }
+        }
+    }
+
+    fn in_body(&mut self, token: Token) {
+        match token {
+            Token::Character('\0') => self.error("unexpected-null-character"),
+            Token::Character(c) => {
+                self.reconstruct_formatting();
+                self.insert_character(c);
+                if !is_whitespace(c) {
+                    self.frameset_ok = false;
+                }
+            }
+            Token::Comment(comment) => self.insert_comment(comment, None),
+            Token::Doctype(_) => self.error("unexpected-doctype"),
+            Token::StartTag(tag) => self.in_body_start_tag(tag),
+            Token::EndTag(tag) => self.in_body_end_tag(tag),
+            Token::Eof => {
+                if !self.template_modes.is_empty() {
+                    self.in_template(Token::Eof);
+                } else if self.unclosed_elements() {
+                    self.error("end-of-file-with-open-elements");
+                }
+            }
+        }
+    }
+
+    fn in_body_start_tag(&mut self, mut tag: Tag) {
+        match tag.name.as_str() {
+            "html" => {
+                self.error("unexpected-html-start-tag");
+                if !self.has_open("template") {
+                    self.add_missing_attributes(self.open[0], tag);
+                }
+            }
+            "base" | "basefont" | "bgsound" | "link" | "meta" | "noframes" | "script" | "style"
+            | "template" | "title" => self.in_head(Token::StartTag(tag)),
+            "body" => {
+                self.error("unexpected-body-start-tag");
+                if self.open.len() > 1
+                    && self.is(self.open[1], "body")
+                    && !self.has_open("template")
+                {
+                    self.frameset_ok = false;
+                    self.add_missing_attributes(self.open[1], tag);
+                }
+            }
+            "frameset" => {
+                self.error("unexpected-frameset-start-tag");
+                if self.open.len() > 1 && self.is(self.open[1], "body") && self.frameset_ok {
+                    self.document.detach(self.open[1]);
+                    self.open.truncate(1);
+                    self.insert_html(&tag);
+                    self.mode = Mode::InFrameset;
+                }
+            }
+            "address" | "article" | "aside" | "blockquote" | "center" | "details" | "dialog"
+            | "dir" | "div" | "dl" | "fieldset" | "figcaption" | "figure" | "footer" | "header"
+            | "hgroup" | "main" | "menu" | "nav" | "ol" | "p" | "search" | "section"
+            | "summary" | "ul" => {
+                self.close_p_in_button_scope();
+                self.insert_html(&tag);
+            }
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                self.close_p_in_button_scope();
+                if self.is_one_of(self.current(), HEADINGS) {
+                    self.error("nested-heading");
+                    self.open.pop();
+                }
+                self.insert_html(&tag);
+            }
+            "pre" | "listing" => {
+                self.close_p_in_button_scope();
+                self.insert_html(&tag);
+                self.ignore_line_feed = true;
+                self.frameset_ok = false;
+            }
+            "form" => {
+                let template = self.has_open("template");
+                if self.form.is_some() && !template {
+                    self.error("nested-form");
+                    return;
+                }
+                self.close_p_in_button_scope();
+                let form = self.insert_html(&tag);
+                if !template {
+                    self.form = Some(form);
+                }
+            }
+            "li" | "dd" | "dt" => {
+                self.frameset_ok = false;
+                let closes: &[&str] = match tag.name.as_str() {
+                    "li" => &["li"],
+                    _ => &["dd", "dt"],
+                };
+                for &node in self.open.clone().iter().rev() {
+                    if let Some(name) = self.html_name(node)
+                        && closes.contains(&name)
+                    {
+                        let name = name.to_string();
+                        self.generate_implied_end_tags(Some(&name));
+                        if !self.is(self.current(), &name) {
+                            self.error("list-item-with-open-elements");
+                        }
+                        self.pop_until(&[&name]);
+                        break;
+                    }
+                    if self.is_special(node) && !self.is_one_of(node, &["address", "div", "p"]) {
+                        break;
+                    }
+                }
+                self.close_p_in_button_scope();
+                self.insert_html(&tag);
+            }
+            "plaintext" => {
+                self.close_p_in_button_scope();
+                self.insert_html(&tag);
+                self.tokenizer_state = Some(State::Plaintext);
+            }
+            "button" => {
+                if self.in_scope(&["button"], Scope::Default) {
+                    self.error("nested-button");
+                    self.generate_implied_end_tags(None);
+                    self.pop_until(&["button"]);
+                }
+                self.reconstruct_formatting();
+                self.insert_html(&tag);
+                self.frameset_ok = false;
+            }
+            "a" => {
+                let open_a = self.formatting_after_marker("a");
+                if let Some(a) = open_a {
+                    self.error("nested-a");
+                    self.adoption_agency(&synthetic("a"));
+                    self.remove_formatting(a);
+                    self.open.retain(|id| *id != a);
+                }
+                self.reconstruct_formatting();
+                let a = self.insert_html(&tag);
+                self.push_formatting(a, tag);
+            }
+            "b" | "big" | "code" | "em" | "font" | "i" | "s" | "small" | "strike" | "strong"
+            | "tt" | "u" => {
+                self.reconstruct_formatting();
+                let element = self.insert_html(&tag);
+                self.push_formatting(element, tag);
+            }
+            "nobr" => {
+                self.reconstruct_formatting();
+                if self.in_scope(&["nobr"], Scope::Default) {
+                    self.error("nested-nobr");
+                    self.adoption_agency(&synthetic("nobr"));
+                    self.reconstruct_formatting();
+                }
+                let nobr = self.insert_html(&tag);
+                self.push_formatting(nobr, tag);
+            }
+            "applet" | "marquee" | "object" => {
+                self.reconstruct_formatting();
+                self.insert_html(&tag);
+                self.formatting.push(Formatting::Marker);
+                self.frameset_ok = false;
+            }
+            "table" => {
+                if self.quirks_mode != QuirksMode::Quirks {
+                    self.close_p_in_button_scope();
+                }
+                self.insert_html(&tag);
+                self.frameset_ok = false;
+                self.mode = Mode::InTable;
+            }
+            "area" | "br" | "embed" | "img" | "keygen" | "wbr" => {
+                self.reconstruct_formatting();
+                self.insert_html(&tag);
+                self.open.pop();
+                self.frameset_ok = false;
+            }
+            "input" => {
+                self.reconstruct_formatting();
+                self.insert_html(&tag);
+                self.open.pop();
+                if !tag
+                    .attribute("type")
+                    .is_some_and(|kind| kind.eq_ignore_ascii_case("hidden"))
+                {
+                    self.frameset_ok = false;
+                }
+            }
+            "param" | "source" | "track" => {
+                self.insert_html(&tag);
+                self.open.pop();
+            }
+            "hr" => {
+                self.close_p_in_button_scope();
+                self.insert_html(&tag);
+                self.open.pop();
+                self.frameset_ok = false;
+            }
+            "image" => {
+                self.error("image-is-img");
+                tag.name = "img".to_string();
+                self.in_body_start_tag(tag);
+            }
+            "textarea" => {
+                self.insert_html(&tag);
+                self.ignore_line_feed = true;
+                self.tokenizer_state = Some(State::Rcdata);
+                self.original_mode = self.mode;
+                self.frameset_ok = false;
+                self.mode = Mode::Text;
+            }
+            "xmp" => {
+                self.close_p_in_button_scope();
+                self.reconstruct_formatting();
+                self.frameset_ok = false;
+                self.generic_text_element(&tag, State::Rawtext);
+            }
+            "iframe" => {
+                self.frameset_ok = false;
+                self.generic_text_element(&tag, State::Rawtext);
+            }
+            "noembed" => self.generic_text_element(&tag, State::Rawtext),
+            "select" => {
+                self.reconstruct_formatting();
+                self.insert_html(&tag);
+                self.frameset_ok = false;
+                self.mode = match self.mode {
+                    Mode::InTable
+                    | Mode::InCaption
+                    | Mode::InTableBody
+                    | Mode::InRow
+                    | Mode::InCell => Mode::InSelectInTable,
+                    _ => Mode::InSelect,
+                };
+            }
+            "optgroup" | "option" => {
+                if self.is(self.current(), "option") {
+                    self.open.pop();
+                }
+                self.reconstruct_formatting();
+                self.insert_html(&tag);
+            }
+            "rb" | "rtc" | "rp" | "rt" => {
+                if self.in_scope(&["ruby"], Scope::Default) {
+                    let except = match tag.name.as_str() {
+                        "rp" | "rt" => Some("rtc"),
+                        _ => None,
+                    };
+                    self.generate_implied_end_tags(except);
+                }
+                self.insert_html(&tag);
+            }
+            "math" | "svg" => {
+                self.reconstruct_formatting();
+                let namespace = match tag.name.as_str() {
+                    "math" => Namespace::MathMl,
+                    _ => Namespace::Svg,
+                };
+                adjust_attributes(&mut tag, namespace);
+                self.insert_element(&tag, namespace);
+                if tag.self_closing {
+                    self.open.pop();
+                }
+            }
+            "caption" | "col" | "colgroup" | "frame" | "head" | "tbody" | "td" | "tfoot" | "th"
+            | "thead" | "tr" => self.error("unexpected-start-tag"),
+            _ => {
+                self.reconstruct_formatting();
+                self.insert_html(&tag);
+            }
+        }
+    }
+
+    fn in_body_end_tag(&mut self, tag: Tag) {
+        match tag.name.as_str() {
+            "template" => self.in_head(Token::EndTag(tag)),
+            "body" | "html" => {
+                if !self.in_scope(&["body"], Scope::Default) {
+                    self.error("body-end-tag-without-body");
+                    return;
+                }
+                if self.unclosed_elements() {
+                    self.error("body-end-tag-with-open-elements");
+                }
+                self.mode = Mode::AfterBody;
+                if tag.name == "html" {
+                    self.reprocess(Mode::AfterBody, Token::EndTag(tag));
+                }
+            }
+            "address" | "article" | "aside" | "blockquote" | "button" | "center" | "details"
+            | "dialog" | "dir" | "div" | "dl" | "fieldset" | "figcaption" | "figure" | "footer"
+            | "header" | "hgroup" | "listing" | "main" | "menu" | "nav" | "ol" | "pre"
+            | "search" | "section" | "summary" | "ul" => {
+                if !self.in_scope(&[&tag.name], Scope::Default) {
+                    self.error("end-tag-without-start-tag");
+                    return;
+                }
+                self.generate_implied_end_tags(None);
+                if !self.is(self.current(), &tag.name) {
+                    self.error("end-tag-with-open-elements");
+                }
+                self.pop_until(&[&tag.name]);
+            }
+            "form" => {
+                if self.has_open("template") {
+                    if !self.in_scope(&["form"], Scope::Default) {
+                        self.error("form-end-tag-without-form");
+                        return;
+                    }
+                    self.generate_implied_end_tags(None);
+                    self.pop_until(&["form"]);
+                    return;
+                }
+
+                let form = self.form.take();
+                let Some(form) = form.filter(|form| self.element_in_scope(*form)) else {
+                    self.error("form-end-tag-without-form");
+                    return;
+                };
+                self.generate_implied_end_tags(None);
+                if self.current() != form {
+                    self.error("form-end-tag-with-open-elements");
+                }
+                self.open.retain(|id| *id != form);
+            }
+            "p" => {
+                if !self.in_scope(&["p"], Scope::Button) {
+                    self.error("p-end-tag-without-p");
+                    self.insert_html(&synthetic("p"));
+                }
+                self.close_p();
+            }
+            "li" | "dd" | "dt" => {
+                let scope = match tag.name.as_str() {
+                    "li" => Scope::ListItem,
+                    _ => Scope::Default,
+                };
+                if !self.in_scope(&[&tag.name], scope) {
+                    self.error("end-tag-without-start-tag");
+                    return;
+                }
+                self.generate_implied_end_tags(Some(&tag.name));
+                if !self.is(self.current(), &tag.name) {
+                    self.error("end-tag-with-open-elements");
+                }
+                self.pop_until(&[&tag.name]);
+            }
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                if !self.in_scope(HEADINGS, Scope::Default) {
+                    self.error("heading-end-tag-without-heading");
+                    return;
+                }
+                self.generate_implied_end_tags(None);
+                if !self.is(self.current(), &tag.name) {
+                    self.error("end-tag-with-open-elements");
+                }
+                self.pop_until(HEADINGS);
+            }
+            name if FORMATTING.contains(&name) => self.adoption_agency(&tag),
+            "applet" | "marquee" | "object" => {
+                if !self.in_scope(&[&tag.name], Scope::Default) {
+                    self.error("end-tag-without-start-tag");
+                    return;
+                }
+                self.generate_implied_end_tags(None);
+                if !self.is(self.current(), &tag.name) {
+                    self.error("end-tag-with-open-elements");
+                }
+                self.pop_until(&[&tag.name]);
+                self.clear_formatting_to_marker();
+            }
+            "br" => {
+                self.error("br-end-tag");
+                self.in_body_start_tag(synthetic("br"));
+            }
+            _ => self.any_other_end_tag(&tag),
+        }
+    }
+
+    fn any_other_end_tag(&mut self, tag: &Tag) {
+        for index in (0..self.open.len()).rev() {
+            let node = self.open[index];
+            if self.is(node, &tag.name) {
+                self.generate_implied_end_tags(Some(&tag.name));
+                if self.current() != node {
+                    self.error("end-tag-with-open-elements");
+                }
+                self.open.truncate(index);
+                return;
+            }
+            if self.is_special(node) {
+                self.error("end-tag-without-start-tag");
+                return;
+            }
+        }
+    }
+
+    fn text(&mut self, token: Token) {
+        match token {
+            Token::Character(c) => self.insert_character(c),
+            Token::Eof => {
+                self.error("end-of-file-in-text");
+                self.open.pop();
+                self.reprocess(self.original_mode, token);
+            }
+            _ => {
+                self.open.pop();
+                self.mode = self.original_mode;
+            }
+        }
+    }
+
+    fn in_table(&mut self, token: Token) {
+        match token {
+            Token::Character(_)
+                if self.is_one_of(
+                    self.current(),
+                    &["table", "tbody", "template", "tfoot", "thead", "tr"],
+                ) =>
+            {
+                self.pending_table_text.clear();
+                self.original_mode = self.mode;
+                self.reprocess(Mode::InTableText, token);
+            }
+            Token::Comment(comment) => self.insert_comment(comment, None),
+            Token::Doctype(_) => self.error("unexpected-doctype"),
+            Token::StartTag(tag) => match tag.name.as_str() {
+                "caption" => {
+                    self.clear_to_context(&["table", "template", "html"]);
+                    self.formatting.push(Formatting::Marker);
+                    self.insert_html(&tag);
+                    self.mode = Mode::InCaption;
+                }
+                "colgroup" => {
+                    self.clear_to_context(&["table", "template", "html"]);
+                    self.insert_html(&tag);
+                    self.mode = Mode::InColumnGroup;
+                }
+                "col" => {
+                    self.clear_to_context(&["table", "template", "html"]);
+                    self.insert_html(&synthetic("colgroup"));
+                    self.reprocess(Mode::InColumnGroup, Token::StartTag(tag));
+                }
+                "tbody" | "tfoot" | "thead" => {
+                    self.clear_to_context(&["table", "template", "html"]);
+                    self.insert_html(&tag);
+                    self.mode = Mode::InTableBody;
+                }
+                "td" | "th" | "tr" => {
+                    self.clear_to_context(&["table", "template", "html"]);
+                    self.insert_html(&synthetic("tbody"));
+                    self.reprocess(Mode::InTableBody, Token::StartTag(tag));
+                }
+                "table" => {
+                    self.error("nested-table");
+                    if self.in_scope(&["table"], Scope::Table) {
+                        self.pop_until(&["table"]);
+                        self.reset_mode();
+                        self.dispatch(Token::StartTag(tag));
+                    }
+                }
+                "style" | "script" | "template" => self.in_head(Token::StartTag(tag)),
+                "input"
+                    if tag
+                        .attribute("type")
+                        .is_some_and(|kind| kind.eq_ignore_ascii_case("hidden")) =>
+                {
+                    self.error("input-in-table");
+                    self.insert_html(&tag);
+                    self.open.pop();
+                }
+                "form" => {
+                    self.error("form-in-table");
+                    if self.has_open("template") || self.form.is_some() {
+                        return;
+                    }
+                    self.form = Some(self.insert_html(&tag));
+                    self.open.pop();
+                }
+                _ => self.foster_parent(Token::StartTag(tag)),
+            },
+            Token::EndTag(tag) => match tag.name.as_str() {
+                "table" => {
+                    if !self.in_scope(&["table"], Scope::Table) {
+                        self.error("table-end-tag-without-table");
+                        return;
+                    }
+                    self.pop_until(&["table"]);
+                    self.reset_mode();
+                }
+                "body" | "caption" | "col" | "colgroup" | "html" | "tbody" | "td" | "tfoot"
+                | "th" | "thead" | "tr" => self.error("unexpected-end-tag"),
+                "template" => self.in_head(Token::EndTag(tag)),
+                _ => self.foster_parent(Token::EndTag(tag)),
+            },
+            Token::Eof => self.in_body(token),
+            token => self.foster_parent(token),
+        }
+    }
+
+    /// Content where it does not belong in a table is put in front of it.
+    fn foster_parent(&mut self, token: Token) {
+        self.error("content-in-table");
+        self.foster_parenting = true;
+        self.in_body(token);
+        self.foster_parenting = false;
+    }
+
+    fn in_table_text(&mut self, token: Token) {
+        match token {
+            Token::Character('\0') => self.error("unexpected-null-character"),
+            Token::Character(c) => self.pending_table_text.push(c),
+            token => {
+                let pending = std::mem::take(&mut self.pending_table_text);
+                if pending.iter().any(|c| !is_whitespace(*c)) {
+                    // one error for the text, not one for every character
+                    self.error("content-in-table");
+                    self.foster_parenting = true;
+                    for c in pending {
+                        self.in_body(Token::Character(c));
+                    }
+                    self.foster_parenting = false;
+                } else {
+                    for c in pending {
+                        self.insert_character(c);
+                    }
+                }
+                self.reprocess(self.original_mode, token);
+            }
+        }
+    }
+
+    fn in_caption(&mut self, token: Token) {
+        match token {
+            Token::EndTag(ref tag) if tag.name == "caption" => {
+                self.close_caption();
+            }
+            Token::StartTag(ref tag)
+                if matches!(
+                    tag.name.as_str(),
+                    "caption"
+                        | "col"
+                        | "colgroup"
+                        | "tbody"
+                        | "td"
+                        | "tfoot"
+                        | "th"
+                        | "thead"
+                        | "tr"
+                ) =>
+            {
+                if self.close_caption() {
+                    self.dispatch(token);
+                }
+            }
+            Token::EndTag(ref tag) if tag.name == "table" => {
+                if self.close_caption() {
+                    self.dispatch(token);
+                }
+            }
+            Token::EndTag(ref tag)
+                if matches!(
+                    tag.name.as_str(),
+                    "body"
+                        | "col"
+                        | "colgroup"
+                        | "html"
+                        | "tbody"
+                        | "td"
+                        | "tfoot"
+                        | "th"
+                        | "thead"
+                        | "tr"
+                ) =>
+            {
+                self.error("unexpected-end-tag")
+            }
+            token => self.in_body(token),
+        }
+    }
+
+    /// Whether there was a caption to close.
+    fn close_caption(&mut self) -> bool {
+        if !self.in_scope(&["caption"], Scope::Table) {
+            self.error("caption-end-tag-without-caption");
+            return false;
+        }
+        self.generate_implied_end_tags(None);
+        if !self.is(self.current(), "caption") {
+            self.error("caption-end-tag-with-open-elements");
+        }
+        self.pop_until(&["caption"]);
+        self.clear_formatting_to_marker();
+        self.mode = Mode::InTable;
+        true
+    }
+
+    fn in_column_group(&mut self, token: Token) {
+        match token {
+            Token::Character(c) if is_whitespace(c) => self.insert_character(c),
+            Token::Comment(comment) => self.insert_comment(comment, None),
+            Token::Doctype(_) => self.error("unexpected-doctype"),
+            Token::StartTag(ref tag) if tag.name == "html" => self.in_body(token),
+            Token::StartTag(tag) if tag.name == "col" => {
+                self.insert_html(&tag);
+                self.open.pop();
+            }
+            Token::EndTag(tag) if tag.name == "colgroup" => {
+                if !self.is(self.current(), "colgroup") {
+                    self.error("colgroup-end-tag-without-colgroup");
+                    return;
+                }
+                self.open.pop();
+                self.mode = Mode::InTable;
+            }
+            Token::EndTag(tag) if tag.name == "col" => self.error("col-end-tag"),
+            Token::StartTag(ref tag) if tag.name == "template" => self.in_head(token),
+            Token::EndTag(ref tag) if tag.name == "template" => self.in_head(token),
+            Token::Eof => self.in_body(token),
+            token => {
+                if !self.is(self.current(), "colgroup") {
+                    self.error("content-in-colgroup");
+                    return;
+                }
+                self.open.pop();
+                self.reprocess(Mode::InTable, token);
+            }
+        }
+    }
+
+    fn in_table_body(&mut self, token: Token) {
+        const CONTEXT: &[&str] = &["tbody", "tfoot", "thead", "template", "html"];
+        match token {
+            Token::StartTag(tag) if tag.name == "tr" => {
+                self.clear_to_context(CONTEXT);
+                self.insert_html(&tag);
+                self.mode = Mode::InRow;
+            }
+            Token::StartTag(tag) if matches!(tag.name.as_str(), "th" | "td") => {
+                self.error("cell-outside-of-row");
+                self.clear_to_context(CONTEXT);
+                self.insert_html(&synthetic("tr"));
+                self.reprocess(Mode::InRow, Token::StartTag(tag));
+            }
+            Token::EndTag(tag) if matches!(tag.name.as_str(), "tbody" | "tfoot" | "thead") => {
+                if !self.in_scope(&[&tag.name], Scope::Table) {
+                    self.error("end-tag-without-start-tag");
+                    return;
+                }
+                self.clear_to_context(CONTEXT);
+                self.open.pop();
+                self.mode = Mode::InTable;
+            }
+            Token::StartTag(ref tag)
+                if matches!(
+                    tag.name.as_str(),
+                    "caption" | "col" | "colgroup" | "tbody" | "tfoot" | "thead"
+                ) =>
+            {
+                self.close_table_body(token)
+            }
+            Token::EndTag(ref tag) if tag.name == "table" => self.close_table_body(token),
+            Token::EndTag(ref tag)
+                if matches!(
+                    tag.name.as_str(),
+                    "body" | "caption" | "col" | "colgroup" | "html" | "td" | "th" | "tr"
+                ) =>
+            {
+                self.error("unexpected-end-tag")
+            }
+            token => self.in_table(token),
+        }
+    }
+
+    fn close_table_body(&mut self, token: Token) {
+        if !self.in_scope(&["tbody", "thead", "tfoot"], Scope::Table) {
+            self.error("no-table-body-to-close");
+            return;
+        }
+        self.clear_to_context(&["tbody", "tfoot", "thead", "template", "html"]);
+        self.open.pop();
+        self.reprocess(Mode::InTable, token);
+    }
+
+    fn in_row(&mut self, token: Token) {
+        match token {
+            Token::StartTag(tag) if matches!(tag.name.as_str(), "th" | "td") => {
+                self.clear_to_context(&["tr", "template", "html"]);
+                self.insert_html(&tag);
+                self.mode = Mode::InCell;
+                self.formatting.push(Formatting::Marker);
+            }
+            Token::EndTag(ref tag) if tag.name == "tr" => {
+                self.close_row();
+            }
+            Token::StartTag(ref tag)
+                if matches!(
+                    tag.name.as_str(),
+                    "caption" | "col" | "colgroup" | "tbody" | "tfoot" | "thead" | "tr"
+                ) =>
+            {
+                if self.close_row() {
+                    self.dispatch(token);
+                }
+            }
+            Token::EndTag(ref tag) if tag.name == "table" => {
+                if self.close_row() {
+                    self.dispatch(token);
+                }
+            }
+            Token::EndTag(ref tag) if matches!(tag.name.as_str(), "tbody" | "tfoot" | "thead") => {
+                if !self.in_scope(&[&tag.name], Scope::Table) {
+                    self.error("end-tag-without-start-tag");
+                    return;
+                }
+                if self.close_row() {
+                    self.dispatch(token);
+                }
+            }
+            Token::EndTag(ref tag)
+                if matches!(
+                    tag.name.as_str(),
+                    "body" | "caption" | "col" | "colgroup" | "html" | "td" | "th"
+                ) =>
+            {
+                self.error("unexpected-end-tag")
+            }
+            token => self.in_table(token),
+        }
+    }
+
+    /// Whether there was a row to close.
+    fn close_row(&mut self) -> bool {
+        if !self.in_scope(&["tr"], Scope::Table) {
+            self.error("no-row-to-close");
+            return false;
+        }
+        self.clear_to_context(&["tr", "template", "html"]);
+        self.open.pop();
+        self.mode = Mode::InTableBody;
+        true
+    }
+
+    fn in_cell(&mut self, token: Token) {
+        match token {
+            Token::EndTag(tag) if matches!(tag.name.as_str(), "td" | "th") => {
+                if !self.in_scope(&[&tag.name], Scope::Table) {
+                    self.error("end-tag-without-start-tag");
+                    return;
+                }
+                self.generate_implied_end_tags(None);
+                if !self.is(self.current(), &tag.name) {
+                    self.error("cell-end-tag-with-open-elements");
+                }
+                self.pop_until(&[&tag.name]);
+                self.clear_formatting_to_marker();
+                self.mode = Mode::InRow;
+            }
+            Token::StartTag(ref tag)
+                if matches!(
+                    tag.name.as_str(),
+                    "caption"
+                        | "col"
+                        | "colgroup"
+                        | "tbody"
+                        | "td"
+                        | "tfoot"
+                        | "th"
+                        | "thead"
+                        | "tr"
+                ) =>
+            {
+                if !self.in_scope(&["td", "th"], Scope::Table) {
+                    self.error("no-cell-to-close");
+                    return;
+                }
+                self.close_cell();
+                self.dispatch(token);
+            }
+            Token::EndTag(ref tag)
+                if matches!(
+                    tag.name.as_str(),
+                    "body" | "caption" | "col" | "colgroup" | "html"
+                ) =>
+            {
+                self.error("unexpected-end-tag")
+            }
+            Token::EndTag(ref tag)
+                if matches!(
+                    tag.name.as_str(),
+                    "table" | "tbody" | "tfoot" | "thead" | "tr"
+                ) =>
+            {
+                if !self.in_scope(&[&tag.name], Scope::Table) {
+                    self.error("end-tag-without-start-tag");
+                    return;
+                }
+                self.close_cell();
+                self.dispatch(token);
+            }
+            token => self.in_body(token),
+        }
+    }
+
+    fn close_cell(&mut self) {
+        self.generate_implied_end_tags(None);
+        if !self.is_one_of(self.current(), &["td", "th"]) {
+            self.error("cell-closed-with-open-elements");
+        }
+        self.pop_until(&["td", "th"]);
+        self.clear_formatting_to_marker();
+        self.mode = Mode::InRow;
+    }
+
+    fn in_select(&mut self, token: Token) {
+        match token {
+            Token::Character('\0') => self.error("unexpected-null-character"),
+            Token::Character(c) => self.insert_character(c),
+            Token::Comment(comment) => self.insert_comment(comment, None),
+            Token::Doctype(_) => self.error("unexpected-doctype"),
+            Token::StartTag(tag) => match tag.name.as_str() {
+                "html" => self.in_body(Token::StartTag(tag)),
+                "option" => {
+                    if self.is(self.current(), "option") {
+                        self.open.pop();
+                    }
+                    self.insert_html(&tag);
+                }
+                "optgroup" | "hr" => {
+                    if self.is(self.current(), "option") {
+                        self.open.pop();
+                    }
+                    if self.is(self.current(), "optgroup") {
+                        self.open.pop();
+                    }
+                    self.insert_html(&tag);
+                    if tag.name == "hr" {
+                        self.open.pop();
+                    }
+                }
+                "select" => {
+                    self.error("nested-select");
+                    if self.in_scope(&["select"], Scope::Select) {
+                        self.pop_until(&["select"]);
+                        self.reset_mode();
+                    }
+                }
+                "input" | "keygen" | "textarea" => {
+                    self.error("input-in-select");
+                    if self.in_scope(&["select"], Scope::Select) {
+                        self.pop_until(&["select"]);
+                        self.reset_mode();
+                        self.dispatch(Token::StartTag(tag));
+                    }
+                }
+                "script" | "template" => self.in_head(Token::StartTag(tag)),
+                _ => self.error("unexpected-start-tag-in-select"),
+            },
+            Token::EndTag(tag) => match tag.name.as_str() {
+                "optgroup" => {
+                    if self.is(self.current(), "option")
+                        && self.open.len() > 1
+                        && self.is(self.open[self.open.len() - 2], "optgroup")
+                    {
+                        self.open.pop();
+                    }
+                    if self.is(self.current(), "optgroup") {
+                        self.open.pop();
+                    } else {
+                        self.error("optgroup-end-tag-without-optgroup");
+                    }
+                }
+                "option" => {
+                    if self.is(self.current(), "option") {
+                        self.open.pop();
+                    } else {
+                        self.error("option-end-tag-without-option");
+                    }
+                }
+                "select" => {
+                    if !self.in_scope(&["select"], Scope::Select) {
+                        self.error("select-end-tag-without-select");
+                        return;
+                    }
+                    self.pop_until(&["select"]);
+                    self.reset_mode();
+                }
+                "template" => self.in_head(Token::EndTag(tag)),
+                _ => self.error("unexpected-end-tag-in-select"),
+            },
+            Token::Eof => self.in_body(token),
+        }
+    }
+
+    fn in_select_in_table(&mut self, token: Token) {
+        const TABLE: &[&str] = &[
+            "caption", "table", "tbody", "tfoot", "thead", "tr", "td", "th",
+        ];
+        match token {
+            Token::StartTag(ref tag) if TABLE.contains(&tag.name.as_str()) => {
+                self.error("table-in-select");
+                self.pop_until(&["select"]);
+                self.reset_mode();
+                self.dispatch(token);
+            }
+            Token::EndTag(ref tag) if TABLE.contains(&tag.name.as_str()) => {
+                self.error("table-end-tag-in-select");
+                if !self.in_scope(&[&tag.name], Scope::Table) {
+                    return;
+                }
+                self.pop_until(&["select"]);
+                self.reset_mode();
+                self.dispatch(token);
+            }
+            token => self.in_select(token),
+        }
+    }
+
+    fn in_template(&mut self, token: Token) {
+        match token {
+            Token::Character(_) | Token::Comment(_) | Token::Doctype(_) => self.in_body(token),
+            Token::StartTag(ref tag) => {
+                let mode = match tag.name.as_str() {
+                    "base" | "basefont" | "bgsound" | "link" | "meta" | "noframes" | "script"
+                    | "style" | "template" | "title" => return self.in_head(token),
+                    "caption" | "colgroup" | "tbody" | "tfoot" | "thead" => Mode::InTable,
+                    "col" => Mode::InColumnGroup,
+                    "tr" => Mode::InTableBody,
+                    "td" | "th" => Mode::InRow,
+                    _ => Mode::InBody,
+                };
+                self.template_modes.pop();
+                self.template_modes.push(mode);
+                self.reprocess(mode, token);
+            }
+            Token::EndTag(ref tag) if tag.name == "template" => self.in_head(token),
+            Token::EndTag(_) => self.error("unexpected-end-tag-in-template"),
+            Token::Eof => {
+                if !self.has_open("template") {
+                    return;
+                }
+                self.error("end-of-file-in-template");
+                self.pop_until(&["template"]);
+                self.clear_formatting_to_marker();
+                self.template_modes.pop();
+                self.reset_mode();
+                self.dispatch(token);
+            }
+        }
+    }
+
+    fn after_body(&mut self, token: Token) {
+        match token {
+            Token::Character(c) if is_whitespace(c) => self.in_body(token),
+            Token::Comment(comment) => self.insert_comment(comment, Some(self.open[0])),
+            Token::Doctype(_) => self.error("unexpected-doctype"),
+            Token::StartTag(ref tag) if tag.name == "html" => self.in_body(token),
+            Token::EndTag(ref tag) if tag.name == "html" => self.mode = Mode::AfterAfterBody,
+            Token::Eof => {}
+            token => {
+                self.error("content-after-body");
+                self.reprocess(Mode::InBody, token);
+            }
+        }
+    }
+
+    fn in_frameset(&mut self, token: Token) {
+        match token {
+            Token::Character(c) if is_whitespace(c) => self.insert_character(c),
+            Token::Comment(comment) => self.insert_comment(comment, None),
+            Token::Doctype(_) => self.error("unexpected-doctype"),
+            Token::StartTag(ref tag) if tag.name == "html" => self.in_body(token),
+            Token::StartTag(tag) if tag.name == "frameset" => {
+                self.insert_html(&tag);
+            }
+            Token::EndTag(tag) if tag.name == "frameset" => {
+                if self.open.len() == 1 {
+                    self.error("frameset-end-tag-without-frameset");
+                    return;
+                }
+                self.open.pop();
+                if !self.is(self.current(), "frameset") {
+                    self.mode = Mode::AfterFrameset;
+                }
+            }
+            Token::StartTag(tag) if tag.name == "frame" => {
+                self.insert_html(&tag);
+                self.open.pop();
+            }
+            Token::StartTag(ref tag) if tag.name == "noframes" => self.in_head(token),
+            Token::Eof => {
+                if self.open.len() > 1 {
+                    self.error("end-of-file-in-frameset");
+                }
+            }
+            _ => self.error("unexpected-token-in-frameset"),
+        }
+    }
+
+    fn after_frameset(&mut self, token: Token) {
+        match token {
+            Token::Character(c) if is_whitespace(c) => self.insert_character(c),
+            Token::Comment(comment) => self.insert_comment(comment, None),
+            Token::Doctype(_) => self.error("unexpected-doctype"),
+            Token::StartTag(ref tag) if tag.name == "html" => self.in_body(token),
+            Token::EndTag(ref tag) if tag.name == "html" => self.mode = Mode::AfterAfterFrameset,
+            Token::StartTag(ref tag) if tag.name == "noframes" => self.in_head(token),
+            Token::Eof => {}
+            _ => self.error("unexpected-token-after-frameset"),
+        }
+    }
+
+    fn after_after_body(&mut self, token: Token) {
+        match token {
+            Token::Comment(comment) => self.insert_comment(comment, Some(self.document.root())),
+            Token::Doctype(_) => self.in_body(token),
+            Token::Character(c) if is_whitespace(c) => self.in_body(token),
+            Token::StartTag(ref tag) if tag.name == "html" => self.in_body(token),
+            Token::Eof => {}
+            token => {
+                self.error("content-after-html");
+                self.reprocess(Mode::InBody, token);
+            }
+        }
+    }
+
+    fn after_after_frameset(&mut self, token: Token) {
+        match token {
+            Token::Comment(comment) => self.insert_comment(comment, Some(self.document.root())),
+            Token::Doctype(_) => self.in_body(token),
+            Token::Character(c) if is_whitespace(c) => self.in_body(token),
+            Token::StartTag(ref tag) if tag.name == "html" => self.in_body(token),
+            Token::StartTag(ref tag) if tag.name == "noframes" => self.in_head(token),
+            Token::Eof => {}
+            _ => self.error("content-after-frameset"),
+        }
+    }
+
+    /// Whether `token` follows the rules for SVG and MathML content instead of
+    /// the insertion mode.
+    fn is_foreign(&self, token: &Token) -> bool {
+        let Some(&node) = self.open.last() else {
+            return false;
+        };
+        let Some(element) = self.document.element(node) else {
+            return false;
+        };
+        if element.namespace == Namespace::Html {
+            return false;
+        }
+
+        let mathml_text = self.is_mathml_text_integration_point(node);
+        match token {
+            Token::StartTag(tag)
+                if mathml_text && tag.name != "mglyph" && tag.name != "malignmark" =>
+            {
+                false
+            }
+            Token::Character(_) if mathml_text => false,
+            Token::StartTag(tag)
+                if tag.name == "svg"
+                    && element.namespace == Namespace::MathMl
+                    && element.name == "annotation-xml" =>
+            {
+                false
+            }
+            Token::StartTag(_) | Token::Character(_) if self.is_html_integration_point(node) => {
+                false
+            }
+            Token::Eof => false,
+            _ => true,
+        }
+    }
+
+    fn is_mathml_text_integration_point(&self, id: NodeId) -> bool {
+        self.document.element(id).is_some_and(|element| {
+            element.namespace == Namespace::MathMl
+                && matches!(element.name.as_str(), "mi" | "mo" | "mn" | "ms" | "mtext")
+        })
+    }
+
+    fn is_html_integration_point(&self, id: NodeId) -> bool {
+        let Some(element) = self.document.element(id) else {
+            return false;
+        };
+        match element.namespace {
+            Namespace::MathMl => {
+                element.name == "annotation-xml"
+                    && element.attribute("encoding").is_some_and(|encoding| {
+                        encoding.eq_ignore_ascii_case("text/html")
+                            || encoding.eq_ignore_ascii_case("application/xhtml+xml")
+                    })
+            }
+            Namespace::Svg => matches!(element.name.as_str(), "foreignObject" | "desc" | "title"),
+            Namespace::Html => false,
+        }
+    }
+
+    fn foreign_content(&mut self, token: Token) {
+        match token {
+            Token::Character('\0') => {
+                self.error("unexpected-null-character");
+                self.insert_character('\u{FFFD}');
+            }
+            Token::Character(c) => {
+                self.insert_character(c);
+                if !is_whitespace(c) {
+                    self.frameset_ok = false;
+                }
+            }
+            Token::Comment(comment) => self.insert_comment(comment, None),
+            Token::Doctype(_) => self.error("unexpected-doctype"),
+            Token::StartTag(ref tag)
+                if BREAKS_OUT_OF_FOREIGN_CONTENT.contains(&tag.name.as_str())
+                    || (tag.name == "font"
+                        && ["color", "face", "size"]
+                            .iter()
+                            .any(|name| tag.attribute(name).is_some())) =>
+            {
+                self.break_out_of_foreign_content(token)
+            }
+            Token::EndTag(ref tag) if tag.name == "br" || tag.name == "p" => {
+                self.break_out_of_foreign_content(token)
+            }
+            Token::StartTag(mut tag) => {
+                let namespace = self
+                    .document
+                    .element(self.current())
+                    .map(|element| element.namespace)
+                    .unwrap_or_default();
+                if namespace == Namespace::Svg
+                    && let Some((_, name)) =
+                        SVG_TAG_NAMES.iter().find(|(from, _)| *from == tag.name)
+                {
+                    tag.name = name.to_string();
+                }
+                adjust_attributes(&mut tag, namespace);
+                self.insert_element(&tag, namespace);
+                if tag.self_closing {
+                    self.open.pop();
+                }
+            }
+            Token::EndTag(tag) => {
+                let mut index = self.open.len() - 1;
+                if self.lowercase_name(self.open[index]) != tag.name {
+                    self.error("unexpected-end-tag-in-foreign-content");
+                }
+                loop {
+                    if index == 0 {
+                        return;
+                    }
+                    if self.lowercase_name(self.open[index]) == tag.name {
+                        self.open.truncate(index);
+                        return;
+                    }
+                    index -= 1;
+                    if self.html_name(self.open[index]).is_some() {
+                        self.process_in(self.mode, Token::EndTag(tag));
+                        return;
+                    }
+                }
+            }
+            Token::Eof => self.process_in(self.mode, token),
+        }
+    }
+
+    fn break_out_of_foreign_content(&mut self, token: Token) {
+        self.error("html-in-foreign-content");
+        while let Some(&node) = self.open.last()
+            && !self.is_mathml_text_integration_point(node)
+            && !self.is_html_integration_point(node)
+            && self.html_name(node).is_none()
+        {
+            self.open.pop();
+        }
+        self.process_in(self.mode, token);
+    }
+
+    fn lowercase_name(&self, id: NodeId) -> String {
+        self.document
+            .name(id)
+            .unwrap_or_default()
+            .to_ascii_lowercase()
+    }
+
+    fn current(&self) -> NodeId {
+        self.open.last().copied().unwrap_or(self.document.root())
+    }
+
+    /// The name of `id` if it is an HTML element.
+    fn html_name(&self, id: NodeId) -> Option<&str> {
+        self.document
+            .element(id)
+            .filter(|element| element.namespace == Namespace::Html)
+            .map(|element| element.name.as_str())
+    }
+
+    fn is(&self, id: NodeId, name: &str) -> bool {
+        self.html_name(id) == Some(name)
+    }
+
+    fn is_one_of(&self, id: NodeId, names: &[&str]) -> bool {
+        self.html_name(id).is_some_and(|name| names.contains(&name))
+    }
+
+    /// Whether an element is open that needs an end tag, which it should have
+    /// had by the end of the body.
+    fn unclosed_elements(&self) -> bool {
+        const END_TAG_OPTIONAL: &[&str] = &[
+            "body", "dd", "dt", "html", "li", "optgroup", "option", "p", "rb", "rp", "rt", "rtc",
+            "tbody", "td", "tfoot", "th", "thead", "tr",
+        ];
+        self.open
+            .iter()
+            .any(|id| !self.is_one_of(*id, END_TAG_OPTIONAL))
+    }
+
+    fn has_open(&self, name: &str) -> bool {
+        self.open.iter().any(|id| self.is(*id, name))
+    }
+
+    fn is_special(&self, id: NodeId) -> bool {
+        let Some(element) = self.document.element(id) else {
+            return false;
+        };
+        match element.namespace {
+            Namespace::Html => SPECIAL.contains(&element.name.as_str()),
+            Namespace::MathMl => matches!(
+                element.name.as_str(),
+                "mi" | "mo" | "mn" | "ms" | "mtext" | "annotation-xml"
+            ),
+            Namespace::Svg => matches!(element.name.as_str(), "foreignObject" | "desc" | "title"),
+        }
+    }
+
+    fn is_scope_boundary(&self, id: NodeId, scope: Scope) -> bool {
+        let Some(element) = self.document.element(id) else {
+            return true;
+        };
+        let name = element.name.as_str();
+        match (scope, element.namespace) {
+            (Scope::Select, Namespace::Html) => !matches!(name, "optgroup" | "option"),
+            (Scope::Select, _) => true,
+            (Scope::Table, Namespace::Html) => matches!(name, "html" | "table" | "template"),
+            (Scope::Table, _) => false,
+            (_, Namespace::Html) => {
+                matches!(
+                    name,
+                    "applet"
+                        | "caption"
+                        | "html"
+                        | "table"
+                        | "td"
+                        | "th"
+                        | "marquee"
+                        | "object"
+                        | "template"
+                ) || (scope == Scope::ListItem && matches!(name, "ol" | "ul"))
+                    || (scope == Scope::Button && name == "button")
+            }
+            (_, Namespace::MathMl) => {
+                matches!(name, "mi" | "mo" | "mn" | "ms" | "mtext" | "annotation-xml")
+            }
+            (_, Namespace::Svg) => matches!(name, "foreignObject" | "desc" | "title"),
+        }
+    }
+
+    /// Whether an HTML element named one of `names` is open, without a scope
+    /// boundary after it.
+    fn in_scope(&self, names: &[&str], scope: Scope) -> bool {
+        for &node in self.open.iter().rev() {
+            if self.is_one_of(node, names) {
+                return true;
+            }
+            if self.is_scope_boundary(node, scope) {
+                return false;
+            }
+        }
+        false
+    }
+
+    fn element_in_scope(&self, element: NodeId) -> bool {
+        for &node in self.open.iter().rev() {
+            if node == element {
+                return true;
+            }
+            if self.is_scope_boundary(node, Scope::Default) {
+                return false;
+            }
+        }
+        false
+    }
+
+    /// Where a new node goes: at the end of the current node, or in front of
+    /// the table when foster parenting.
+    fn appropriate_place(&self, target: Option<NodeId>) -> (NodeId, usize) {
+        let target = target.unwrap_or(self.current());
+        if !(self.foster_parenting
+            && self.is_one_of(target, &["table", "tbody", "tfoot", "thead", "tr"]))
+        {
+            return (target, self.document.children(target).len());
+        }
+
+        let last_template = self.open.iter().rposition(|id| self.is(*id, "template"));
+        let last_table = self.open.iter().rposition(|id| self.is(*id, "table"));
+        match (last_template, last_table) {
+            (Some(template), table) if table.is_none_or(|table| template > table) => {
+                let template = self.open[template];
+                (template, self.document.children(template).len())
+            }
+            (_, None) => (self.open[0], self.document.children(self.open[0]).len()),
+            (_, Some(table)) => {
+                let table_id = self.open[table];
+                match self.document.parent(table_id) {
+                    Some(parent) => {
+                        let index = self
+                            .document
+                            .children(parent)
+                            .iter()
+                            .position(|child| *child == table_id)
+                            .unwrap_or(0);
+                        (parent, index)
+                    }
+                    None => {
+                        let above = self.open[table - 1];
+                        (above, self.document.children(above).len())
+                    }
+                }
+            }
+        }
+    }
+
+    fn create_element(&mut self, tag: &Tag, namespace: Namespace) -> NodeId {
+        self.document.create(NodeData::Element(Element {
+            name: tag.name.clone(),
+            attributes: tag.attributes.clone(),
+            namespace,
+        }))
+    }
+
+    fn insert_element(&mut self, tag: &Tag, namespace: Namespace) -> NodeId {
+        let (parent, index) = self.appropriate_place(None);
+        let element = self.create_element(tag, namespace);
+        self.document.insert(parent, index, element);
+        self.open.push(element);
+        if namespace != Namespace::Html || VOID.contains(&tag.name.as_str()) {
+            self.self_closing_acknowledged = true;
+        }
+        element
+    }
+
+    fn insert_html(&mut self, tag: &Tag) -> NodeId {
+        self.insert_element(tag, Namespace::Html)
+    }
+
+    fn insert_character(&mut self, c: char) {
+        let (parent, index) = self.appropriate_place(None);
+        if parent == self.document.root() {
+            return;
+        }
+        self.document
+            .insert_text(parent, index, c.encode_utf8(&mut [0; 4]));
+    }
+
+    fn insert_comment(&mut self, comment: String, parent: Option<NodeId>) {
+        let (parent, index) = match parent {
+            Some(parent) => (parent, self.document.children(parent).len()),
+            None => self.appropriate_place(None),
+        };
+        let comment = self.document.create(NodeData::Comment(comment));
+        self.document.insert(parent, index, comment);
+    }
+
+    /// `<title>`, `<style>` and the like, whose content is only text.
+    fn generic_text_element(&mut self, tag: &Tag, state: State) {
+        self.insert_html(tag);
+        self.tokenizer_state = Some(state);
+        self.original_mode = self.mode;
+        self.mode = Mode::Text;
+    }
+
+    fn add_missing_attributes(&mut self, id: NodeId, tag: Tag) {
+        let Some(element) = self.document.element_mut(id) else {
+            return;
+        };
+        for (name, value) in tag.attributes {
+            if element.attribute(&name).is_none() {
+                element.attributes.push((name, value));
+            }
+        }
+    }
+
+    fn pop_until(&mut self, names: &[&str]) {
+        while let Some(node) = self.open.pop() {
+            if self.is_one_of(node, names) {
+                break;
+            }
+        }
+    }
+
+    fn clear_to_context(&mut self, names: &[&str]) {
+        while !self.is_one_of(self.current(), names) {
+            self.open.pop();
+        }
+    }
+
+    fn generate_implied_end_tags(&mut self, except: Option<&str>) {
+        while let Some(name) = self.html_name(self.current())
+            && IMPLIED_END_TAGS.contains(&name)
+            && Some(name) != except
+        {
+            self.open.pop();
+        }
+    }
+
+    fn generate_all_implied_end_tags(&mut self) {
+        const THOROUGHLY: &[&str] = &[
+            "caption", "colgroup", "dd", "dt", "li", "optgroup", "option", "p", "rb", "rp", "rt",
+            "rtc", "tbody", "td", "tfoot", "th", "thead", "tr",
+        ];
+        while self.is_one_of(self.current(), THOROUGHLY) {
+            self.open.pop();
+        }
+    }
+
+    fn close_p(&mut self) {
+        self.generate_implied_end_tags(Some("p"));
+        if !self.is(self.current(), "p") {
+            self.error("p-closed-with-open-elements");
+        }
+        self.pop_until(&["p"]);
+    }
+
+    fn close_p_in_button_scope(&mut self) {
+        if self.in_scope(&["p"], Scope::Button) {
+            self.close_p();
+        }
+    }
+
+    /// The open formatting element named `name` after the last marker.
+    fn formatting_after_marker(&self, name: &str) -> Option<NodeId> {
+        for entry in self.formatting.iter().rev() {
+            match entry {
+                Formatting::Marker => return None,
+                Formatting::Element(id, _) if self.is(*id, name) => return Some(*id),
+                Formatting::Element(..) => {}
+            }
+        }
+        None
+    }
+
+    fn formatting_index(&self, id: NodeId) -> Option<usize> {
+        self.formatting
+            .iter()
+            .position(|entry| matches!(entry, Formatting::Element(element, _) if *element == id))
+    }
+
+    fn remove_formatting(&mut self, id: NodeId) {
+        if let Some(index) = self.formatting_index(id) {
+            self.formatting.remove(index);
+        }
+    }
+
+    /// Adds to the active formatting elements, with at most three of the
+    /// same element with the same attributes after the last marker.
+    fn push_formatting(&mut self, id: NodeId, tag: Tag) {
+        let mut same = Vec::new();
+        for (index, entry) in self.formatting.iter().enumerate().rev() {
+            match entry {
+                Formatting::Marker => break,
+                Formatting::Element(_, other) if other.name == tag.name => {
+                    let mut a = other.attributes.clone();
+                    let mut b = tag.attributes.clone();
+                    a.sort();
+                    b.sort();
+                    if a == b {
+                        same.push(index);
+                    }
+                }
+                Formatting::Element(..) => {}
+            }
+        }
+        if same.len() >= 3 {
+            self.formatting.remove(*same.last().unwrap());
+        }
+        self.formatting.push(Formatting::Element(id, tag));
+    }
+
+    fn clear_formatting_to_marker(&mut self) {
+        while let Some(entry) = self.formatting.pop() {
+            if let Formatting::Marker = entry {
+                break;
+            }
+        }
+    }
+
+    /// Reopens formatting elements that were closed by something else, so in
+    /// `<b><p>x</b>y` the `y` is bold too.
+    fn reconstruct_formatting(&mut self) {
+        let is_open = |builder: &TreeBuilder, index: usize| match &builder.formatting[index] {
+            Formatting::Marker => true,
+            Formatting::Element(id, _) => builder.open.contains(id),
+        };
+        let Some(last) = self.formatting.len().checked_sub(1) else {
+            return;
+        };
+        if is_open(self, last) {
+            return;
+        }
+
+        let mut index = last;
+        while index > 0 && !is_open(self, index - 1) {
+            index -= 1;
+        }
+        for index in index..self.formatting.len() {
+            let Formatting::Element(_, tag) = self.formatting[index].clone() else {
+                continue;
+            };
+            let element = self.insert_html(&tag);
+            self.formatting[index] = Formatting::Element(element, tag);
+        }
+    }
+
+    /// What an end tag of a formatting element does when it is closed out of
+    /// order, https://html.spec.whatwg.org/multipage/parsing.html#adoption-agency-algorithm.
+    fn adoption_agency(&mut self, tag: &Tag) {
+        let subject = tag.name.as_str();
+        let current = self.current();
+        if self.is(current, subject) && self.formatting_index(current).is_none() {
+            self.open.pop();
+            return;
+        }
+
+        for _ in 0..8 {
+            let Some(formatting_element) = self.formatting_after_marker(subject) else {
+                return self.any_other_end_tag(tag);
+            };
+            let Some(formatting_open) = self.open.iter().position(|id| *id == formatting_element)
+            else {
+                self.error("formatting-element-is-not-open");
+                self.remove_formatting(formatting_element);
+                return;
+            };
+            if !self.element_in_scope(formatting_element) {
+                self.error("formatting-element-is-not-in-scope");
+                return;
+            }
+            if formatting_element != self.current() {
+                self.error("formatting-element-is-not-the-current-node");
+            }
+
+            let furthest_block = (formatting_open + 1..self.open.len())
+                .find(|index| self.is_special(self.open[*index]));
+            let Some(furthest_block_index) = furthest_block else {
+                self.open.truncate(formatting_open);
+                self.remove_formatting(formatting_element);
+                return;
+            };
+            let furthest_block = self.open[furthest_block_index];
+
+            let common_ancestor = self.open[formatting_open - 1];
+            let mut bookmark = self.formatting_index(formatting_element).unwrap();
+            let mut node_index = furthest_block_index;
+            let mut last_node = furthest_block;
+
+            let mut inner = 0;
+            loop {
+                inner += 1;
+                node_index -= 1;
+                let mut node = self.open[node_index];
+                if node == formatting_element {
+                    break;
+                }
+
+                let mut node_formatting = self.formatting_index(node);
+                if inner > 3
+                    && let Some(index) = node_formatting
+                {
+                    self.formatting.remove(index);
+                    if index < bookmark {
+                        bookmark -= 1;
+                    }
+                    node_formatting = None;
+                }
+                let Some(node_formatting) = node_formatting else {
+                    self.open.remove(node_index);
+                    continue;
+                };
+
+                let Formatting::Element(_, node_tag) = self.formatting[node_formatting].clone()
+                else {
+                    unreachable!();
+                };
+                let replacement = self.create_element(&node_tag, Namespace::Html);
+                self.formatting[node_formatting] = Formatting::Element(replacement, node_tag);
+                self.open[node_index] = replacement;
+                node = replacement;
+
+                if last_node == furthest_block {
+                    bookmark = node_formatting + 1;
+                }
+                self.document.append(node, last_node);
+                last_node = node;
+            }
+
+            let (parent, index) = self.appropriate_place(Some(common_ancestor));
+            self.document.insert(parent, index, last_node);
+
+            let Formatting::Element(_, formatting_tag) =
+                self.formatting[self.formatting_index(formatting_element).unwrap()].clone()
+            else {
+                unreachable!();
+            };
+            let replacement = self.create_element(&formatting_tag, Namespace::Html);
+            for child in self.document.children(furthest_block).to_vec() {
+                self.document.append(replacement, child);
+            }
+            self.document.append(furthest_block, replacement);
+
+            let old = self.formatting_index(formatting_element).unwrap();
+            self.formatting.remove(old);
+            if old < bookmark {
+                bookmark -= 1;
+            }
+            self.formatting.insert(
+                bookmark.min(self.formatting.len()),
+                Formatting::Element(replacement, formatting_tag),
+            );
+
+            self.open.retain(|id| *id != formatting_element);
+            let furthest_block_index = self
+                .open
+                .iter()
+                .position(|id| *id == furthest_block)
+                .unwrap();
+            self.open.insert(furthest_block_index + 1, replacement);
+        }
+    }
+
+    fn reset_mode(&mut self) {
+        for (index, &node) in self.open.iter().enumerate().rev() {
+            let last = index == 0;
+            self.mode = match self.html_name(node) {
+                Some("select") => {
+                    let mut mode = Mode::InSelect;
+                    for &ancestor in self.open[..index].iter().rev() {
+                        if self.is(ancestor, "template") {
+                            break;
+                        }
+                        if self.is(ancestor, "table") {
+                            mode = Mode::InSelectInTable;
+                            break;
+                        }
+                    }
+                    mode
+                }
+                Some("td" | "th") if !last => Mode::InCell,
+                Some("tr") => Mode::InRow,
+                Some("tbody" | "thead" | "tfoot") => Mode::InTableBody,
+                Some("caption") => Mode::InCaption,
+                Some("colgroup") => Mode::InColumnGroup,
+                Some("table") => Mode::InTable,
+                Some("template") => *self.template_modes.last().unwrap_or(&Mode::InBody),
+                Some("head") if !last => Mode::InHead,
+                Some("body") => Mode::InBody,
+                Some("frameset") => Mode::InFrameset,
+                Some("html") => match self.head {
+                    None => Mode::BeforeHead,
+                    Some(_) => Mode::AfterHead,
+                },
+                _ if last => Mode::InBody,
+                _ => continue,
+            };
+            return;
+        }
+    }
+}
+
+fn synthetic(name: &str) -> Tag {
+    Tag {
+        name: name.to_string(),
+        ..Tag::default()
+    }
+}
+
+/// SVG and MathML attributes the tokenizer lowercased that are not lowercase.
+fn adjust_attributes(tag: &mut Tag, namespace: Namespace) {
+    for (name, _) in &mut tag.attributes {
+        match namespace {
+            Namespace::MathMl if name == "definitionurl" => *name = "definitionURL".to_string(),
+            Namespace::Svg => {
+                if let Some((_, adjusted)) = SVG_ATTRIBUTES.iter().find(|(from, _)| from == name) {
+                    *name = adjusted.to_string();
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn quirks_mode(doctype: &Doctype) -> QuirksMode {
+    let public_id = doctype
+        .public_id
+        .as_deref()
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    let system_id = doctype
+        .system_id
+        .as_deref()
+        .map(|id| id.to_ascii_lowercase());
+    let frameset_or_transitional =
+        |prefixes: [&str; 2]| prefixes.iter().any(|prefix| public_id.starts_with(prefix));
+
+    if doctype.force_quirks
+        || doctype.name.as_deref() != Some("html")
+        || matches!(
+            public_id.as_str(),
+            "-//w3o//dtd w3 html strict 3.0//en//" | "-/w3c/dtd html 4.0 transitional/en" | "html"
+        )
+        || system_id.as_deref()
+            == Some("http://www.ibm.com/data/dtd/v11/ibmxhtml1-transitional.dtd")
+        || QUIRKS_PUBLIC_PREFIXES
+            .iter()
+            .any(|prefix| public_id.starts_with(prefix))
+        || (system_id.is_none()
+            && frameset_or_transitional([
+                "-//w3c//dtd html 4.01 frameset//",
+                "-//w3c//dtd html 4.01 transitional//",
+            ]))
+    {
+        QuirksMode::Quirks
+    } else if frameset_or_transitional([
+        "-//w3c//dtd xhtml 1.0 frameset//",
+        "-//w3c//dtd xhtml 1.0 transitional//",
+    ]) || (system_id.is_some()
+        && frameset_or_transitional([
+            "-//w3c//dtd html 4.01 frameset//",
+            "-//w3c//dtd html 4.01 transitional//",
+        ]))
+    {
+        QuirksMode::LimitedQuirks
+    } else {
+        QuirksMode::NoQuirks
+    }
+}
+
+#[cfg(test)]
+fn tree(html: &str) -> String {
+    super::parse(html).dump()
+}
+
+#[test]
+fn implicit_html_head_and_body() {
+    assert_eq!(tree(""), "| <html>\n|   <head>\n|   <body>\n");
+    assert_eq!(
+        tree("<!doctype html>\n<title>Hi</title>\n<p>Hello"),
+        "| <!DOCTYPE html>\n\
+         | <html>\n\
+         |   <head>\n\
+         |     <title>\n\
+         |       \"Hi\"\n\
+         |     \"\n\"\n\
+         |   <body>\n\
+         |     <p>\n\
+         |       \"Hello\"\n"
+    );
+    assert_eq!(
+        tree("<html lang=en><head><meta charset=utf-8></head><body>a</body></html>b"),
+        "| <html>\n\
+         |   lang=\"en\"\n\
+         |   <head>\n\
+         |     <meta>\n\
+         |       charset=\"utf-8\"\n\
+         |   <body>\n\
+         |     \"ab\"\n"
+    );
+}
+
+#[test]
+fn void_and_auto_closed_elements() {
+    assert_eq!(
+        tree("<p>one<br>two<p>three<div>four</div><ul><li>a<li>b<ul><li>c</ul></ul>"),
+        "| <html>\n\
+         |   <head>\n\
+         |   <body>\n\
+         |     <p>\n\
+         |       \"one\"\n\
+         |       <br>\n\
+         |       \"two\"\n\
+         |     <p>\n\
+         |       \"three\"\n\
+         |     <div>\n\
+         |       \"four\"\n\
+         |     <ul>\n\
+         |       <li>\n\
+         |         \"a\"\n\
+         |       <li>\n\
+         |         \"b\"\n\
+         |         <ul>\n\
+         |           <li>\n\
+         |             \"c\"\n"
+    );
+    assert_eq!(
+        tree("<b>x</i></p><img/>y"),
+        "| <html>\n\
+         |   <head>\n\
+         |   <body>\n\
+         |     <b>\n\
+         |       \"x\"\n\
+         |       <p>\n\
+         |       <img>\n\
+         |       \"y\"\n"
+    );
+}
+
+#[test]
+fn misnested_formatting_and_tables() {
+    assert_eq!(
+        tree("<a>1<p>2</a>3</p>"),
+        "| <html>\n\
+         |   <head>\n\
+         |   <body>\n\
+         |     <a>\n\
+         |       \"1\"\n\
+         |     <p>\n\
+         |       <a>\n\
+         |         \"2\"\n\
+         |       \"3\"\n"
+    );
+    assert_eq!(
+        tree("<b><i>1</b>2</i>"),
+        "| <html>\n\
+         |   <head>\n\
+         |   <body>\n\
+         |     <b>\n\
+         |       <i>\n\
+         |         \"1\"\n\
+         |     <i>\n\
+         |       \"2\"\n"
+    );
+    // text that is not in a cell goes in front of the table
+    assert_eq!(
+        tree("<table><tr><td>a</td>x</tr></table>"),
+        "| <html>\n\
+         |   <head>\n\
+         |   <body>\n\
+         |     \"x\"\n\
+         |     <table>\n\
+         |       <tbody>\n\
+         |         <tr>\n\
+         |           <td>\n\
+         |             \"a\"\n"
+    );
+}
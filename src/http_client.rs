@@ -1,24 +1,117 @@
-use rustls::{ClientConnection, StreamOwned};
-use std::fmt::{Display, Formatter};
-use std::io::ErrorKind;
-use std::sync::Arc;
+use std::fmt::{Debug, Display, Formatter};
+use std::fs::{self, File, OpenOptions};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Instant, SystemTime};
 use std::{
     collections::HashMap,
     error::Error,
-    io::{Read, Write},
-    net::TcpStream,
+    io::{self, BufReader, Read, Write},
 };
 
+mod auth;
+mod body;
+mod download;
+mod event_source;
+mod gemini;
+mod gopher;
+mod har;
+mod hash;
+mod hpack;
+mod hsts;
+mod http2;
+mod resolver;
+#[cfg(test)]
+mod test_server;
+mod transport;
+mod websocket;
+
+pub use auth::{AuthenticationRequired, CredentialStore, Credentials};
+pub use event_source::{Event, EventSource};
+pub use gemini::{InputRequired, KnownHosts};
+pub use gopher::MENU_MIME_TYPE as GOPHER_MENU_MIME_TYPE;
+pub use har::HarRecorder;
+pub use hsts::HstsStore;
+pub use resolver::{HostOverride, Resolver};
+pub use transport::{NetworkTransport, RecordingTransport, ReplayTransport, Transport};
+pub use websocket::{Message, WebSocket};
+
+use crate::tls::TlsConfig;
 use crate::url::URL;
+use har::{RecordingBody, Timings};
+use transport::{Recording, Stream};
 
-#[derive(Debug, Clone)]
-pub struct Response {
+/// A response whose body has not been read yet. Status and headers are there
+/// as soon as the server sent them, the body is read as it arrives through `Read`.
+pub struct StreamingResponse {
     pub status: u16,
     pub version: String,
     pub explanation: String,
-    pub body: Option<String>,
-    pub headers: HashMap<String, String>,
+    pub headers: Headers,
     pub request: Request,
+    body: Box<dyn Read + Send>,
+    bytes_read: u64,
+    timings: Timings,
+    recording: Option<Recording>,
+}
+
+impl StreamingResponse {
+    /// The expected body size, if the server told us. Useful for progress.
+    pub fn content_length(&self) -> Option<u64> {
+        self.headers
+            .get("content-length")
+            .and_then(|length| length.parse().ok())
+    }
+
+    /// Not something to show, see `Client::save`.
+    pub fn is_download(&self) -> bool {
+        download::is_download(&self.headers)
+    }
+
+    /// Where the response came from, after redirects.
+    pub fn url(&self) -> &URL {
+        &self.request.url
+    }
+
+    pub fn bytes_read(&self) -> u64 {
+        self.bytes_read
+    }
+
+    /// Reads the rest of a response nobody is going to look at, a redirect or
+    /// a `401`, if it is being recorded, so the recording can be replayed.
+    fn finish_recording(&mut self) -> io::Result<()> {
+        if self.recording.is_some() {
+            io::copy(self, &mut io::sink())?;
+        }
+        Ok(())
+    }
+}
+
+impl Read for StreamingResponse {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.body.read(buf)?;
+        self.bytes_read += read as u64;
+        // the end of the body as its framing says, the whole exchange is in
+        if read == 0
+            && !buf.is_empty()
+            && let Some(recording) = self.recording.take()
+        {
+            recording.complete();
+        }
+        Ok(read)
+    }
+}
+
+impl Debug for StreamingResponse {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        f.debug_struct("StreamingResponse")
+            .field("status", &self.status)
+            .field("version", &self.version)
+            .field("explanation", &self.explanation)
+            .field("headers", &self.headers)
+            .field("bytes_read", &self.bytes_read)
+            .finish()
+    }
 }
 
 type Headers = HashMap<String, String>;
@@ -36,167 +129,488 @@ impl Display for Method {
 }
 
 #[derive(Debug, Clone)]
-struct Request {
+pub struct Request {
     url: URL,
     headers: Option<Headers>,
     method: Method,
     body: Option<String>,
 }
 
-pub fn post(
-    url: URL,
-    body: Option<String>,
-    headers: Option<Headers>,
-) -> Result<Response, Box<dyn Error>> {
-    todo!()
+pub struct Client {
+    transport: Box<dyn Transport>,
+    recorder: Option<Arc<HarRecorder>>,
+    credentials: Arc<CredentialStore>,
+    hsts: HstsStore,
+    /// HTTP/2 connections by origin, shared by every request to it. Whoever
+    /// connects to an origin holds its slot until they know what the server
+    /// speaks, so requests made at the same time end up on one connection.
+    http2: Mutex<HashMap<String, Arc<Mutex<Option<http2::Connection>>>>>,
 }
 
-pub fn get(url: String, headers: Option<Headers>) -> Result<Response, Box<dyn Error>> {
-    let request = Request {
-        method: Method::GET,
-        url: URL::parse(&url)?,
-        headers,
-        body: None,
-    };
-
-    do_request(request, 0)
-}
+impl Client {
+    pub fn new(tls: &TlsConfig) -> Result<Client, Box<dyn Error>> {
+        Ok(Client::with_transport(NetworkTransport::new(tls)?))
+    }
 
-fn do_request(request: Request, redirect_count: u8) -> Result<Response, Box<dyn Error>> {
+    /// A client that gets its connections from `transport` instead of the network.
+    pub fn with_transport(transport: impl Transport + 'static) -> Client {
+        Client {
+            transport: Box::new(transport),
+            recorder: None,
+            credentials: Arc::new(CredentialStore::default()),
+            hsts: HstsStore::default(),
+            http2: Mutex::new(HashMap::new()),
+        }
+    }
 
-    println!("Doing request {:?}", request);
+    /// Records every exchange from now on, including each hop of a redirect.
+    pub fn with_har_recorder(mut self, recorder: Arc<HarRecorder>) -> Client {
+        self.recorder = Some(recorder);
+        self
+    }
 
-    if redirect_count > 20 {
-        return Err("Too many redirects".into());
+    /// Answers `401 Unauthorized` with credentials from `store`, which can be
+    /// filled in while the client is in use.
+    pub fn with_credential_store(mut self, store: Arc<CredentialStore>) -> Client {
+        self.credentials = store;
+        self
     }
 
-    let raw_request = raw_http_request(&request);
+    /// Opens a `ws://` or `wss://` url, offering `protocols` as subprotocols.
+    pub fn websocket(&self, url: String, protocols: &[&str]) -> Result<WebSocket, Box<dyn Error>> {
+        let url = URL::parse(&url)?;
+        if !matches!(url.scheme.as_str(), "ws" | "wss") {
+            return Err(format!("not a websocket url: {url}").into());
+        }
+        log::info!("websocket {url}");
 
-    println!("BEGIN REQUEST");
-    println!("{raw_request}");
-    println!("END REQUEST");
+        let connection = self.transport.connect(&url)?;
+        WebSocket::handshake(connection.stream, &url, protocols)
+    }
 
-    let response = match request.url.scheme.as_str() {
-        "http" => do_http_request(&raw_request, &request.url),
-        "https" => do_https_request(&raw_request, &request.url),
-        _ => Err("unsupported scheme".into()),
-    }?;
+    /// Starts out knowing the hosts in `store`, like a preload list.
+    pub fn with_hsts_store(mut self, store: HstsStore) -> Client {
+        self.hsts = store;
+        self
+    }
 
-    println!("BEGIN RESPONSE:");
-    println!("{response}");
-    println!("END RESPONSE:");
+    pub fn post(
+        &self,
+        url: URL,
+        body: Option<String>,
+        headers: Option<Headers>,
+    ) -> Result<StreamingResponse, Box<dyn Error>> {
+        todo!()
+    }
 
-    let parsed = parse_response(response, request)?;
+    /// Returns as soon as the response head is in, so the body can be consumed
+    /// while it downloads.
+    pub fn get_streaming(
+        &self,
+        url: String,
+        headers: Option<Headers>,
+    ) -> Result<StreamingResponse, Box<dyn Error>> {
+        let request = Request {
+            method: Method::GET,
+            url: URL::parse(&url)?,
+            headers,
+            body: None,
+        };
 
-    match parsed.status {
-        100..200 => Ok(parsed),
-        200..300 => Ok(parsed),
-        300..400 => do_request(get_next_request(&parsed)?, redirect_count + 1),
-        400..500 => todo!("your fault"),
-        500..600 => todo!("my fault"),
-        _ => Err("unknown http code".into()),
+        self.open(request, 0)
     }
-}
 
-fn parse_response(response: String, request: Request) -> Result<Response, Box<dyn Error>> {
-    let mut response_lines = response.split("\r\n");
+    /// Saves the body of `response` into `dir` and returns where it ended up.
+    /// If an earlier attempt at the same url was cut off, the rest is asked for
+    /// with `Range`, and the server only sends everything again if its copy
+    /// changed in between.
+    pub fn save(
+        &self,
+        response: StreamingResponse,
+        dir: &Path,
+        mut progress: impl FnMut(u64, Option<u64>),
+    ) -> Result<PathBuf, Box<dyn Error>> {
+        fs::create_dir_all(dir)?;
+        let url = response.request.url.clone();
+        let name = download::file_name(&url, &response.headers);
+        let partial = download::Partial::new(dir, &name);
 
-    let (version, status, explanation) = split_response_status_line(response_lines.next())?;
+        let accepts_ranges = response
+            .headers
+            .get("accept-ranges")
+            .is_some_and(|unit| unit.eq_ignore_ascii_case("bytes"));
+        let mut response = response;
+        if accepts_ranges && let Some((length, validator)) = partial.resumable(&url) {
+            log::info!("resuming {url} from byte {length}");
+            let mut request = response.request.clone();
+            drop(response);
 
-    let mut headers: HashMap<String, String> = HashMap::new();
+            let headers = request.headers.get_or_insert_with(Headers::new);
+            headers.insert("range".to_string(), format!("bytes={length}-"));
+            headers.insert("if-range".to_string(), validator);
+            response = self.open(request, 0)?;
+        }
 
-    loop {
-        if let Some(current_line) = response_lines.next() {
-            if let Some((key, value)) = current_line.split_once(":") {
-                headers.insert(
-                    key.trim().to_lowercase().to_string(),
-                    value.trim().to_string(),
-                );
-            } else {
+        let status = match response.status {
+            // the only success gemini has, anything else failed before this
+            20 if url.scheme == "gemini" => 200,
+            status => status,
+        };
+        let (mut file, mut written, total) = match status {
+            206 => {
+                let (first, total) = download::content_range(&response.headers)?;
+                let file = OpenOptions::new().append(true).open(&partial.path)?;
+                if first != file.metadata()?.len() {
+                    return Err(format!(
+                        "asked to resume at {}, got {first}",
+                        file.metadata()?.len()
+                    )
+                    .into());
+                }
+                (file, first, total)
+            }
+            200..300 => {
+                // a new download, or the server's copy changed and If-Range sent all of it
+                partial.start(&url, download::validator(&response.headers).as_deref())?;
+                let total = response.content_length();
+                (File::create(&partial.path)?, 0, total)
+            }
+            status => return Err(format!("download failed with status {status}").into()),
+        };
+
+        let mut chunk = [0u8; 16 * 1024];
+        loop {
+            let read = response.read(&mut chunk)?;
+            if read == 0 {
                 break;
             }
-        } else {
-            break;
+            file.write_all(&chunk[..read])?;
+            written += read as u64;
+            progress(written, total);
         }
+        file.flush()?;
+
+        if let Some(total) = total
+            && written < total
+        {
+            return Err(format!("download ended after {written} of {total} bytes").into());
+        }
+
+        Ok(partial.finish(dir.join(name))?)
     }
 
-    if headers.contains_key("transfer-encoding") || headers.contains_key("content-encoding") {
-        return Err("body is encoded, dont know how to handle that".into());
+    /// Sends the request and follows redirects until there is a response to
+    /// hand back, without reading its body.
+    fn open(
+        &self,
+        request: Request,
+        redirect_count: u8,
+    ) -> Result<StreamingResponse, Box<dyn Error>> {
+        let request = self.upgrade_to_https(request);
+        log::info!("{} {}", request.method, request.url);
+
+        if redirect_count > 20 {
+            return Err("Too many redirects".into());
+        }
+
+        let started = SystemTime::now();
+        let mut response = match request.url.scheme.as_str() {
+            "http" | "https" => self.open_request(request),
+            "gemini" => self.open_exchange(request, gemini::exchange),
+            "gopher" => self.open_exchange(request, gopher::exchange),
+            _ => Err("unsupported scheme".into()),
+        }?;
+
+        if let Some(recorder) = &self.recorder {
+            let entry = har_entry(
+                &response.request,
+                &response.version,
+                response.status,
+                &response.explanation,
+                &response.headers,
+                started,
+                response.timings.clone(),
+            );
+            let body = std::mem::replace(&mut response.body, Box::new(io::empty()));
+            response.body = Box::new(RecordingBody::new(body, recorder.clone(), entry));
+        }
+
+        if response.request.url.scheme == "gemini" {
+            // only a success has a body, the rest end with their header
+            if response.status != 20 {
+                response.finish_recording()?;
+            }
+            return match gemini::next_request(&response)? {
+                Some(next) => self.open(next, redirect_count + 1),
+                None => Ok(response),
+            };
+        }
+
+        if response.request.url.scheme == "https"
+            && let Some(policy) = response.headers.get("strict-transport-security")
+        {
+            self.hsts.observe(&response.request.url.host, policy);
+        }
+
+        if response.status == 401
+            && let Some(retry) = self.authenticate(&response)?
+        {
+            response.finish_recording()?;
+            return self.open(retry, redirect_count + 1);
+        }
+
+        match next_request(response.status, &response.headers, &response.request)? {
+            Some(next) => {
+                response.finish_recording()?;
+                self.open(next, redirect_count + 1)
+            }
+            None => Ok(response),
+        }
     }
 
-    Ok(Response {
-        status,
-        explanation: explanation.to_string(),
-        version: version.to_string(),
-        body: response_lines.next().map(|rest| rest.trim().to_string()),
-        headers,
-        request,
-    })
-}
+    /// Plain http to a host that is known to be https only goes to https
+    /// instead, so nobody on the way gets to see or change it.
+    fn upgrade_to_https(&self, mut request: Request) -> Request {
+        if request.url.scheme == "http" && self.hsts.is_known(&request.url.host) {
+            log::info!(
+                "{} is https only, upgrading {}",
+                request.url.host,
+                request.url
+            );
+            request.url.scheme = "https".to_string();
+            if request.url.port.as_deref() == Some("80") {
+                request.url.port = None;
+            }
+        }
+        request
+    }
 
-fn do_http_request(request: &str, url: &URL) -> Result<String, Box<dyn Error>> {
-    let domain = url.domain();
-    let mut stream = TcpStream::connect(domain).unwrap();
+    /// Repeats a request that got `401` with credentials from the store or the
+    /// url. Fails with `AuthenticationRequired` if there are none, or if the ones
+    /// we sent were turned down, so someone can be asked for them.
+    fn authenticate(
+        &self,
+        response: &StreamingResponse,
+    ) -> Result<Option<Request>, Box<dyn Error>> {
+        let request = &response.request;
+        let challenges = response
+            .headers
+            .get("www-authenticate")
+            .map(|header| auth::parse_challenges(header))
+            .unwrap_or_default();
+        let Some(challenge) = auth::pick(&challenges) else {
+            // nothing we can answer, show whatever the server sent
+            return Ok(None);
+        };
 
-    let _ = stream.write_all(request.as_bytes());
+        let origin = request.url.origin();
+        let realm = challenge.realm();
+        let required = |rejected| AuthenticationRequired {
+            url: request.url.to_string(),
+            realm: realm.to_string(),
+            rejected,
+        };
 
-    let mut response = String::new();
-    let _ = stream.read_to_string(&mut response)?;
+        // a stale digest nonce means the credentials were fine, just try again
+        let stale = challenge
+            .params
+            .get("stale")
+            .is_some_and(|s| s.eq_ignore_ascii_case("true"));
+        let already_sent = request
+            .headers
+            .as_ref()
+            .is_some_and(|headers| headers.contains_key("authorization"));
+        if already_sent && !stale {
+            self.credentials.remove(&origin, realm);
+            return Err(required(true).into());
+        }
 
-    Ok(response)
-}
+        let credentials = match (self.credentials.get(&origin, realm), &request.url.userinfo) {
+            (Some(credentials), _) => credentials,
+            (None, Some(userinfo)) => {
+                let credentials = Credentials::from_userinfo(userinfo);
+                self.credentials.insert(&origin, realm, credentials.clone());
+                credentials
+            }
+            (None, None) => return Err(required(false).into()),
+        };
 
-fn do_https_request(request: &str, url: &URL) -> Result<String, Box<dyn Error>> {
-    //
-    // prepare root certificates
-    let root_store =
-        rustls::RootCertStore::from_iter(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+        let uri = match &request.url.query {
+            Some(query) => format!("{}?{query}", request.url.path),
+            None => request.url.path.clone(),
+        };
+        let authorization =
+            auth::authorization(challenge, &credentials, &request.method.to_string(), &uri)?;
+        log::debug!("answering {} challenge for {realm:?}", challenge.scheme);
 
-    // build TLS client config
-    let config = rustls::ClientConfig::builder()
-        .with_root_certificates(root_store)
-        .with_no_client_auth();
+        let mut retry = request.clone();
+        retry
+            .headers
+            .get_or_insert_with(Headers::new)
+            .insert("authorization".to_string(), authorization);
+        Ok(Some(retry))
+    }
 
-    let config = Arc::new(config);
-    // connect TCP stream
-    let tcp_stream = TcpStream::connect(url.domain()).map_err(|e| {
-        println!("could not create tcp connection: {e}");
-        e
-    })?;
+    fn open_request(&self, request: Request) -> Result<StreamingResponse, Box<dyn Error>> {
+        // h2 is only ever negotiated over TLS
+        let slot = match request.url.scheme.as_str() {
+            "https" => Some(
+                self.http2
+                    .lock()
+                    .unwrap()
+                    .entry(request.url.origin())
+                    .or_default()
+                    .clone(),
+            ),
+            _ => None,
+        };
+        let mut pooled = slot.as_ref().map(|slot| slot.lock().unwrap());
+        if let Some(connection) = pooled.as_deref().and_then(Option::as_ref)
+            && connection.is_open()
+        {
+            let connection = connection.clone();
+            drop(pooled);
+            return connection.stream(request);
+        }
 
-    println!("tcp connected");
+        let connecting = Instant::now();
+        let connection = self.transport.connect(&request.url)?;
+        let connect = connecting.elapsed();
 
-    // create TLS connection
-    let server_name = url.host.clone().try_into()?;
-    let tls_conn = ClientConnection::new(config, server_name).map_err(|e| {
-        println!("could not create tls connection: {e}");
-        e
-    })?;
+        let mut response = if connection.http2 {
+            let shared = http2::Connection::handshake(connection.stream)?;
+            if let Some(pooled) = pooled.as_deref_mut() {
+                *pooled = Some(shared.clone());
+            }
+            drop(pooled);
+            shared.stream(request)?
+        } else {
+            drop(pooled);
+            open_http1_exchange(connection.stream, request)?
+        };
 
-    println!("tls established");
+        response.timings.connect = connect;
+        // upgrades and event streams don't end, there is nothing to replay
+        let streaming = response
+            .headers
+            .get("content-type")
+            .is_some_and(|mime| mime.starts_with("text/event-stream"));
+        if response.status != 101 && !streaming {
+            response.recording = connection.recording;
+        }
+        Ok(response)
+    }
 
-    let mut tls_stream = StreamOwned::new(tls_conn, tcp_stream);
+    /// For the protocols that are one request and one response per connection.
+    fn open_exchange(
+        &self,
+        request: Request,
+        exchange: impl FnOnce(Box<dyn Stream>, Request) -> Result<StreamingResponse, Box<dyn Error>>,
+    ) -> Result<StreamingResponse, Box<dyn Error>> {
+        let connecting = Instant::now();
+        let connection = self.transport.connect(&request.url)?;
+        let connect = connecting.elapsed();
 
-    tls_stream.write_all(request.as_bytes()).map_err(|e| {
-        println!("could not write to stream: {e}");
+        let mut response = exchange(connection.stream, request)?;
+        response.timings.connect = connect;
+        response.recording = connection.recording;
+        Ok(response)
+    }
+}
+
+/// Decides what to do once the status is known: hand the response back, or
+/// follow a redirect with the returned request.
+fn next_request(
+    status: u16,
+    headers: &Headers,
+    request: &Request,
+) -> Result<Option<Request>, Box<dyn Error>> {
+    match status {
+        100..200 => Ok(None),
+        200..300 => Ok(None),
+        300..400 => Ok(Some(get_next_request(headers, request)?)),
+        // 401 is answered by the client, the rest are pages to show
+        400..500 => Ok(None),
+        500..600 => Ok(None),
+        _ => Err("unknown http code".into()),
+    }
+}
+
+/// Sends one HTTP/1.1 request on a fresh connection and reads the response head.
+/// The body is left on the connection, which the server closes when it is done
+/// since we send `connection: close`.
+fn open_http1_exchange(
+    mut stream: impl Read + Write + Send + 'static,
+    request: Request,
+) -> Result<StreamingResponse, Box<dyn Error>> {
+    let raw_request = raw_http_request(&request);
+    log::trace!("request:\n{raw_request}");
+
+    let sending = Instant::now();
+    stream.write_all(raw_request.as_bytes()).map_err(|e| {
+        log::warn!("could not write to stream: {e}");
         e
     })?;
+    let send = sending.elapsed();
 
-    println!("wrote request to stream");
-
-    let mut response = Vec::new();
+    let waiting = Instant::now();
+    let mut reader = BufReader::new(stream);
+    let head = body::read_head(&mut reader)?;
+    let wait = waiting.elapsed();
 
-    match tls_stream.read_to_end(&mut response) {
-        Ok(_) => {}
-        Err(e) if e.kind() == ErrorKind::UnexpectedEof => {
-            // harmless - the server closed without close_notify
-        }
-        Err(e) => return Err(e.into()),
+    log::debug!("{} {} {}", head.version, head.status, head.explanation);
+    for (key, value) in &head.headers {
+        log::trace!("{key}: {value}");
     }
 
-    println!("read response");
+    let body = body::body_reader(reader, &head)?;
 
-    Ok(String::from_utf8_lossy(&response).to_string())
+    Ok(StreamingResponse {
+        status: head.status,
+        version: head.version,
+        explanation: head.explanation,
+        headers: head.headers,
+        request,
+        body,
+        bytes_read: 0,
+        recording: None,
+        timings: Timings {
+            send,
+            wait,
+            ..Timings::default()
+        },
+    })
+}
+
+fn har_entry(
+    request: &Request,
+    version: &str,
+    status: u16,
+    explanation: &str,
+    headers: &Headers,
+    started: SystemTime,
+    timings: Timings,
+) -> har::Entry {
+    let mut request_headers = vec![("Host".to_string(), request.url.host.clone())];
+    request_headers.extend(with_default_headers(&request.headers));
+
+    har::Entry {
+        started,
+        method: request.method.to_string(),
+        url: request.url.to_string(),
+        http_version: version.to_string(),
+        request_headers,
+        request_body_size: request.body.as_ref().map_or(0, |body| body.len()),
+        status,
+        status_text: explanation.to_string(),
+        response_headers: headers
+            .iter()
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect(),
+        body_size: 0,
+        redirect_url: headers.get("location").cloned().unwrap_or_default(),
+        timings,
+    }
 }
 
 fn split_response_status_line(
@@ -257,27 +671,576 @@ fn with_default_headers(headers: &Option<Headers>) -> Headers {
     default_headers
 }
 
-fn get_next_request(response: &Response) -> Result<Request, Box<dyn Error>> {
-    let location = response
-        .headers
+fn get_next_request(headers: &Headers, request: &Request) -> Result<Request, Box<dyn Error>> {
+    let location = headers
         .get("location")
         .expect("redirect response did not have location header");
 
-    let original_request = response.request.clone();
+    let original_request = request.clone();
 
     let url = match location {
         l if l.starts_with("/") => {
-            let URL { scheme, host, .. } = original_request.url;
+            let URL { scheme, host, .. } = &original_request.url;
             // what about port?
             URL::parse(&format!("{scheme}://{host}{location}"))?
         }
         _ => URL::parse(location)?,
     };
 
+    // credentials are only for the origin that asked for them
+    let mut headers = original_request.headers;
+    let same_origin = (&url.scheme, &url.host, &url.port)
+        == (
+            &original_request.url.scheme,
+            &original_request.url.host,
+            &original_request.url.port,
+        );
+    if !same_origin && let Some(headers) = headers.as_mut() {
+        headers.remove("authorization");
+    }
+
     Ok(Request {
         method: Method::GET,
         url,
-        headers: original_request.headers,
+        headers,
         body: None,
     })
 }
+
+#[cfg(test)]
+use test_server::{Reply, TestServer};
+
+/// All of the body, for tests that don't care how it arrives.
+#[cfg(test)]
+fn read_body(response: &mut StreamingResponse) -> String {
+    let mut body = String::new();
+    response.read_to_string(&mut body).unwrap();
+    body
+}
+
+#[test]
+fn redirects_are_followed() {
+    let server = TestServer::builder()
+        .route("/new", Reply::ok("<p>moved</p>"))
+        .http();
+    let old = TestServer::builder()
+        .route("/old", Reply::redirect(301, &server.url("/new")))
+        .http();
+
+    let client = Client::new(&TlsConfig::default()).unwrap();
+    let mut response = client.get_streaming(old.url("/old"), None).unwrap();
+
+    assert_eq!(response.status, 200);
+    assert_eq!(read_body(&mut response), "<p>moved</p>");
+    let received = server.received();
+    assert_eq!(received[0].method, "GET");
+    assert_eq!(received[0].path, "/new");
+    assert_eq!(received[0].headers["connection"], "close");
+}
+
+#[test]
+fn chunked_bodies_are_joined() {
+    let server = TestServer::builder()
+        .route("/", Reply::chunked(&["<p>one", " two", "</p>"]))
+        .http();
+
+    let client = Client::new(&TlsConfig::default()).unwrap();
+    let mut response = client.get_streaming(server.url("/"), None).unwrap();
+
+    assert_eq!(read_body(&mut response), "<p>one two</p>");
+}
+
+#[test]
+fn malformed_status_line_is_an_error() {
+    let server = TestServer::builder()
+        .route("/", Reply::raw("HTTP/1.1 OK\r\n\r\n"))
+        .http();
+
+    let client = Client::new(&TlsConfig::default()).unwrap();
+    assert!(client.get_streaming(server.url("/"), None).is_err());
+}
+
+#[test]
+fn head_arrives_before_a_slow_body() {
+    let server = TestServer::builder()
+        .route(
+            "/",
+            Reply::ok("<p>late</p>").slow(std::time::Duration::from_millis(200)),
+        )
+        .http();
+
+    let client = Client::new(&TlsConfig::default()).unwrap();
+    let opened = Instant::now();
+    let mut response = client.get_streaming(server.url("/"), None).unwrap();
+    let head_after = opened.elapsed();
+
+    let mut body = String::new();
+    response.read_to_string(&mut body).unwrap();
+
+    assert!(opened.elapsed() - head_after >= std::time::Duration::from_millis(150));
+    assert_eq!(response.content_length(), Some(11));
+    assert_eq!(body, "<p>late</p>");
+}
+
+#[test]
+fn https_with_a_trusted_test_root() {
+    let server = TestServer::builder()
+        .route("/", Reply::ok("<p>secure</p>"))
+        .https();
+
+    let client = Client::new(&server.tls_config()).unwrap();
+    let mut response = client.get_streaming(server.url("/"), None).unwrap();
+    assert_eq!(read_body(&mut response), "<p>secure</p>");
+
+    let untrusting = Client::new(&TlsConfig::default()).unwrap();
+    let error = untrusting.get_streaming(server.url("/"), None).unwrap_err();
+    assert!(
+        error
+            .downcast_ref::<crate::tls::CertificateError>()
+            .is_some()
+    );
+}
+
+#[test]
+fn concurrent_http2_requests_share_a_connection() {
+    use std::sync::Barrier;
+    use std::thread;
+
+    let server = TestServer::builder()
+        .route("/one", Reply::ok("<p>one</p>"))
+        .route("/two", Reply::ok("<p>two</p>"))
+        .http2();
+    let client = Client::new(&server.tls_config()).unwrap();
+
+    // both responses are open at the same time before either body is read
+    let (started, answered) = (Barrier::new(2), Barrier::new(2));
+    let bodies: Vec<String> = thread::scope(|scope| {
+        let fetch = |path| {
+            let (client, server) = (&client, &server);
+            let (started, answered) = (&started, &answered);
+            scope.spawn(move || {
+                started.wait();
+                let mut response = client.get_streaming(server.url(path), None).unwrap();
+                answered.wait();
+                assert_eq!(response.version, "HTTP/2");
+                read_body(&mut response)
+            })
+        };
+        let (one, two) = (fetch("/one"), fetch("/two"));
+        vec![one.join().unwrap(), two.join().unwrap()]
+    });
+
+    assert_eq!(bodies, ["<p>one</p>", "<p>two</p>"]);
+    assert_eq!(server.received().len(), 2);
+    assert_eq!(server.connections(), 1);
+}
+
+#[test]
+fn basic_auth_from_the_url_and_the_store() {
+    let server = TestServer::builder()
+        .route_fn("/tools", |request| {
+            match request.headers.get("authorization").map(String::as_str) {
+                // alice:secret
+                Some("Basic YWxpY2U6c2VjcmV0") => Reply::ok("<p>welcome</p>"),
+                _ => Reply::status(
+                    401,
+                    "Unauthorized",
+                    &[("WWW-Authenticate", r#"Basic realm="intranet""#)],
+                    "",
+                ),
+            }
+        })
+        .http();
+    let url = server.url("/tools");
+
+    let client = Client::new(&TlsConfig::default()).unwrap();
+    let with_userinfo = url.replace("localhost", "alice:secret@localhost");
+    let mut response = client.get_streaming(with_userinfo, None).unwrap();
+    assert_eq!(read_body(&mut response), "<p>welcome</p>");
+
+    let store = Arc::new(CredentialStore::default());
+    let client = Client::new(&TlsConfig::default())
+        .unwrap()
+        .with_credential_store(store.clone());
+    let error = client.get_streaming(url.clone(), None).unwrap_err();
+    let required = error.downcast_ref::<AuthenticationRequired>().unwrap();
+    assert_eq!(required.realm, "intranet");
+    assert!(!required.rejected);
+
+    let origin = URL::parse(&url).unwrap().origin();
+    store.insert(
+        &origin,
+        "intranet",
+        Credentials::from_userinfo("alice:wrong"),
+    );
+    let error = client.get_streaming(url.clone(), None).unwrap_err();
+    assert!(
+        error
+            .downcast_ref::<AuthenticationRequired>()
+            .unwrap()
+            .rejected
+    );
+
+    store.insert(
+        &origin,
+        "intranet",
+        Credentials::from_userinfo("alice:secret"),
+    );
+    assert_eq!(client.get_streaming(url, None).unwrap().status, 200);
+}
+
+#[test]
+fn digest_auth_is_answered() {
+    let server = TestServer::builder()
+        .route_fn("/", |request| match request.headers.get("authorization") {
+            Some(answer) if answer.starts_with(r#"Digest username="alice""#) => {
+                Reply::ok("<p>welcome</p>")
+            }
+            _ => Reply::status(
+                401,
+                "Unauthorized",
+                &[(
+                    "WWW-Authenticate",
+                    r#"Digest realm="intranet", qop="auth", algorithm=SHA-256, nonce="abc""#,
+                )],
+                "",
+            ),
+        })
+        .http();
+
+    let store = Arc::new(CredentialStore::default());
+    let origin = URL::parse(&server.url("/")).unwrap().origin();
+    store.insert(
+        &origin,
+        "intranet",
+        Credentials::from_userinfo("alice:secret"),
+    );
+
+    let client = Client::new(&TlsConfig::default())
+        .unwrap()
+        .with_credential_store(store);
+    let mut response = client.get_streaming(server.url("/"), None).unwrap();
+
+    assert_eq!(read_body(&mut response), "<p>welcome</p>");
+    let answer = &server.received()[1].headers["authorization"];
+    assert!(answer.contains(r#"uri="/", "#));
+    assert!(answer.contains("algorithm=SHA-256, qop=auth, nc=00000001"));
+}
+
+#[test]
+fn interrupted_downloads_are_resumed() {
+    let server = TestServer::builder()
+        .route_fn("/data.bin", |request| {
+            let range = request.headers.get("range").map(String::as_str);
+            let if_range = request.headers.get("if-range").map(String::as_str);
+            match (range, if_range) {
+                (Some("bytes=5-"), Some(r#""v1""#)) => Reply::status(
+                    206,
+                    "Partial Content",
+                    &[("Content-Range", "bytes 5-9/10"), ("ETag", r#""v1""#)],
+                    "56789",
+                ),
+                _ => Reply::status(
+                    200,
+                    "OK",
+                    &[
+                        ("Content-Type", "application/octet-stream"),
+                        ("Accept-Ranges", "bytes"),
+                        ("ETag", r#""v1""#),
+                    ],
+                    "0123456789",
+                ),
+            }
+        })
+        .http();
+    let dir = std::env::temp_dir().join(format!("downloads-{}", std::process::id()));
+    let url = URL::parse(&server.url("/data.bin")).unwrap();
+    let client = Client::new(&TlsConfig::default()).unwrap();
+
+    let save = |validator: &str| {
+        let partial = download::Partial::new(&dir, "data.bin");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(&partial.path, "01234").unwrap();
+        partial.start(&url, Some(validator)).unwrap();
+
+        let response = client.get_streaming(url.to_string(), None).unwrap();
+        assert!(response.is_download());
+        let mut reported = 0;
+        let saved = client
+            .save(response, &dir, |bytes, total| {
+                assert_eq!(total, Some(10));
+                reported = bytes;
+            })
+            .unwrap();
+        assert_eq!(reported, 10);
+        let content = fs::read_to_string(&saved).unwrap();
+        fs::remove_file(saved).unwrap();
+        content
+    };
+
+    assert_eq!(save(r#""v1""#), "0123456789");
+    assert_eq!(server.received()[1].headers["range"], "bytes=5-");
+
+    // the server's copy changed, so If-Range makes it send everything
+    assert_eq!(save(r#""v0""#), "0123456789");
+    assert!(!dir.join("data.bin.part").exists());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn gemini_downloads_are_saved() {
+    let server = TestServer::builder()
+        .route("/paper.pdf", Reply::raw("20 application/pdf\r\n%PDF-1.7\n"))
+        .gemini();
+    let dir = std::env::temp_dir().join(format!("gemini-downloads-{}", std::process::id()));
+    let client = Client::new(&TlsConfig::default()).unwrap();
+
+    let response = client
+        .get_streaming(server.url("/paper.pdf"), None)
+        .unwrap();
+    assert!(response.is_download());
+    let saved = client.save(response, &dir, |_, _| {}).unwrap();
+    let content = fs::read_to_string(&saved).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(saved.file_name().unwrap(), "paper.pdf");
+    assert_eq!(content, "%PDF-1.7\n");
+}
+
+#[test]
+fn pinned_hosts_connect_to_the_pinned_address() {
+    let server = TestServer::builder()
+        .route("/", Reply::ok("<p>pinned</p>"))
+        .http();
+    let port = URL::parse(&server.url("/")).unwrap().port.unwrap();
+
+    let pinned = HostOverride::parse(&format!("intranet.test:{port}:127.0.0.1")).unwrap();
+    let transport = NetworkTransport::new(&TlsConfig::default())
+        .unwrap()
+        .with_resolver(Resolver::default().with_overrides(vec![pinned]));
+    let client = Client::with_transport(transport);
+    let mut response = client
+        .get_streaming(format!("http://intranet.test:{port}/"), None)
+        .unwrap();
+
+    assert_eq!(read_body(&mut response), "<p>pinned</p>");
+    assert_eq!(server.received()[0].headers["host"], "intranet.test");
+}
+
+#[test]
+fn hsts_upgrades_plain_http() {
+    let server = TestServer::builder()
+        .route(
+            "/",
+            Reply::status(
+                200,
+                "OK",
+                &[("Strict-Transport-Security", "max-age=600")],
+                "<p>secure</p>",
+            ),
+        )
+        .https();
+    let client = Client::new(&server.tls_config()).unwrap();
+
+    // plain http to a tls port fails until the host is known to be https only
+    let insecure = server.url("/").replace("https://", "http://");
+    assert!(client.get_streaming(insecure.clone(), None).is_err());
+
+    client.get_streaming(server.url("/"), None).unwrap();
+    let mut response = client.get_streaming(insecure, None).unwrap();
+    assert_eq!(read_body(&mut response), "<p>secure</p>");
+    assert_eq!(response.request.url.scheme, "https");
+}
+
+#[test]
+fn preloaded_hosts_are_upgraded() {
+    let list = std::env::temp_dir().join(format!("hsts-preload-{}", std::process::id()));
+    fs::write(&list, "# test list\nlocalhost includeSubDomains\n").unwrap();
+    let store = HstsStore::default().with_preload_list(&list).unwrap();
+    fs::remove_file(&list).unwrap();
+
+    let server = TestServer::builder()
+        .route("/", Reply::ok("<p>preloaded</p>"))
+        .https();
+    let client = Client::new(&server.tls_config())
+        .unwrap()
+        .with_hsts_store(store);
+
+    let insecure = server.url("/").replace("https://", "http://");
+    let mut response = client.get_streaming(insecure, None).unwrap();
+    assert_eq!(read_body(&mut response), "<p>preloaded</p>");
+}
+
+#[test]
+fn websocket_echo() {
+    use std::io::BufRead;
+    use std::net::TcpListener;
+    use websocket::{Frame, Opcode, accept, encode_frame, read_frame};
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+
+    // echoes every frame as it is, after pinging the client once
+    let server = std::thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream);
+        let mut key = String::new();
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            if let Some(value) = line.strip_prefix("Sec-WebSocket-Key:") {
+                key = value.trim().to_string();
+            }
+            if line == "\r\n" {
+                break;
+            }
+        }
+        let handshake = format!(
+            "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\n\
+             Connection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+            accept(&key)
+        );
+        let ping = Frame {
+            fin: true,
+            opcode: Opcode::Ping,
+            payload: b"hi".to_vec(),
+            masked: false,
+        };
+        let stream = reader.get_mut();
+        stream.write_all(handshake.as_bytes()).unwrap();
+        stream.write_all(&encode_frame(&ping)).unwrap();
+
+        let mut received = Vec::new();
+        loop {
+            let frame = read_frame(&mut reader).unwrap();
+            assert!(frame.masked, "clients have to mask");
+            received.push((frame.opcode, frame.fin, frame.payload.len()));
+            if frame.opcode != Opcode::Pong {
+                let echo = Frame {
+                    masked: false,
+                    ..frame.clone()
+                };
+                reader.get_mut().write_all(&encode_frame(&echo)).unwrap();
+            }
+            if frame.opcode == Opcode::Close {
+                return received;
+            }
+        }
+    });
+
+    let client = Client::new(&TlsConfig::default()).unwrap();
+    let mut socket = client
+        .websocket(format!("ws://127.0.0.1:{port}/echo"), &[])
+        .unwrap();
+
+    socket.send(Message::Text("hello".to_string())).unwrap();
+    assert_eq!(
+        socket.receive().unwrap(),
+        Message::Text("hello".to_string())
+    );
+
+    let long: Vec<u8> = (0..40_000).map(|i| i as u8).collect();
+    socket.send(Message::Binary(long.clone())).unwrap();
+    assert_eq!(socket.receive().unwrap(), Message::Binary(long));
+
+    socket.close(1000, "done").unwrap();
+    assert!(socket.receive().is_err());
+
+    assert_eq!(
+        server.join().unwrap(),
+        vec![
+            (Opcode::Text, true, 5),
+            (Opcode::Pong, true, 2),
+            (Opcode::Binary, false, 16384),
+            (Opcode::Continuation, false, 16384),
+            (Opcode::Continuation, true, 7232),
+            (Opcode::Close, true, 6),
+        ]
+    );
+}
+
+#[test]
+fn gemini_statuses() {
+    let server = TestServer::builder()
+        .route(
+            "/",
+            Reply::raw("20 text/gemini\r\n# Hello\n=> /old Old page\n"),
+        )
+        .route("/old", Reply::raw("31 /\r\n"))
+        .route("/search", Reply::raw("10 Search for what?\r\n"))
+        .route(
+            "/search?rust%20lang",
+            Reply::raw("20 text/plain\r\nresults"),
+        )
+        .route("/gone", Reply::raw("51 Not found\r\n"))
+        .gemini();
+    let client = Client::new(&TlsConfig::default()).unwrap();
+
+    let mut response = client.get_streaming(server.url("/old"), None).unwrap();
+    assert_eq!(response.status, 20);
+    assert_eq!(response.request.url.path, "/");
+    assert_eq!(response.headers["content-type"], "text/gemini");
+    assert_eq!(read_body(&mut response), "# Hello\n=> /old Old page\n");
+
+    let error = client
+        .get_streaming(server.url("/search"), None)
+        .unwrap_err();
+    let input = error.downcast_ref::<InputRequired>().unwrap();
+    assert_eq!(input.prompt, "Search for what?");
+    assert!(!input.sensitive);
+
+    let mut response = client
+        .get_streaming(server.url("/search?rust%20lang"), None)
+        .unwrap();
+    assert_eq!(read_body(&mut response), "results");
+
+    let error = client.get_streaming(server.url("/gone"), None).unwrap_err();
+    assert!(error.to_string().contains("51: Not found"));
+    assert_eq!(server.received()[0].method, "GEMINI");
+
+    // another server on localhost with a different certificate
+    let impostor = TestServer::builder()
+        .route("/", Reply::raw("20 text/gemini\r\nhi"))
+        .gemini();
+    assert!(client.get_streaming(impostor.url("/"), None).is_err());
+}
+
+#[test]
+fn gopher_items() {
+    let server = TestServer::builder()
+        .route(
+            "",
+            Reply::raw("iWelcome\t\tnull.host\t1\r\n0About\t/about.txt\tlocalhost\t70\r\n.\r\n"),
+        )
+        .route("/about.txt", Reply::raw("All about us\r\n"))
+        .route(
+            "/search\trust lang",
+            Reply::raw("0Rust\t/rust\tlocalhost\t70\r\n.\r\n"),
+        )
+        .gopher();
+    let client = Client::new(&TlsConfig::default()).unwrap();
+
+    let mut response = client.get_streaming(server.url("/"), None).unwrap();
+    assert_eq!(response.headers["content-type"], GOPHER_MENU_MIME_TYPE);
+    assert!(read_body(&mut response).starts_with("iWelcome"));
+
+    let mut response = client
+        .get_streaming(server.url("/0/about.txt"), None)
+        .unwrap();
+    assert_eq!(response.headers["content-type"], "text/plain");
+    assert_eq!(read_body(&mut response), "All about us\r\n");
+
+    let error = client
+        .get_streaming(server.url("/7/search"), None)
+        .unwrap_err();
+    let input = error.downcast_ref::<InputRequired>().unwrap();
+    assert_eq!(input.url, server.url("/7/search"));
+
+    let mut response = client
+        .get_streaming(server.url("/7/search?rust%20lang"), None)
+        .unwrap();
+    assert!(read_body(&mut response).starts_with("0Rust"));
+    let paths: Vec<_> = server.received().into_iter().map(|r| r.path).collect();
+    assert_eq!(paths, ["", "/about.txt", "/search\trust lang"]);
+    assert_eq!(server.received()[0].method, "GOPHER");
+}
@@ -0,0 +1,424 @@
+//! HTTP authentication, Basic from https://www.rfc-editor.org/rfc/rfc7617 and
+//! Digest from https://www.rfc-editor.org/rfc/rfc7616.
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::sync::Mutex;
+
+use super::hash::{hex, md5, random_bytes, sha256};
+use crate::url::percent_decode;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Challenge {
+    /// Lowercased, like `basic` or `digest`.
+    pub scheme: String,
+    /// Names are lowercased, quoted values are unquoted.
+    pub params: HashMap<String, String>,
+}
+
+impl Challenge {
+    pub fn realm(&self) -> &str {
+        self.params.get("realm").map(String::as_str).unwrap_or("")
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Credentials {
+    pub username: String,
+    pub password: String,
+}
+
+impl Credentials {
+    /// From the `user:password` part of a url, percent-encoding removed.
+    pub fn from_userinfo(userinfo: &str) -> Credentials {
+        let (username, password) = userinfo.split_once(':').unwrap_or((userinfo, ""));
+        Credentials {
+            username: percent_decode(username),
+            password: percent_decode(password),
+        }
+    }
+}
+
+/// Credentials per origin and realm, shared between the client and the login
+/// prompt that fills it in.
+#[derive(Debug, Default)]
+pub struct CredentialStore {
+    entries: Mutex<HashMap<(String, String), Credentials>>,
+}
+
+impl CredentialStore {
+    pub fn insert(&self, origin: &str, realm: &str, credentials: Credentials) {
+        self.entries
+            .lock()
+            .unwrap()
+            .insert((origin.to_string(), realm.to_string()), credentials);
+    }
+
+    pub fn get(&self, origin: &str, realm: &str) -> Option<Credentials> {
+        self.entries
+            .lock()
+            .unwrap()
+            .get(&(origin.to_string(), realm.to_string()))
+            .cloned()
+    }
+
+    pub fn remove(&self, origin: &str, realm: &str) {
+        self.entries
+            .lock()
+            .unwrap()
+            .remove(&(origin.to_string(), realm.to_string()));
+    }
+}
+
+/// The server wants credentials we don't have, or turned down the ones we sent.
+#[derive(Debug)]
+pub struct AuthenticationRequired {
+    pub url: String,
+    pub realm: String,
+    pub rejected: bool,
+}
+
+impl Display for AuthenticationRequired {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match self.rejected {
+            true => write!(f, "wrong username or password for {:?}", self.realm),
+            false => write!(f, "{} needs a username and password", self.url),
+        }
+    }
+}
+
+impl Error for AuthenticationRequired {}
+
+/// Parses every challenge in a `WWW-Authenticate` value, like
+/// `Digest realm="x", qop="auth", Basic realm="y"`.
+pub fn parse_challenges(header: &str) -> Vec<Challenge> {
+    let mut parser = Parser {
+        input: header.as_bytes(),
+        position: 0,
+    };
+    let mut challenges = Vec::new();
+
+    loop {
+        parser.skip_separators();
+        let scheme = parser.token();
+        if scheme.is_empty() {
+            break;
+        }
+
+        let mut params = HashMap::new();
+        loop {
+            parser.skip_whitespace();
+            let checkpoint = parser.position;
+            let name = parser.token();
+            parser.skip_whitespace();
+
+            if name.is_empty() || !parser.eat(b'=') {
+                // not a parameter, so the next challenge starts here
+                parser.position = checkpoint;
+                break;
+            }
+
+            if parser.peek() == Some(b'=') || parser.at_list_end() {
+                // token68, like `Negotiate abc==`, no parameters of our business
+                while parser.eat(b'=') {}
+                params.clear();
+                break;
+            }
+
+            parser.skip_whitespace();
+            let value = match parser.peek() {
+                Some(b'"') => parser.quoted_string(),
+                _ => parser.token(),
+            };
+            params.insert(name.to_lowercase(), value);
+
+            parser.skip_whitespace();
+            if !parser.eat(b',') {
+                break;
+            }
+        }
+
+        challenges.push(Challenge {
+            scheme: scheme.to_lowercase(),
+            params,
+        });
+    }
+
+    challenges
+}
+
+struct Parser<'a> {
+    input: &'a [u8],
+    position: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<u8> {
+        self.input.get(self.position).copied()
+    }
+
+    fn eat(&mut self, byte: u8) -> bool {
+        if self.peek() == Some(byte) {
+            self.position += 1;
+            return true;
+        }
+        false
+    }
+
+    fn skip_whitespace(&mut self) {
+        while matches!(self.peek(), Some(b' ' | b'\t')) {
+            self.position += 1;
+        }
+    }
+
+    fn skip_separators(&mut self) {
+        while matches!(self.peek(), Some(b' ' | b'\t' | b',')) {
+            self.position += 1;
+        }
+    }
+
+    fn at_list_end(&self) -> bool {
+        let rest = &self.input[self.position..];
+        rest.iter()
+            .find(|b| !matches!(b, b' ' | b'\t'))
+            .is_none_or(|&b| b == b',')
+    }
+
+    fn token(&mut self) -> String {
+        let start = self.position;
+        while let Some(byte) = self.peek() {
+            let is_tchar = byte.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~/".contains(&byte);
+            if !is_tchar {
+                break;
+            }
+            self.position += 1;
+        }
+        String::from_utf8_lossy(&self.input[start..self.position]).to_string()
+    }
+
+    fn quoted_string(&mut self) -> String {
+        let mut value = Vec::new();
+        self.eat(b'"');
+        while let Some(byte) = self.peek() {
+            self.position += 1;
+            match byte {
+                b'"' => break,
+                b'\\' => {
+                    if let Some(escaped) = self.peek() {
+                        value.push(escaped);
+                        self.position += 1;
+                    }
+                }
+                byte => value.push(byte),
+            }
+        }
+        String::from_utf8_lossy(&value).to_string()
+    }
+}
+
+/// The strongest challenge we know how to answer.
+pub fn pick(challenges: &[Challenge]) -> Option<&Challenge> {
+    let strength = |challenge: &Challenge| match challenge.scheme.as_str() {
+        "digest" => match digest_algorithm(challenge) {
+            Some(Algorithm::Sha256) => Some(3),
+            Some(Algorithm::Md5) => Some(2),
+            None => None,
+        },
+        "basic" => Some(1),
+        _ => None,
+    };
+
+    challenges
+        .iter()
+        .filter_map(|challenge| strength(challenge).map(|s| (s, challenge)))
+        .max_by_key(|(s, _)| *s)
+        .map(|(_, challenge)| challenge)
+}
+
+/// The `Authorization` header value that answers `challenge`. `uri` is the
+/// request target, path and query.
+pub fn authorization(
+    challenge: &Challenge,
+    credentials: &Credentials,
+    method: &str,
+    uri: &str,
+) -> Result<String, Box<dyn Error>> {
+    match challenge.scheme.as_str() {
+        "basic" => Ok(basic(credentials)),
+        "digest" => digest(challenge, credentials, method, uri, &cnonce()),
+        scheme => Err(format!("unsupported authentication scheme {scheme}").into()),
+    }
+}
+
+fn basic(credentials: &Credentials) -> String {
+    let Credentials { username, password } = credentials;
+    format!(
+        "Basic {}",
+        base64(format!("{username}:{password}").as_bytes())
+    )
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Algorithm {
+    Md5,
+    Sha256,
+}
+
+/// `None` for algorithms we can't compute, the `-sess` variants hash the same way.
+fn digest_algorithm(challenge: &Challenge) -> Option<Algorithm> {
+    let algorithm = challenge
+        .params
+        .get("algorithm")
+        .map(|a| a.to_uppercase())
+        .unwrap_or("MD5".to_string());
+
+    match algorithm.trim_end_matches("-SESS") {
+        "MD5" => Some(Algorithm::Md5),
+        "SHA-256" => Some(Algorithm::Sha256),
+        _ => None,
+    }
+}
+
+fn digest(
+    challenge: &Challenge,
+    credentials: &Credentials,
+    method: &str,
+    uri: &str,
+    cnonce: &str,
+) -> Result<String, Box<dyn Error>> {
+    let algorithm = digest_algorithm(challenge).ok_or("unsupported digest algorithm")?;
+    let hash = |data: String| match algorithm {
+        Algorithm::Md5 => hex(&md5(data.as_bytes())),
+        Algorithm::Sha256 => hex(&sha256(data.as_bytes())),
+    };
+
+    let realm = challenge.realm();
+    let nonce = challenge
+        .params
+        .get("nonce")
+        .ok_or("digest challenge without nonce")?;
+    let session = challenge
+        .params
+        .get("algorithm")
+        .is_some_and(|a| a.to_uppercase().ends_with("-SESS"));
+    // auth-int would need the body hashed, plain auth is all servers require in practice
+    let qop = match challenge.params.get("qop") {
+        Some(qop) if qop.split(',').any(|q| q.trim() == "auth") => Some("auth"),
+        Some(qop) => return Err(format!("unsupported digest qop {qop}").into()),
+        None => None,
+    };
+    let nc = "00000001";
+
+    let Credentials { username, password } = credentials;
+    let mut ha1 = hash(format!("{username}:{realm}:{password}"));
+    if session {
+        ha1 = hash(format!("{ha1}:{nonce}:{cnonce}"));
+    }
+    let ha2 = hash(format!("{method}:{uri}"));
+
+    let response = match qop {
+        Some(qop) => hash(format!("{ha1}:{nonce}:{nc}:{cnonce}:{qop}:{ha2}")),
+        None => hash(format!("{ha1}:{nonce}:{ha2}")),
+    };
+
+    let mut header = format!(
+        r#"Digest username="{}", realm="{}", uri="{}", nonce="{}", response="{response}""#,
+        quote(username),
+        quote(realm),
+        quote(uri),
+        quote(nonce)
+    );
+    if let Some(algorithm) = challenge.params.get("algorithm") {
+        header.push_str(&format!(", algorithm={algorithm}"));
+    }
+    if let Some(qop) = qop {
+        header.push_str(&format!(r#", qop={qop}, nc={nc}, cnonce="{cnonce}""#));
+    }
+    if let Some(opaque) = challenge.params.get("opaque") {
+        header.push_str(&format!(r#", opaque="{}""#, quote(opaque)));
+    }
+
+    Ok(header)
+}
+
+fn quote(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Random, so a server can't choose what the client hashes.
+fn cnonce() -> String {
+    hex(&random_bytes())[..32].to_string()
+}
+
+pub fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut out = String::new();
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, &b)| n | (b as u32) << (16 - 8 * i));
+
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+#[test]
+fn parse_multiple_challenges() {
+    let challenges = parse_challenges(
+        r#"Newauth realm="apps", type=1, title="Login to \"apps\"", Basic realm="simple""#,
+    );
+
+    assert_eq!(challenges.len(), 2);
+    assert_eq!(challenges[0].scheme, "newauth");
+    assert_eq!(challenges[0].params["title"], r#"Login to "apps""#);
+    assert_eq!(challenges[0].params["type"], "1");
+    assert_eq!(challenges[1].scheme, "basic");
+    assert_eq!(challenges[1].realm(), "simple");
+    assert_eq!(pick(&challenges), Some(&challenges[1]));
+}
+
+#[test]
+fn basic_credentials() {
+    // https://www.rfc-editor.org/rfc/rfc7617#section-2
+    let credentials = Credentials::from_userinfo("Aladdin:open%20sesame");
+    assert_eq!(basic(&credentials), "Basic QWxhZGRpbjpvcGVuIHNlc2FtZQ==");
+}
+
+#[test]
+fn digest_rfc_7616_example() {
+    // https://www.rfc-editor.org/rfc/rfc7616#section-3.9.1
+    let header = r#"Digest realm="http-auth@example.org", qop="auth, auth-int", algorithm=SHA-256, nonce="7ypf/xlj9XXwfDPEoM4URrv/xwf94BcCAzFZH4GiTo0v", opaque="FQhe/qaU925kfnzjCev0ciny7QMkPqMAFRtzCUYo5tdS", Digest realm="http-auth@example.org", qop="auth, auth-int", algorithm=MD5, nonce="7ypf/xlj9XXwfDPEoM4URrv/xwf94BcCAzFZH4GiTo0v", opaque="FQhe/qaU925kfnzjCev0ciny7QMkPqMAFRtzCUYo5tdS""#;
+    let challenges = parse_challenges(header);
+    let credentials = Credentials {
+        username: "Mufasa".to_string(),
+        password: "Circle of Life".to_string(),
+    };
+    let cnonce = "f2/wE4q74E6zIJEtWaHKaf5wv/H5QzzpXusqGemxURZJ";
+
+    let sha256 = pick(&challenges).unwrap();
+    assert_eq!(sha256.params["algorithm"], "SHA-256");
+    let answer = digest(sha256, &credentials, "GET", "/dir/index.html", cnonce).unwrap();
+    assert!(answer.contains(
+        r#"response="753927fa0e85d155564e2e272a28d1802ca10daf4496794697cf8db5856cb6c1""#
+    ));
+
+    let answer = digest(
+        &challenges[1],
+        &credentials,
+        "GET",
+        "/dir/index.html",
+        cnonce,
+    )
+    .unwrap();
+    assert!(answer.contains(r#"response="8ca523f5e9506fed4657c9700eebdbec""#));
+    assert!(answer.contains(r#"opaque="FQhe/qaU925kfnzjCev0ciny7QMkPqMAFRtzCUYo5tdS""#));
+}
@@ -0,0 +1,193 @@
+//! Reading HTTP/1.1 response heads and bodies off a connection incrementally.
+use std::error::Error;
+use std::io::{self, BufRead, ErrorKind, Read};
+
+use super::Headers;
+
+pub struct Head {
+    pub version: String,
+    pub status: u16,
+    pub explanation: String,
+    pub headers: Headers,
+}
+
+/// Reads the status line and headers, leaving the reader at the start of the body.
+pub fn read_head(reader: &mut impl BufRead) -> Result<Head, Box<dyn Error>> {
+    let status_line = read_line(reader)?;
+    let (version, status, explanation) = super::split_response_status_line(Some(&status_line))?;
+
+    let mut headers = Headers::new();
+
+    loop {
+        let line = read_line(reader)?;
+        if let Some((key, value)) = line.split_once(":") {
+            headers.insert(
+                key.trim().to_lowercase().to_string(),
+                value.trim().to_string(),
+            );
+        } else {
+            break;
+        }
+    }
+
+    Ok(Head {
+        version: version.to_string(),
+        status,
+        explanation: explanation.to_string(),
+        headers,
+    })
+}
+
+/// Picks how to find the end of the body from the status and headers.
+pub fn body_reader<R: BufRead + Send + 'static>(
+    reader: R,
+    head: &Head,
+) -> Result<Box<dyn Read + Send>, Box<dyn Error>> {
+    if head.headers.contains_key("content-encoding") {
+        return Err("body is encoded, dont know how to handle that".into());
+    }
+
+    if matches!(head.status, 100..200 | 204 | 304) {
+        return Ok(Box::new(io::empty()));
+    }
+
+    match head.headers.get("transfer-encoding") {
+        Some(encoding) if encoding.eq_ignore_ascii_case("chunked") => {
+            return Ok(Box::new(ChunkedReader::new(reader)));
+        }
+        Some(encoding) => return Err(format!("unsupported transfer encoding {encoding}").into()),
+        None => {}
+    }
+
+    match head.headers.get("content-length") {
+        Some(length) => Ok(Box::new(reader.take(length.parse()?))),
+        None => Ok(Box::new(UntilClose(reader))),
+    }
+}
+
+pub fn read_line(reader: &mut impl BufRead) -> Result<String, Box<dyn Error>> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Err("connection closed before the response head was complete".into());
+    }
+    Ok(line.trim_end_matches(['\r', '\n']).to_string())
+}
+
+/// A body without length that ends when the server closes the connection.
+pub struct UntilClose<R>(pub R);
+
+impl<R: Read> Read for UntilClose<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.0.read(buf) {
+            // harmless - the server closed without close_notify
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => Ok(0),
+            result => result,
+        }
+    }
+}
+
+/// Decodes `transfer-encoding: chunked`, https://www.rfc-editor.org/rfc/rfc9112#section-7.1
+pub struct ChunkedReader<R> {
+    inner: R,
+    /// Bytes left in the current chunk, `None` between chunks.
+    remaining: Option<u64>,
+    done: bool,
+}
+
+impl<R: BufRead> ChunkedReader<R> {
+    pub fn new(inner: R) -> Self {
+        ChunkedReader {
+            inner,
+            remaining: None,
+            done: false,
+        }
+    }
+
+    fn read_chunk_size(&mut self) -> io::Result<u64> {
+        let mut line = String::new();
+        self.inner.read_line(&mut line)?;
+
+        // chunk extensions after ';' are allowed and ignored
+        let size = line.split(';').next().unwrap_or("").trim();
+        u64::from_str_radix(size, 16).map_err(|_| {
+            io::Error::new(
+                ErrorKind::InvalidData,
+                format!("invalid chunk size {line:?}"),
+            )
+        })
+    }
+
+    fn skip_line(&mut self) -> io::Result<String> {
+        let mut line = String::new();
+        if self.inner.read_line(&mut line)? == 0 {
+            return Err(ErrorKind::UnexpectedEof.into());
+        }
+        Ok(line)
+    }
+}
+
+impl<R: BufRead> Read for ChunkedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            if self.done || buf.is_empty() {
+                return Ok(0);
+            }
+
+            match self.remaining {
+                None | Some(0) => {
+                    if self.remaining == Some(0) {
+                        // CRLF that ends the chunk data
+                        self.skip_line()?;
+                    }
+
+                    let size = self.read_chunk_size()?;
+                    if size == 0 {
+                        // trailers until the empty line
+                        while self.skip_line()?.trim() != "" {}
+                        self.done = true;
+                    } else {
+                        self.remaining = Some(size);
+                    }
+                }
+                Some(remaining) => {
+                    let max = buf.len().min(remaining as usize);
+                    let read = self.inner.read(&mut buf[..max])?;
+                    if read == 0 {
+                        return Err(ErrorKind::UnexpectedEof.into());
+                    }
+                    self.remaining = Some(remaining - read as u64);
+                    return Ok(read);
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn decode_chunked_body() {
+    let raw =
+        "4\r\nWiki\r\n7;ext=1\r\npedia i\r\nB\r\nn \r\nchunks.\r\n0\r\nExpires: never\r\n\r\n";
+    let mut decoded = String::new();
+    ChunkedReader::new(raw.as_bytes())
+        .read_to_string(&mut decoded)
+        .unwrap();
+
+    assert_eq!(decoded, "Wikipedia in \r\nchunks.");
+}
+
+#[test]
+fn head_is_read_before_the_body() {
+    let raw = "HTTP/1.1 200 OK\r\nContent-Type: text/html\r\nContent-Length: 5\r\n\r\nhelloextra";
+    let mut reader = io::BufReader::new(raw.as_bytes());
+
+    let head = read_head(&mut reader).unwrap();
+    assert_eq!(head.status, 200);
+    assert_eq!(head.headers.get("content-type").unwrap(), "text/html");
+
+    let mut body = String::new();
+    body_reader(reader, &head)
+        .unwrap()
+        .read_to_string(&mut body)
+        .unwrap();
+    assert_eq!(body, "hello");
+}
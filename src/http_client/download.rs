@@ -0,0 +1,243 @@
+//! Saving responses that aren't pages to disk. Bytes go to a `.part` file next
+//! to a small `.part.meta` file naming the url and validator they came from, so
+//! an interrupted download can continue with a range request.
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::Headers;
+use crate::url::{URL, percent_decode};
+
+/// Anything the browser can't show is saved instead, so is anything the server
+/// asks us to save.
+pub fn is_download(headers: &Headers) -> bool {
+    if let Some(disposition) = headers.get("content-disposition")
+        && disposition
+            .split(';')
+            .next()
+            .is_some_and(|kind| kind.trim().eq_ignore_ascii_case("attachment"))
+    {
+        return true;
+    }
+
+    match headers.get("content-type") {
+        Some(content_type) => {
+            let mime = content_type
+                .split(';')
+                .next()
+                .unwrap_or("")
+                .trim()
+                .to_lowercase();
+            !(mime.starts_with("text/") || mime == "application/xhtml+xml")
+        }
+        // no type, the server most likely meant a page
+        None => false,
+    }
+}
+
+/// `filename` from `Content-Disposition` if there is one, otherwise the last
+/// segment of the url path.
+pub fn file_name(url: &URL, headers: &Headers) -> String {
+    let from_disposition = headers.get("content-disposition").and_then(|disposition| {
+        let params: Vec<(String, String)> = disposition
+            .split(';')
+            .skip(1)
+            .filter_map(|param| param.split_once('='))
+            .map(|(name, value)| (name.trim().to_lowercase(), value.trim().to_string()))
+            .collect();
+
+        // RFC 6266 prefers the extended `filename*=UTF-8''name` when both are given
+        let extended = params
+            .iter()
+            .find(|(name, _)| name == "filename*")
+            .and_then(|(_, value)| value.split_once("''"))
+            .map(|(_, encoded)| percent_decode(encoded));
+        let plain = params
+            .iter()
+            .find(|(name, _)| name == "filename")
+            .map(|(_, value)| value.trim_matches('"').to_string());
+
+        extended.or(plain)
+    });
+
+    let from_path = url
+        .path
+        .rsplit('/')
+        .next()
+        .map(percent_decode)
+        .filter(|segment| !segment.is_empty());
+
+    let name = from_disposition
+        .or(from_path)
+        .unwrap_or("download".to_string());
+
+    // never let the server pick a path outside the downloads directory
+    let name: String = name
+        .chars()
+        .map(|c| {
+            if matches!(c, '/' | '\\') || c.is_control() {
+                '_'
+            } else {
+                c
+            }
+        })
+        .collect();
+    match name.trim_start_matches('.') {
+        "" => "download".to_string(),
+        name => name.to_string(),
+    }
+}
+
+/// What the server calls this version of the resource, sent back as
+/// `If-Range` so we only get a range of the same bytes.
+pub fn validator(headers: &Headers) -> Option<String> {
+    // weak etags are not allowed in If-Range
+    match headers.get("etag") {
+        Some(etag) if !etag.starts_with("W/") => Some(etag.clone()),
+        _ => headers.get("last-modified").cloned(),
+    }
+}
+
+/// `Content-Range: bytes 100-999/1000` as the first byte and the total length.
+pub fn content_range(headers: &Headers) -> Result<(u64, Option<u64>), Box<dyn Error>> {
+    let value = headers
+        .get("content-range")
+        .ok_or("206 response without content-range")?;
+    let range = value
+        .strip_prefix("bytes ")
+        .ok_or(format!("unsupported content-range {value}"))?;
+    let (span, total) = range
+        .split_once('/')
+        .ok_or(format!("invalid content-range {value}"))?;
+    let (first, _) = span
+        .split_once('-')
+        .ok_or(format!("invalid content-range {value}"))?;
+
+    let total = match total {
+        "*" => None,
+        total => Some(total.parse()?),
+    };
+    Ok((first.parse()?, total))
+}
+
+/// Bytes from an earlier attempt at downloading `url` into `dir`.
+pub struct Partial {
+    pub path: PathBuf,
+    meta_path: PathBuf,
+}
+
+impl Partial {
+    pub fn new(dir: &Path, name: &str) -> Partial {
+        Partial {
+            path: dir.join(format!("{name}.part")),
+            meta_path: dir.join(format!("{name}.part.meta")),
+        }
+    }
+
+    /// How far the earlier attempt got, if it was for the same url and the
+    /// server gave a validator to resume against.
+    pub fn resumable(&self, url: &URL) -> Option<(u64, String)> {
+        let meta = fs::read_to_string(&self.meta_path).ok()?;
+        let (saved_url, validator) = meta.split_once('\n')?;
+        if saved_url != url.to_string() || validator.trim().is_empty() {
+            return None;
+        }
+
+        let length = fs::metadata(&self.path).ok()?.len();
+        (length > 0).then(|| (length, validator.trim().to_string()))
+    }
+
+    pub fn start(&self, url: &URL, validator: Option<&str>) -> std::io::Result<()> {
+        fs::write(
+            &self.meta_path,
+            format!("{url}\n{}\n", validator.unwrap_or("")),
+        )
+    }
+
+    /// Moves the finished file to `target`, or next to it if that name is taken.
+    pub fn finish(self, target: PathBuf) -> std::io::Result<PathBuf> {
+        let target = free_path(target);
+        fs::rename(&self.path, &target)?;
+        let _ = fs::remove_file(&self.meta_path);
+        Ok(target)
+    }
+}
+
+/// `name.zip`, then `name (1).zip`, `name (2).zip` and so on.
+fn free_path(target: PathBuf) -> PathBuf {
+    if !target.exists() {
+        return target;
+    }
+
+    let stem = target
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let extension = target
+        .extension()
+        .map(|e| format!(".{}", e.to_string_lossy()))
+        .unwrap_or_default();
+
+    (1..)
+        .map(|n| target.with_file_name(format!("{stem} ({n}){extension}")))
+        .find(|candidate| !candidate.exists())
+        .expect("some name is free")
+}
+
+#[test]
+fn downloads_are_told_apart_from_pages() {
+    let headers = |pairs: &[(&str, &str)]| -> Headers {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    };
+
+    assert!(!is_download(&headers(&[(
+        "content-type",
+        "text/html; charset=utf-8"
+    )])));
+    assert!(!is_download(&headers(&[])));
+    assert!(is_download(&headers(&[(
+        "content-type",
+        "application/zip"
+    )])));
+    assert!(is_download(&headers(&[
+        ("content-type", "text/csv"),
+        ("content-disposition", "attachment; filename=report.csv")
+    ])));
+}
+
+#[test]
+fn file_names() {
+    let url = URL::parse("https://example.com/files/a%20b.tar.gz").unwrap();
+    let headers = |disposition: &str| -> Headers {
+        Headers::from([("content-disposition".to_string(), disposition.to_string())])
+    };
+
+    assert_eq!(file_name(&url, &Headers::new()), "a b.tar.gz");
+    assert_eq!(
+        file_name(&url, &headers(r#"attachment; filename="report.pdf""#)),
+        "report.pdf"
+    );
+    assert_eq!(
+        file_name(
+            &url,
+            &headers(r#"attachment; filename="x.txt"; filename*=UTF-8''%E2%82%AC%20rates.txt"#)
+        ),
+        "€ rates.txt"
+    );
+    assert_eq!(
+        file_name(&url, &headers(r#"attachment; filename="../../.bashrc""#)),
+        "_.._.bashrc"
+    );
+}
+
+#[test]
+fn parse_content_range() {
+    let headers = Headers::from([(
+        "content-range".to_string(),
+        "bytes 100-999/1000".to_string(),
+    )]);
+    assert_eq!(content_range(&headers).unwrap(), (100, Some(1000)));
+}
@@ -0,0 +1,319 @@
+//! Server-Sent Events, the `text/event-stream` format and reconnection rules
+//! from https://html.spec.whatwg.org/multipage/server-sent-events.html.
+//! A background thread keeps a streaming response open, parses events as the
+//! lines arrive and sends them over a channel. When the connection drops it
+//! waits a bit and asks again with `Last-Event-ID` so nothing is missed.
+use std::io::{self, BufRead, BufReader};
+use std::sync::Arc;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+use std::time::Duration;
+
+use super::{Client, Headers};
+
+/// How long to wait before reconnecting until the server says otherwise.
+const DEFAULT_RETRY: Duration = Duration::from_secs(3);
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Event {
+    /// `message` unless the server named it with `event:`.
+    pub event_type: String,
+    pub data: String,
+    /// The last `id:` seen, on this event or an earlier one.
+    pub last_event_id: String,
+}
+
+pub struct EventSource {
+    /// Disconnects when the server can't be reached anymore, answered with
+    /// something that isn't an event stream, or asked us to stop with `204`.
+    pub events: Receiver<Event>,
+}
+
+impl EventSource {
+    /// Starts listening to `url` on a background thread. Dropping the
+    /// `EventSource` stops it once the next event arrives.
+    pub fn open(client: Arc<Client>, url: String) -> EventSource {
+        let (sender, events) = mpsc::channel();
+
+        thread::Builder::new()
+            .name("event-source".to_string())
+            .spawn(move || listen(&client, &url, sender))
+            .expect("could not start event source thread");
+
+        EventSource { events }
+    }
+}
+
+fn listen(client: &Client, url: &str, events: Sender<Event>) {
+    let mut parser = Parser::default();
+
+    loop {
+        let mut headers = Headers::from([
+            ("accept".to_string(), "text/event-stream".to_string()),
+            ("cache-control".to_string(), "no-cache".to_string()),
+        ]);
+        if !parser.last_event_id.is_empty() {
+            headers.insert("last-event-id".to_string(), parser.last_event_id.clone());
+        }
+
+        match client.get_streaming(url.to_string(), Some(headers)) {
+            Ok(response) if response.status == 204 => {
+                log::info!("{url} asked for no more events");
+                return;
+            }
+            Ok(response) => {
+                let content_type = response
+                    .headers
+                    .get("content-type")
+                    .and_then(|value| value.split(';').next())
+                    .map(|mime| mime.trim().to_lowercase());
+                if response.status != 200 || content_type.as_deref() != Some("text/event-stream") {
+                    log::warn!(
+                        "{url} is not an event stream: {} {content_type:?}",
+                        response.status
+                    );
+                    return;
+                }
+
+                parser.reconnected();
+                for line in Lines::new(BufReader::new(response)) {
+                    let line = match line {
+                        Ok(line) => line,
+                        Err(e) => {
+                            log::debug!("event stream {url} broke off: {e}");
+                            break;
+                        }
+                    };
+                    if let Some(event) = parser.line(&line)
+                        && events.send(event).is_err()
+                    {
+                        // nobody is listening anymore
+                        return;
+                    }
+                }
+            }
+            Err(e) => log::debug!("could not connect to event stream {url}: {e}"),
+        }
+
+        let retry = parser.retry.unwrap_or(DEFAULT_RETRY);
+        log::debug!("reconnecting to {url} in {retry:?}");
+        thread::sleep(retry);
+    }
+}
+
+/// Collects fields until a blank line dispatches them as an event.
+#[derive(Debug, Default)]
+pub struct Parser {
+    data: String,
+    event_type: String,
+    last_event_id: String,
+    /// From `retry:`, how long to wait before reconnecting.
+    pub retry: Option<Duration>,
+}
+
+impl Parser {
+    pub fn line(&mut self, line: &str) -> Option<Event> {
+        if line.is_empty() {
+            return self.dispatch();
+        }
+        if line.starts_with(':') {
+            // a comment, often sent to keep the connection alive
+            return None;
+        }
+
+        let (field, value) = match line.split_once(':') {
+            Some((field, value)) => (field, value.strip_prefix(' ').unwrap_or(value)),
+            None => (line, ""),
+        };
+
+        match field {
+            "event" => self.event_type = value.to_string(),
+            "data" => {
+                self.data.push_str(value);
+                self.data.push('\n');
+            }
+            "id" if !value.contains('\0') => self.last_event_id = value.to_string(),
+            "retry" if !value.is_empty() && value.bytes().all(|b| b.is_ascii_digit()) => {
+                if let Ok(millis) = value.parse() {
+                    self.retry = Some(Duration::from_millis(millis));
+                }
+            }
+            _ => log::trace!("ignoring event stream field {field}"),
+        }
+        None
+    }
+
+    /// A half received event is dropped when the connection is made again,
+    /// the last event id and retry time are kept.
+    fn reconnected(&mut self) {
+        self.data.clear();
+        self.event_type.clear();
+    }
+
+    fn dispatch(&mut self) -> Option<Event> {
+        let event_type = std::mem::take(&mut self.event_type);
+        if self.data.is_empty() {
+            return None;
+        }
+
+        let mut data = std::mem::take(&mut self.data);
+        data.pop();
+        Some(Event {
+            event_type: match event_type.is_empty() {
+                true => "message".to_string(),
+                false => event_type,
+            },
+            data,
+            last_event_id: self.last_event_id.clone(),
+        })
+    }
+}
+
+/// Splits the stream on CRLF, LF or CR alone, without the byte order mark
+/// the stream may start with. A line cut off by the end of the stream is
+/// dropped since it may not be complete.
+struct Lines<R> {
+    reader: R,
+    /// The last line ended in CR, so an LF right after it belongs to it.
+    after_cr: bool,
+    first: bool,
+}
+
+impl<R: BufRead> Lines<R> {
+    fn new(reader: R) -> Lines<R> {
+        Lines {
+            reader,
+            after_cr: false,
+            first: true,
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for Lines<R> {
+    type Item = io::Result<String>;
+
+    fn next(&mut self) -> Option<io::Result<String>> {
+        let mut line = Vec::new();
+        loop {
+            let buffer = match self.reader.fill_buf() {
+                Ok([]) => return None,
+                Ok(buffer) => buffer,
+                Err(e) => return Some(Err(e)),
+            };
+
+            if self.after_cr {
+                self.after_cr = false;
+                if buffer[0] == b'\n' {
+                    self.reader.consume(1);
+                    continue;
+                }
+            }
+
+            match buffer.iter().position(|&b| b == b'\n' || b == b'\r') {
+                Some(end) => {
+                    line.extend_from_slice(&buffer[..end]);
+                    self.after_cr = buffer[end] == b'\r';
+                    self.reader.consume(end + 1);
+                    break;
+                }
+                None => {
+                    line.extend_from_slice(buffer);
+                    let length = buffer.len();
+                    self.reader.consume(length);
+                }
+            }
+        }
+
+        let mut line = String::from_utf8_lossy(&line).to_string();
+        if std::mem::take(&mut self.first) && line.starts_with('\u{feff}') {
+            line.remove(0);
+        }
+        Some(Ok(line))
+    }
+}
+
+/// Parses a whole stream, for tests.
+#[cfg(test)]
+fn parse(stream: &str) -> Vec<Event> {
+    let mut parser = Parser::default();
+    Lines::new(stream.as_bytes())
+        .filter_map(|line| parser.line(&line.unwrap()))
+        .collect()
+}
+
+#[test]
+fn parse_fields() {
+    let event = |event_type: &str, data: &str, id: &str| Event {
+        event_type: event_type.to_string(),
+        data: data.to_string(),
+        last_event_id: id.to_string(),
+    };
+
+    assert_eq!(
+        parse("\u{feff}: ping\ndata: YHOO\ndata: +2\ndata\n\nid: 1\nevent: add\ndata:10\n\n"),
+        vec![event("message", "YHOO\n+2\n", ""), event("add", "10", "1")]
+    );
+    assert_eq!(
+        parse("data:  two spaces\r\rid\r\ndata: x\r\n\r\ndata: unfinished\n"),
+        vec![
+            event("message", " two spaces", ""),
+            event("message", "x", "")
+        ]
+    );
+    // only `data` makes an event, and an id with NUL in it is ignored
+    assert_eq!(
+        parse("event: empty\n\nid: 7\0\ndata: y\n\n"),
+        vec![event("message", "y", "")]
+    );
+}
+
+#[test]
+fn retry_takes_only_digits() {
+    let mut parser = Parser::default();
+    parser.line("retry: 1.5");
+    assert_eq!(parser.retry, None);
+    parser.line("retry: 250");
+    assert_eq!(parser.retry, Some(Duration::from_millis(250)));
+}
+
+#[test]
+fn reconnects_with_the_last_event_id() {
+    use super::test_server::{Reply, TestServer};
+
+    let stream =
+        |body: &str| Reply::status(200, "OK", &[("Content-Type", "text/event-stream")], body);
+    let server = TestServer::builder()
+        .route_fn("/events", move |request| {
+            match request.headers.get("last-event-id").map(String::as_str) {
+                None => stream(
+                    "retry: 10\nid: 1\ndata: one\n\nevent: update\ndata: a\ndata: b\nid: 2\n\n",
+                ),
+                Some("2") => stream(": still there\r\nid: 3\r\ndata: three\r\n\r\n"),
+                Some(_) => Reply::status(204, "No Content", &[], ""),
+            }
+        })
+        .http();
+    let client = Arc::new(Client::new(&server.tls_config()).unwrap());
+
+    let source = EventSource::open(client, server.url("/events"));
+    let events: Vec<(String, String, String)> = source
+        .events
+        .iter()
+        .map(|event| (event.event_type, event.data, event.last_event_id))
+        .collect();
+
+    let owned = |parts: [&str; 3]| parts.map(str::to_string).into();
+    assert_eq!(
+        events,
+        vec![
+            owned(["message", "one", "1"]),
+            owned(["update", "a\nb", "2"]),
+            owned(["message", "three", "3"]),
+        ]
+    );
+
+    let received = server.received();
+    assert_eq!(received.len(), 3);
+    assert_eq!(received[0].headers["accept"], "text/event-stream");
+    assert_eq!(received[1].headers["last-event-id"], "2");
+}
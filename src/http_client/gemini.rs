@@ -0,0 +1,298 @@
+//! The Gemini protocol, https://geminiprotocol.net/docs/protocol-specification.gmi.
+//! A request is the url on one line over TLS, the response is a status, one
+//! line of meta and the body. Servers mostly use self-signed certificates, so
+//! instead of checking them against roots the first certificate seen for a host
+//! is pinned and a different one later is refused.
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::{CryptoProvider, verify_tls12_signature, verify_tls13_signature};
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::{ClientConfig, DigitallySignedStruct, SignatureScheme};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::fs::{self, OpenOptions};
+use std::io::{BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use super::har::Timings;
+use super::hash::{hex, sha256};
+use super::{Headers, Method, Request, StreamingResponse, body};
+
+/// Longest url a server has to accept.
+const MAX_REQUEST_LENGTH: usize = 1024;
+
+/// The server asked for a line of text, to be sent back as the query of the
+/// same url. Gopher search items ask the same way.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InputRequired {
+    pub url: String,
+    pub prompt: String,
+    /// Status 11, the input should not be shown while it is typed.
+    pub sensitive: bool,
+}
+
+impl Display for InputRequired {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        write!(f, "{} asks for input: {}", self.url, self.prompt)
+    }
+}
+
+impl Error for InputRequired {}
+
+/// Certificate fingerprints of the hosts seen so far, optionally kept in a
+/// file so they are remembered between runs.
+#[derive(Debug, Default)]
+pub struct KnownHosts {
+    fingerprints: Mutex<HashMap<String, String>>,
+    file: Option<PathBuf>,
+}
+
+impl KnownHosts {
+    /// Reads `host fingerprint` lines from `path` if it exists, new hosts are
+    /// appended to it.
+    pub fn with_file(mut self, path: &Path) -> Result<KnownHosts, Box<dyn Error>> {
+        if path.exists() {
+            let known = fs::read_to_string(path)
+                .map_err(|e| format!("could not read {}: {e}", path.display()))?;
+            let mut fingerprints = self.fingerprints.lock().unwrap();
+            for line in known.lines() {
+                if let Some((host, fingerprint)) = line.trim().split_once(' ') {
+                    fingerprints.insert(host.to_string(), fingerprint.trim().to_string());
+                }
+            }
+        }
+
+        self.file = Some(path.to_path_buf());
+        Ok(self)
+    }
+
+    /// Pins `fingerprint` if the host is new, otherwise checks it is the pinned one.
+    fn check(&self, host: &str, fingerprint: &str) -> bool {
+        let mut fingerprints = self.fingerprints.lock().unwrap();
+        match fingerprints.get(host) {
+            Some(pinned) => pinned == fingerprint,
+            None => {
+                log::info!("trusting the certificate of {host} on first use: {fingerprint}");
+                fingerprints.insert(host.to_string(), fingerprint.to_string());
+                if let Some(path) = &self.file {
+                    let appended = OpenOptions::new()
+                        .create(true)
+                        .append(true)
+                        .open(path)
+                        .and_then(|mut file| writeln!(file, "{host} {fingerprint}"));
+                    if let Err(e) = appended {
+                        log::warn!("could not remember {host} in {}: {e}", path.display());
+                    }
+                }
+                true
+            }
+        }
+    }
+}
+
+/// TLS for gemini, checking certificates against `known_hosts` only.
+pub fn client_config(known_hosts: Arc<KnownHosts>) -> ClientConfig {
+    ClientConfig::builder()
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(TrustOnFirstUse {
+            known_hosts,
+            provider: rustls::crypto::aws_lc_rs::default_provider(),
+        }))
+        .with_no_client_auth()
+}
+
+#[derive(Debug)]
+struct TrustOnFirstUse {
+    known_hosts: Arc<KnownHosts>,
+    provider: CryptoProvider,
+}
+
+impl ServerCertVerifier for TrustOnFirstUse {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let host = server_name.to_str();
+        let fingerprint = hex(&sha256(end_entity));
+
+        match self.known_hosts.check(&host, &fingerprint) {
+            true => Ok(ServerCertVerified::assertion()),
+            false => {
+                log::warn!("the certificate of {host} changed, it is now {fingerprint}");
+                Err(rustls::Error::InvalidCertificate(
+                    rustls::CertificateError::ApplicationVerificationFailure,
+                ))
+            }
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
+
+/// Sends the request line and reads the response header. The status goes in
+/// `status` as is, successful responses get their mime type as `content-type`
+/// and redirects their target as `location`, so the rest of the client can
+/// treat it like any other response.
+pub fn exchange(
+    mut stream: impl Read + Write + Send + 'static,
+    request: Request,
+) -> Result<StreamingResponse, Box<dyn Error>> {
+    let line = format!("{}\r\n", request.url);
+    if line.len() > MAX_REQUEST_LENGTH + 2 {
+        return Err("gemini urls can be at most 1024 bytes".into());
+    }
+    log::trace!("gemini request: {}", line.trim_end());
+
+    let sending = Instant::now();
+    stream.write_all(line.as_bytes())?;
+    stream.flush()?;
+    let send = sending.elapsed();
+
+    let waiting = Instant::now();
+    let mut reader = BufReader::new(stream);
+    let header = body::read_line(&mut reader)?;
+    let wait = waiting.elapsed();
+
+    let (status, meta) = parse_header(&header)?;
+    log::debug!("gemini {status} {meta}");
+
+    let mut headers = Headers::new();
+    match status {
+        20..30 => {
+            let mime = match meta.is_empty() {
+                true => "text/gemini; charset=utf-8",
+                false => meta.as_str(),
+            };
+            headers.insert("content-type".to_string(), mime.to_string());
+        }
+        30..40 => {
+            headers.insert("location".to_string(), meta.clone());
+        }
+        _ => {}
+    }
+
+    Ok(StreamingResponse {
+        status,
+        version: "gemini".to_string(),
+        explanation: meta,
+        headers,
+        request,
+        body: Box::new(body::UntilClose(reader)),
+        bytes_read: 0,
+        recording: None,
+        timings: Timings {
+            send,
+            wait,
+            ..Timings::default()
+        },
+    })
+}
+
+/// `<two digit status><space><meta>`, meta may be missing.
+fn parse_header(header: &str) -> Result<(u16, String), Box<dyn Error>> {
+    let (status, meta) = header.split_once(' ').unwrap_or((header, ""));
+    if status.len() != 2 || !status.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(format!("invalid gemini response header {header:?}").into());
+    }
+
+    Ok((status.parse()?, meta.trim().to_string()))
+}
+
+/// Follows redirects, asks for input and turns failures into errors.
+pub fn next_request(response: &StreamingResponse) -> Result<Option<Request>, Box<dyn Error>> {
+    let meta = &response.explanation;
+    let url = response.request.url.to_string();
+
+    match response.status {
+        10 | 11 => Err(Box::new(InputRequired {
+            url,
+            prompt: meta.clone(),
+            sensitive: response.status == 11,
+        })),
+        20..30 => Ok(None),
+        30..40 => {
+            let target = response.request.url.resolve(meta)?;
+            if target.scheme != "gemini" {
+                return Err(format!("{url} redirects to another protocol: {target}").into());
+            }
+            Ok(Some(Request {
+                method: Method::GET,
+                url: target,
+                headers: None,
+                body: None,
+            }))
+        }
+        40..50 => Err(format!("temporary failure {}: {meta}", response.status).into()),
+        50..60 => Err(format!("permanent failure {}: {meta}", response.status).into()),
+        60..70 => Err(format!(
+            "a client certificate is required ({}): {meta}",
+            response.status
+        )
+        .into()),
+        status => Err(format!("unknown gemini status {status}").into()),
+    }
+}
+
+#[test]
+fn parse_response_headers() {
+    assert_eq!(
+        parse_header("20 text/gemini; lang=en").unwrap(),
+        (20, "text/gemini; lang=en".to_string())
+    );
+    assert_eq!(parse_header("51").unwrap(), (51, String::new()));
+    assert!(parse_header("2 text/gemini").is_err());
+    assert!(parse_header("HTTP/1.1 200 OK").is_err());
+}
+
+#[test]
+fn certificates_are_pinned_on_first_use() {
+    let path = std::env::temp_dir().join(format!("known-hosts-{}", std::process::id()));
+    let _ = fs::remove_file(&path);
+
+    let known_hosts = KnownHosts::default().with_file(&path).unwrap();
+    assert!(known_hosts.check("example.org", "aa"));
+    assert!(known_hosts.check("example.org", "aa"));
+    assert!(!known_hosts.check("example.org", "bb"));
+
+    let reloaded = KnownHosts::default().with_file(&path).unwrap();
+    assert!(!reloaded.check("example.org", "bb"));
+    fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,108 @@
+//! The Gopher protocol, https://www.rfc-editor.org/rfc/rfc1436, with urls as
+//! in https://www.rfc-editor.org/rfc/rfc4266. The path of a url is the item
+//! type followed by the selector, `gopher://host/0/about.txt` is the text file
+//! `/about.txt`. The selector goes to the server on a line by itself and
+//! whatever comes back until the connection closes is the item.
+use std::error::Error;
+use std::io::{BufReader, Read, Write};
+use std::time::Instant;
+
+use super::gemini::InputRequired;
+use super::har::Timings;
+use super::{Headers, Request, StreamingResponse, body};
+use crate::url::percent_decode;
+
+/// What menus are served as, see `gophermap` for how they are shown.
+pub const MENU_MIME_TYPE: &str = "text/x-gopher-menu";
+
+/// Item type and selector from the url path, with the search words of a
+/// type 7 item after `%09` or `?`.
+fn item(path: &str) -> (char, String, Option<String>) {
+    let path = percent_decode(path.strip_prefix('/').unwrap_or(path));
+    let mut chars = path.chars();
+    let Some(item_type) = chars.next() else {
+        // the root of a server is always a menu
+        return ('1', String::new(), None);
+    };
+
+    let selector = chars.as_str();
+    let split = match item_type {
+        '7' => selector
+            .split_once('\t')
+            .or_else(|| selector.split_once('?')),
+        _ => selector.split_once('\t'),
+    };
+    match split {
+        Some((selector, search)) => (item_type, selector.to_string(), Some(search.to_string())),
+        None => (item_type, selector.to_string(), None),
+    }
+}
+
+fn mime_type(item_type: char) -> &'static str {
+    match item_type {
+        '0' => "text/plain",
+        '1' | '7' => MENU_MIME_TYPE,
+        'h' => "text/html",
+        'g' => "image/gif",
+        'I' => "image/x-unknown",
+        _ => "application/octet-stream",
+    }
+}
+
+pub fn exchange(
+    mut stream: impl Read + Write + Send + 'static,
+    request: Request,
+) -> Result<StreamingResponse, Box<dyn Error>> {
+    let (item_type, selector, search) = item(&request.url.path);
+
+    let line = match (item_type, search) {
+        ('7', None) => {
+            return Err(Box::new(InputRequired {
+                prompt: format!("Search {}", request.url.host),
+                url: request.url.to_string(),
+                sensitive: false,
+            }));
+        }
+        (_, Some(search)) => format!("{selector}\t{search}\r\n"),
+        (_, None) => format!("{selector}\r\n"),
+    };
+    log::trace!("gopher request: {}", line.trim_end());
+
+    let sending = Instant::now();
+    stream.write_all(line.as_bytes())?;
+    stream.flush()?;
+    let send = sending.elapsed();
+
+    Ok(StreamingResponse {
+        status: 200,
+        version: "gopher".to_string(),
+        explanation: "OK".to_string(),
+        headers: Headers::from([("content-type".to_string(), mime_type(item_type).to_string())]),
+        request,
+        body: Box::new(body::UntilClose(BufReader::new(stream))),
+        bytes_read: 0,
+        recording: None,
+        timings: Timings {
+            send,
+            ..Timings::default()
+        },
+    })
+}
+
+#[test]
+fn items_from_paths() {
+    assert_eq!(item("/"), ('1', String::new(), None));
+    assert_eq!(item(""), ('1', String::new(), None));
+    assert_eq!(
+        item("/0/about%20me.txt"),
+        ('0', "/about me.txt".to_string(), None)
+    );
+    assert_eq!(
+        item("/7/search%09rust"),
+        ('7', "/search".to_string(), Some("rust".to_string()))
+    );
+    assert_eq!(
+        item("/7/search?rust%20lang"),
+        ('7', "/search".to_string(), Some("rust lang".to_string()))
+    );
+}
@@ -0,0 +1,263 @@
+//! Records every exchange the client makes and writes them out as a HAR 1.2
+//! file, http://www.softwareishard.com/blog/har-12-spec/
+use std::io::{self, Read};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+#[derive(Debug, Clone, Default)]
+pub struct Timings {
+    pub connect: Duration,
+    pub send: Duration,
+    pub wait: Duration,
+    pub receive: Duration,
+}
+
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub started: SystemTime,
+    pub method: String,
+    pub url: String,
+    pub http_version: String,
+    pub request_headers: Vec<(String, String)>,
+    pub request_body_size: usize,
+    pub status: u16,
+    pub status_text: String,
+    pub response_headers: Vec<(String, String)>,
+    pub body_size: u64,
+    /// Where a redirect pointed, this is how HAR links the hops of a chain.
+    pub redirect_url: String,
+    pub timings: Timings,
+}
+
+#[derive(Debug, Default)]
+pub struct HarRecorder {
+    entries: Mutex<Vec<Entry>>,
+}
+
+impl HarRecorder {
+    pub fn record(&self, entry: Entry) {
+        log::debug!("recorded {} {} {}", entry.method, entry.url, entry.status);
+        self.entries.lock().unwrap().push(entry);
+    }
+
+    pub fn write_to(&self, path: &Path) -> io::Result<()> {
+        std::fs::write(path, self.to_json())
+    }
+
+    pub fn to_json(&self) -> String {
+        let entries: Vec<String> = self
+            .entries
+            .lock()
+            .unwrap()
+            .iter()
+            .map(entry_json)
+            .collect();
+
+        format!(
+            r#"{{"log":{{"version":"1.2","creator":{{"name":"browser-engineering","version":{}}},"entries":[{}]}}}}"#,
+            json_string(env!("CARGO_PKG_VERSION")),
+            entries.join(",")
+        )
+    }
+}
+
+/// Wraps a response body and records the entry once the body has been read to
+/// the end, or dropped half way.
+pub struct RecordingBody {
+    inner: Box<dyn Read + Send>,
+    recorder: Arc<HarRecorder>,
+    entry: Option<Entry>,
+    receive_started: Instant,
+}
+
+impl RecordingBody {
+    pub fn new(
+        inner: Box<dyn Read + Send>,
+        recorder: Arc<HarRecorder>,
+        entry: Entry,
+    ) -> RecordingBody {
+        RecordingBody {
+            inner,
+            recorder,
+            entry: Some(entry),
+            receive_started: Instant::now(),
+        }
+    }
+
+    fn finish(&mut self) {
+        if let Some(mut entry) = self.entry.take() {
+            entry.timings.receive = self.receive_started.elapsed();
+            self.recorder.record(entry);
+        }
+    }
+}
+
+impl Read for RecordingBody {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        match self.entry.as_mut() {
+            Some(entry) if read > 0 => entry.body_size += read as u64,
+            Some(_) => self.finish(),
+            None => {}
+        }
+        Ok(read)
+    }
+}
+
+impl Drop for RecordingBody {
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
+
+fn entry_json(entry: &Entry) -> String {
+    let Timings {
+        connect,
+        send,
+        wait,
+        receive,
+    } = &entry.timings;
+    let total = *connect + *send + *wait + *receive;
+
+    let mime_type = entry
+        .response_headers
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case("content-type"))
+        .map(|(_, value)| value.as_str())
+        .unwrap_or("");
+
+    let request = format!(
+        r#"{{"method":{},"url":{},"httpVersion":{},"cookies":[],"headers":{},"queryString":[],"headersSize":-1,"bodySize":{}}}"#,
+        json_string(&entry.method),
+        json_string(&entry.url),
+        json_string(&entry.http_version),
+        headers_json(&entry.request_headers),
+        entry.request_body_size
+    );
+
+    let response = format!(
+        r#"{{"status":{},"statusText":{},"httpVersion":{},"cookies":[],"headers":{},"content":{{"size":{},"mimeType":{}}},"redirectURL":{},"headersSize":-1,"bodySize":{}}}"#,
+        entry.status,
+        json_string(&entry.status_text),
+        json_string(&entry.http_version),
+        headers_json(&entry.response_headers),
+        entry.body_size,
+        json_string(mime_type),
+        json_string(&entry.redirect_url),
+        entry.body_size
+    );
+
+    format!(
+        r#"{{"startedDateTime":{},"time":{},"request":{},"response":{},"cache":{{}},"timings":{{"blocked":-1,"dns":-1,"ssl":-1,"connect":{},"send":{},"wait":{},"receive":{}}}}}"#,
+        json_string(&iso_8601(entry.started)),
+        millis(total),
+        request,
+        response,
+        millis(*connect),
+        millis(*send),
+        millis(*wait),
+        millis(*receive)
+    )
+}
+
+fn headers_json(headers: &[(String, String)]) -> String {
+    let headers: Vec<String> = headers
+        .iter()
+        .map(|(name, value)| {
+            format!(
+                r#"{{"name":{},"value":{}}}"#,
+                json_string(name),
+                json_string(value)
+            )
+        })
+        .collect();
+
+    format!("[{}]", headers.join(","))
+}
+
+fn millis(duration: Duration) -> String {
+    format!("{:.3}", duration.as_secs_f64() * 1000.0)
+}
+
+fn json_string(value: &str) -> String {
+    let mut out = String::with_capacity(value.len() + 2);
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// UTC timestamp like `2009-07-24T19:20:30.450Z`.
+fn iso_8601(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let seconds = since_epoch.as_secs();
+    let (days, seconds_of_day) = (seconds / 86_400, seconds % 86_400);
+
+    // civil_from_days, https://howardhinnant.github.io/date_algorithms.html
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}.{:03}Z",
+        seconds_of_day / 3600,
+        seconds_of_day % 3600 / 60,
+        seconds_of_day % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+#[test]
+fn timestamps_are_iso_8601() {
+    let time = UNIX_EPOCH + Duration::from_millis(1_248_463_230_450);
+    assert_eq!(iso_8601(time), "2009-07-24T19:20:30.450Z");
+}
+
+#[test]
+fn entry_is_recorded_when_body_is_done() {
+    let recorder = Arc::new(HarRecorder::default());
+    let entry = Entry {
+        started: UNIX_EPOCH,
+        method: "GET".to_string(),
+        url: "http://example.com/".to_string(),
+        http_version: "HTTP/1.1".to_string(),
+        request_headers: vec![("Host".to_string(), "example.com".to_string())],
+        request_body_size: 0,
+        status: 200,
+        status_text: "OK".to_string(),
+        response_headers: vec![("content-type".to_string(), "text/html".to_string())],
+        body_size: 0,
+        redirect_url: String::new(),
+        timings: Timings::default(),
+    };
+
+    let mut body = RecordingBody::new(Box::new(&b"<p>\"hi\"</p>"[..]), recorder.clone(), entry);
+    let mut text = String::new();
+    body.read_to_string(&mut text).unwrap();
+
+    let json = recorder.to_json();
+    assert!(json.contains(r#""url":"http://example.com/""#));
+    assert!(json.contains(r#""content":{"size":11,"mimeType":"text/html"}"#));
+}
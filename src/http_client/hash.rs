@@ -0,0 +1,85 @@
+//! The hash functions and random bytes HTTP Digest authentication, gemini
+//! certificate pinning and the WebSocket handshake need, from aws-lc, the
+//! crypto library rustls already uses, and MD5 from the md-5 crate.
+use aws_lc_rs::digest::{self, SHA1_FOR_LEGACY_USE_ONLY, SHA256};
+use aws_lc_rs::rand::{SecureRandom, SystemRandom};
+use md5::{Digest, Md5};
+
+pub fn md5(message: &[u8]) -> [u8; 16] {
+    // aws-lc-rs leaves MD5 out
+    Md5::digest(message).into()
+}
+
+pub fn sha1(message: &[u8]) -> [u8; 20] {
+    digest::digest(&SHA1_FOR_LEGACY_USE_ONLY, message)
+        .as_ref()
+        .try_into()
+        .expect("sha-1 digests are 20 bytes")
+}
+
+pub fn sha256(message: &[u8]) -> [u8; 32] {
+    digest::digest(&SHA256, message)
+        .as_ref()
+        .try_into()
+        .expect("sha-256 digests are 32 bytes")
+}
+
+/// From the operating system's secure random number generator, so nonces
+/// and WebSocket masking keys can't be guessed.
+pub fn random_bytes() -> [u8; 32] {
+    let mut bytes = [0u8; 32];
+    SystemRandom::new()
+        .fill(&mut bytes)
+        .expect("the system random number generator failed");
+    bytes
+}
+
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+#[test]
+fn md5_test_suite() {
+    // https://www.rfc-editor.org/rfc/rfc1321#appendix-A.5
+    assert_eq!(hex(&md5(b"")), "d41d8cd98f00b204e9800998ecf8427e");
+    assert_eq!(hex(&md5(b"abc")), "900150983cd24fb0d6963f7d28e17f72");
+    assert_eq!(
+        hex(&md5(
+            b"12345678901234567890123456789012345678901234567890123456789012345678901234567890"
+        )),
+        "57edf4a22be3c955ac49da2e2107b67a"
+    );
+}
+
+#[test]
+fn sha256_test_vectors() {
+    assert_eq!(
+        hex(&sha256(b"abc")),
+        "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+    );
+    assert_eq!(
+        hex(&sha256(
+            b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"
+        )),
+        "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"
+    );
+}
+
+#[test]
+fn sha1_test_vectors() {
+    assert_eq!(
+        hex(&sha1(b"abc")),
+        "a9993e364706816aba3e25717850c26c9cd0d89d"
+    );
+    assert_eq!(
+        hex(&sha1(
+            b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"
+        )),
+        "84983e441c3bd26ebaae4aa1f95129e5e54670f1"
+    );
+}
+
+#[test]
+fn random_bytes_differ() {
+    assert_ne!(random_bytes(), random_bytes());
+}
@@ -0,0 +1,208 @@
+//! HTTP Strict Transport Security, https://www.rfc-editor.org/rfc/rfc6797.
+//! Hosts that said they are https only, or that are on the preload list, are
+//! never asked for over plain http again.
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+/// Hosts are remembered for a year at most, like Chromium does, however long
+/// they ask for.
+const LONGEST_MAX_AGE: Duration = Duration::from_secs(365 * 24 * 60 * 60);
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Policy {
+    pub max_age: Duration,
+    pub include_subdomains: bool,
+}
+
+#[derive(Debug, Clone)]
+struct Entry {
+    expires: Option<SystemTime>,
+    include_subdomains: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct HstsStore {
+    /// Lowercased host names, preloaded ones never expire.
+    entries: Mutex<HashMap<String, Entry>>,
+}
+
+impl HstsStore {
+    /// Reads a preload list, one host per line with an optional
+    /// `includeSubDomains` after it. Lines starting with `#` are comments.
+    pub fn with_preload_list(self, path: &Path) -> Result<HstsStore, Box<dyn Error>> {
+        let list = fs::read_to_string(path)
+            .map_err(|e| format!("could not read {}: {e}", path.display()))?;
+
+        {
+            let mut entries = self.entries.lock().unwrap();
+            for line in list.lines().map(str::trim) {
+                if line.is_empty() || line.starts_with('#') {
+                    continue;
+                }
+
+                let mut words = line.split_whitespace();
+                let host = words.next().expect("line is not empty").to_lowercase();
+                let include_subdomains =
+                    words.any(|word| word.eq_ignore_ascii_case("includeSubDomains"));
+                entries.insert(
+                    host,
+                    Entry {
+                        expires: None,
+                        include_subdomains,
+                    },
+                );
+            }
+            log::debug!("{} hosts in the hsts store after preloading", entries.len());
+        }
+
+        Ok(self)
+    }
+
+    /// Takes note of a `Strict-Transport-Security` header. Only call this for
+    /// responses that came over https, the header means nothing otherwise.
+    pub fn observe(&self, host: &str, header: &str) {
+        if host.parse::<IpAddr>().is_ok() {
+            // the policy is for names, never for addresses
+            return;
+        }
+
+        let Some(policy) = parse_header(header) else {
+            log::warn!("ignoring invalid strict-transport-security from {host}: {header}");
+            return;
+        };
+
+        let host = host.to_lowercase();
+        let mut entries = self.entries.lock().unwrap();
+        if policy.max_age.is_zero() {
+            log::debug!("{host} asked to be forgotten by hsts");
+            entries.remove(&host);
+            return;
+        }
+
+        let max_age = policy.max_age.min(LONGEST_MAX_AGE);
+        let Some(expires) = SystemTime::now().checked_add(max_age) else {
+            log::warn!(
+                "ignoring strict-transport-security from {host}, the clock is too far ahead"
+            );
+            return;
+        };
+        entries.insert(
+            host,
+            Entry {
+                expires: Some(expires),
+                include_subdomains: policy.include_subdomains,
+            },
+        );
+    }
+
+    /// Whether plain http to `host` has to be turned into https.
+    pub fn is_known(&self, host: &str) -> bool {
+        let host = host.to_lowercase();
+        let now = SystemTime::now();
+        let entries = self.entries.lock().unwrap();
+
+        // the host itself, then every parent domain that includes its subdomains
+        let mut domain = host.as_str();
+        let mut exact = true;
+        loop {
+            if let Some(entry) = entries.get(domain)
+                && entry.expires.is_none_or(|expires| expires > now)
+                && (exact || entry.include_subdomains)
+            {
+                return true;
+            }
+
+            match domain.split_once('.') {
+                Some((_, parent)) => domain = parent,
+                None => return false,
+            }
+            exact = false;
+        }
+    }
+}
+
+/// `max-age=31536000; includeSubDomains`. Directives are case insensitive,
+/// unknown ones are ignored and a header with a directive twice is invalid.
+pub fn parse_header(header: &str) -> Option<Policy> {
+    let mut max_age = None;
+    let mut include_subdomains = false;
+    let mut seen = Vec::new();
+
+    for directive in header.split(';').map(str::trim) {
+        if directive.is_empty() {
+            continue;
+        }
+
+        let (name, value) = match directive.split_once('=') {
+            Some((name, value)) => (
+                name.trim().to_lowercase(),
+                Some(value.trim().trim_matches('"')),
+            ),
+            None => (directive.to_lowercase(), None),
+        };
+        if seen.contains(&name) {
+            return None;
+        }
+
+        match (name.as_str(), value) {
+            ("max-age", Some(value)) => max_age = Some(Duration::from_secs(value.parse().ok()?)),
+            ("max-age", None) => return None,
+            ("includesubdomains", _) => include_subdomains = true,
+            _ => {}
+        }
+        seen.push(name);
+    }
+
+    Some(Policy {
+        max_age: max_age?,
+        include_subdomains,
+    })
+}
+
+#[test]
+fn parse_policies() {
+    assert_eq!(
+        parse_header(r#"max-age="31536000"; includeSubDomains; preload"#),
+        Some(Policy {
+            max_age: Duration::from_secs(31_536_000),
+            include_subdomains: true,
+        })
+    );
+    assert_eq!(parse_header("includeSubDomains"), None);
+    assert_eq!(parse_header("max-age=1; max-age=2"), None);
+    assert_eq!(parse_header("max-age=soon"), None);
+}
+
+#[test]
+fn subdomains_are_only_covered_when_included() {
+    let store = HstsStore::default();
+    store.observe("example.com", "max-age=600; includeSubDomains");
+    store.observe("other.org", "max-age=600");
+    store.observe("127.0.0.1", "max-age=600");
+
+    assert!(store.is_known("Example.com"));
+    assert!(store.is_known("www.example.com"));
+    assert!(store.is_known("other.org"));
+    assert!(!store.is_known("www.other.org"));
+    assert!(!store.is_known("127.0.0.1"));
+
+    store.observe("other.org", "max-age=0");
+    assert!(!store.is_known("other.org"));
+}
+
+#[test]
+fn huge_max_ages_are_capped() {
+    let store = HstsStore::default();
+    store.observe("example.com", "max-age=18446744073709551615");
+    assert!(store.is_known("example.com"));
+
+    let expires = store.entries.lock().unwrap()["example.com"]
+        .expires
+        .unwrap();
+    assert!(expires <= SystemTime::now() + LONGEST_MAX_AGE);
+}
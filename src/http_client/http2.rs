@@ -0,0 +1,847 @@
+//! HTTP/2 client, https://www.rfc-editor.org/rfc/rfc9113
+//!
+//! Only used over TLS when the server picks `h2` during ALPN. A connection is
+//! shared by the requests to its origin, each on a stream of its own. A thread
+//! per connection reads the frames off it and sorts them into their streams,
+//! where the response bodies wait until they are read.
+use std::collections::{HashMap, VecDeque};
+use std::error::Error;
+use std::io::{self, ErrorKind, Read, Write};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::har::Timings;
+use super::hpack::{self, Decoder, HeaderList};
+use super::{Headers, Request, StreamingResponse, with_default_headers};
+use crate::url::URL;
+
+pub const ALPN_PROTOCOL: &[u8] = b"h2";
+
+/// How long the connection's thread waits for the server before it looks for
+/// new requests to send, reads on the connection have to time out after this.
+pub const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+const FRAME_DATA: u8 = 0x0;
+const FRAME_HEADERS: u8 = 0x1;
+const FRAME_RST_STREAM: u8 = 0x3;
+const FRAME_SETTINGS: u8 = 0x4;
+const FRAME_PUSH_PROMISE: u8 = 0x5;
+const FRAME_PING: u8 = 0x6;
+const FRAME_GOAWAY: u8 = 0x7;
+const FRAME_WINDOW_UPDATE: u8 = 0x8;
+const FRAME_CONTINUATION: u8 = 0x9;
+
+const FLAG_END_STREAM: u8 = 0x1;
+const FLAG_ACK: u8 = 0x1;
+const FLAG_END_HEADERS: u8 = 0x4;
+const FLAG_PADDED: u8 = 0x8;
+const FLAG_PRIORITY: u8 = 0x20;
+
+const SETTINGS_HEADER_TABLE_SIZE: u16 = 0x1;
+const SETTINGS_ENABLE_PUSH: u16 = 0x2;
+const SETTINGS_INITIAL_WINDOW_SIZE: u16 = 0x4;
+const SETTINGS_MAX_FRAME_SIZE: u16 = 0x5;
+
+const DEFAULT_WINDOW_SIZE: i64 = 65_535;
+const DEFAULT_MAX_FRAME_SIZE: usize = 16_384;
+const MAX_WINDOW_SIZE: i64 = (1 << 31) - 1;
+
+/// RST_STREAM error code for a response nobody wants anymore.
+const CANCEL: u32 = 0x8;
+
+/// What we advertise as our receive window, per stream and for the connection.
+const RECEIVE_WINDOW_SIZE: u32 = 1 << 20;
+
+/// Headers that only make sense for a single HTTP/1.1 hop, section 8.2.2.
+const CONNECTION_HEADERS: [&str; 6] = [
+    "connection",
+    "host",
+    "keep-alive",
+    "proxy-connection",
+    "transfer-encoding",
+    "upgrade",
+];
+
+#[derive(Debug)]
+struct Frame {
+    kind: u8,
+    flags: u8,
+    stream_id: u32,
+    payload: Vec<u8>,
+}
+
+struct Stream {
+    request: Request,
+    status: Option<u16>,
+    headers: HeaderList,
+    /// Received and not read yet.
+    body: VecDeque<u8>,
+    /// END_STREAM came, what is in `body` is all there is.
+    ended: bool,
+    /// Why the response will never be complete.
+    error: Option<String>,
+    /// Request body bytes still waiting for flow control window.
+    unsent_body: VecDeque<u8>,
+    send_window: i64,
+}
+
+/// What the connection's thread and the requests on the connection share.
+struct State {
+    streams: HashMap<u32, Stream>,
+    next_stream_id: u32,
+    /// Streams whose HEADERS the connection's thread has yet to send, in order.
+    opening: VecDeque<u32>,
+    /// Streams whose responses nobody is going to read, to be reset.
+    cancelled: Vec<u32>,
+    /// Why no more streams can be opened, the server sent GOAWAY or is gone.
+    closed: Option<String>,
+}
+
+struct Shared {
+    state: Mutex<State>,
+    /// Signalled whenever frames were sorted into streams.
+    changed: Condvar,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap()
+    }
+}
+
+/// A header block that is continued in CONTINUATION frames.
+struct HeaderBlock {
+    stream_id: u32,
+    fragments: Vec<u8>,
+    /// END_STREAM was set on the HEADERS frame that started it.
+    ends_stream: bool,
+}
+
+/// A handle on a connection, clones share it. The connection is closed once
+/// the handles and the bodies read from it are all gone.
+#[derive(Clone)]
+pub struct Connection {
+    shared: Arc<Shared>,
+}
+
+impl Connection {
+    /// Takes a stream where `h2` has already been negotiated, sends the
+    /// connection preface and starts the thread that owns the stream from
+    /// then on. Reads on `io` have to time out after `POLL_INTERVAL`, or new
+    /// requests wait for the server to send something before they go out.
+    pub fn handshake(io: impl Read + Write + Send + 'static) -> Result<Connection, Box<dyn Error>> {
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State {
+                streams: HashMap::new(),
+                next_stream_id: 1,
+                opening: VecDeque::new(),
+                cancelled: Vec::new(),
+                closed: None,
+            }),
+            changed: Condvar::new(),
+        });
+        let mut driver = Driver {
+            io,
+            shared: shared.clone(),
+            decoder: Decoder::new(4096),
+            incoming: Vec::new(),
+            continuing: None,
+            send_window: DEFAULT_WINDOW_SIZE,
+            peer_initial_window: DEFAULT_WINDOW_SIZE,
+            peer_max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+        };
+
+        driver.io.write_all(PREFACE)?;
+
+        let mut settings = Vec::new();
+        for (id, value) in [
+            (SETTINGS_ENABLE_PUSH, 0),
+            (SETTINGS_INITIAL_WINDOW_SIZE, RECEIVE_WINDOW_SIZE),
+        ] {
+            settings.extend_from_slice(&id.to_be_bytes());
+            settings.extend_from_slice(&value.to_be_bytes());
+        }
+        driver.write_frame(FRAME_SETTINGS, 0, 0, &settings)?;
+
+        // the connection window can only be raised with a WINDOW_UPDATE
+        let increment = RECEIVE_WINDOW_SIZE - DEFAULT_WINDOW_SIZE as u32;
+        driver.write_frame(FRAME_WINDOW_UPDATE, 0, 0, &increment.to_be_bytes())?;
+
+        thread::Builder::new()
+            .name("http2".to_string())
+            .spawn(move || driver.run())?;
+
+        Ok(Connection { shared })
+    }
+
+    /// Whether new requests can still go on this connection.
+    pub fn is_open(&self) -> bool {
+        self.shared.lock().closed.is_none()
+    }
+
+    /// Sends `request` on a new stream and returns as soon as its response
+    /// headers are in. The body is read as the caller consumes it, other
+    /// requests can go on the connection in the meantime.
+    pub fn stream(&self, request: Request) -> Result<StreamingResponse, Box<dyn Error>> {
+        let waiting = Instant::now();
+        let mut state = self.shared.lock();
+        if let Some(reason) = &state.closed {
+            return Err(format!("http2 connection closed: {reason}").into());
+        }
+
+        let stream_id = state.next_stream_id;
+        state.next_stream_id += 2;
+        let body = request.body.clone().unwrap_or_default().into_bytes();
+        state.streams.insert(
+            stream_id,
+            Stream {
+                request,
+                status: None,
+                headers: HeaderList::new(),
+                body: VecDeque::new(),
+                ended: false,
+                error: None,
+                unsent_body: body.into(),
+                send_window: 0,
+            },
+        );
+        state.opening.push_back(stream_id);
+
+        // the connection's thread sends it and tells us when the server answered
+        let mut state = self
+            .shared
+            .changed
+            .wait_while(state, |state| {
+                let stream = &state.streams[&stream_id];
+                stream.status.is_none() && stream.error.is_none()
+            })
+            .unwrap();
+
+        if state.streams[&stream_id].status.is_none() {
+            let stream = state.streams.remove(&stream_id).expect("checked above");
+            return Err(stream.error.expect("woken up without status").into());
+        }
+        let stream = &state.streams[&stream_id];
+
+        let timings = Timings {
+            wait: waiting.elapsed(),
+            ..Timings::default()
+        };
+        Ok(streaming_response(
+            stream.status,
+            stream.response_headers(),
+            stream.request.clone(),
+            timings,
+            Box::new(BodyReader {
+                shared: self.shared.clone(),
+                stream_id,
+            }),
+        ))
+    }
+}
+
+/// Owns the connection, on a thread of its own: sends what the requests
+/// queue and sorts what the server sends into their streams.
+struct Driver<S: Read + Write> {
+    io: S,
+    shared: Arc<Shared>,
+    decoder: Decoder,
+    /// Read, but not a whole frame yet.
+    incoming: Vec<u8>,
+    /// Only CONTINUATION may follow while a header block is unfinished.
+    continuing: Option<HeaderBlock>,
+    send_window: i64,
+    peer_initial_window: i64,
+    peer_max_frame_size: usize,
+}
+
+impl<S: Read + Write> Driver<S> {
+    fn run(mut self) {
+        let reason = match self.serve() {
+            Ok(()) => "no longer used".to_string(),
+            Err(e) => e.to_string(),
+        };
+        log::debug!("http2 connection closed: {reason}");
+
+        let mut state = self.shared.lock();
+        state.closed.get_or_insert(reason.clone());
+        for stream in state.streams.values_mut() {
+            if !stream.ended && stream.error.is_none() {
+                stream.error = Some(format!("http2 connection closed: {reason}"));
+            }
+        }
+        drop(state);
+        self.shared.changed.notify_all();
+    }
+
+    /// Until the server hangs up, or nobody holds on to the connection anymore.
+    fn serve(&mut self) -> Result<(), Box<dyn Error>> {
+        let mut buffer = [0u8; 16 * 1024];
+        // ours is the last reference once the handles and body readers are gone
+        while Arc::strong_count(&self.shared) > 1 {
+            self.send_queued()?;
+
+            let read = match self.io.read(&mut buffer) {
+                Ok(0) => return Err("the server closed the connection".into()),
+                Ok(read) => read,
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                    continue;
+                }
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            };
+            self.incoming.extend_from_slice(&buffer[..read]);
+
+            while let Some(frame) = self.next_frame()? {
+                self.handle_frame(frame)?;
+            }
+            self.shared.changed.notify_all();
+        }
+        Ok(())
+    }
+
+    fn send_queued(&mut self) -> Result<(), Box<dyn Error>> {
+        let (opening, cancelled) = {
+            let mut state = self.shared.lock();
+            (
+                std::mem::take(&mut state.opening),
+                std::mem::take(&mut state.cancelled),
+            )
+        };
+
+        for stream_id in cancelled {
+            self.write_frame(FRAME_RST_STREAM, 0, stream_id, &CANCEL.to_be_bytes())?;
+        }
+        for stream_id in opening {
+            self.open_stream(stream_id)?;
+        }
+        Ok(())
+    }
+
+    fn open_stream(&mut self, stream_id: u32) -> Result<(), Box<dyn Error>> {
+        let (header_block, has_body) = {
+            let mut state = self.shared.lock();
+            let Some(stream) = state.streams.get_mut(&stream_id) else {
+                return Ok(());
+            };
+            stream.send_window = self.peer_initial_window;
+            (
+                hpack::encode(&request_headers(&stream.request)),
+                !stream.unsent_body.is_empty(),
+            )
+        };
+
+        let mut chunks = header_block.chunks(self.peer_max_frame_size).peekable();
+        let mut kind = FRAME_HEADERS;
+        // an empty block still needs one HEADERS frame
+        let mut chunk = chunks.next().unwrap_or(&[]);
+        loop {
+            let mut flags = 0;
+            if kind == FRAME_HEADERS && !has_body {
+                flags |= FLAG_END_STREAM;
+            }
+            let last = chunks.peek().is_none();
+            if last {
+                flags |= FLAG_END_HEADERS;
+            }
+            self.write_frame(kind, flags, stream_id, chunk)?;
+
+            match chunks.next() {
+                Some(next) => chunk = next,
+                None => break,
+            }
+            kind = FRAME_CONTINUATION;
+        }
+
+        self.send_pending_data(stream_id)
+    }
+
+    /// Sends as much of the request body as the flow control windows allow.
+    fn send_pending_data(&mut self, stream_id: u32) -> Result<(), Box<dyn Error>> {
+        loop {
+            let (chunk, flags) = {
+                let mut state = self.shared.lock();
+                let Some(stream) = state.streams.get_mut(&stream_id) else {
+                    return Ok(());
+                };
+                if stream.unsent_body.is_empty() {
+                    return Ok(());
+                }
+
+                let allowed = stream
+                    .send_window
+                    .min(self.send_window)
+                    .min(self.peer_max_frame_size as i64);
+                if allowed <= 0 {
+                    return Ok(());
+                }
+
+                let length = stream.unsent_body.len().min(allowed as usize);
+                let chunk: Vec<u8> = stream.unsent_body.drain(..length).collect();
+                let flags = if stream.unsent_body.is_empty() {
+                    FLAG_END_STREAM
+                } else {
+                    0
+                };
+                stream.send_window -= length as i64;
+                self.send_window -= length as i64;
+                (chunk, flags)
+            };
+
+            self.write_frame(FRAME_DATA, flags, stream_id, &chunk)?;
+        }
+    }
+
+    /// Frames for streams that were reset or finished are still read, and
+    /// their header blocks decoded, but otherwise dropped.
+    fn handle_frame(&mut self, frame: Frame) -> Result<(), Box<dyn Error>> {
+        let Frame {
+            kind,
+            flags,
+            stream_id,
+            payload,
+        } = frame;
+
+        if let Some(continuing) = &self.continuing
+            && (kind != FRAME_CONTINUATION || stream_id != continuing.stream_id)
+        {
+            return Err("expected CONTINUATION frame".into());
+        }
+
+        match kind {
+            FRAME_DATA => {
+                let data = strip_padding(flags, &payload)?;
+
+                // hand the window straight back, the body is buffered anyway
+                if !payload.is_empty() {
+                    let increment = (payload.len() as u32).to_be_bytes();
+                    self.write_frame(FRAME_WINDOW_UPDATE, 0, 0, &increment)?;
+                    if flags & FLAG_END_STREAM == 0 {
+                        self.write_frame(FRAME_WINDOW_UPDATE, 0, stream_id, &increment)?;
+                    }
+                }
+
+                if let Some(stream) = self.shared.lock().streams.get_mut(&stream_id) {
+                    stream.body.extend(data);
+                    stream.ended |= flags & FLAG_END_STREAM != 0;
+                }
+            }
+            FRAME_HEADERS | FRAME_CONTINUATION => {
+                let fragment = if kind == FRAME_HEADERS {
+                    let fragment = strip_padding(flags, &payload)?;
+                    if flags & FLAG_PRIORITY != 0 {
+                        fragment.get(5..).ok_or("HEADERS frame too short")?
+                    } else {
+                        fragment
+                    }
+                } else {
+                    &payload[..]
+                };
+
+                let mut block = self.continuing.take().unwrap_or(HeaderBlock {
+                    stream_id,
+                    fragments: Vec::new(),
+                    ends_stream: flags & FLAG_END_STREAM != 0,
+                });
+                block.fragments.extend_from_slice(fragment);
+                if flags & FLAG_END_HEADERS == 0 {
+                    self.continuing = Some(block);
+                    return Ok(());
+                }
+
+                // the block has to be decoded even if we drop it, to keep the
+                // hpack table in sync with the server
+                let headers = self.decoder.decode(&block.fragments)?;
+                let status = headers
+                    .iter()
+                    .find(|(name, _)| name == ":status")
+                    .map(|(_, value)| value.parse::<u16>())
+                    .transpose()?;
+
+                let mut state = self.shared.lock();
+                let Some(stream) = state.streams.get_mut(&stream_id) else {
+                    return Ok(());
+                };
+                match (stream.status, status) {
+                    // interim responses like 103 Early Hints are skipped
+                    (None, Some(100..200)) => {}
+                    (None, Some(status)) => {
+                        stream.status = Some(status);
+                        stream.headers = headers;
+                    }
+                    (None, None) => return Err("response without :status".into()),
+                    // trailers, after the body
+                    (Some(_), _) => stream.headers.extend(headers),
+                }
+                stream.ended |= stream.status.is_some() && block.ends_stream;
+            }
+            FRAME_RST_STREAM => {
+                let code = u32::from_be_bytes(
+                    payload
+                        .get(0..4)
+                        .ok_or("RST_STREAM frame too short")?
+                        .try_into()?,
+                );
+                if let Some(stream) = self.shared.lock().streams.get_mut(&stream_id)
+                    && !stream.ended
+                {
+                    stream.error = Some(format!("server reset stream with error code {code}"));
+                }
+            }
+            FRAME_SETTINGS if flags & FLAG_ACK == 0 => {
+                self.apply_settings(&payload)?;
+                self.write_frame(FRAME_SETTINGS, FLAG_ACK, 0, &[])?;
+            }
+            FRAME_PUSH_PROMISE => return Err("server push was disabled".into()),
+            FRAME_PING if flags & FLAG_ACK == 0 => {
+                self.write_frame(FRAME_PING, FLAG_ACK, 0, &payload)?;
+            }
+            FRAME_GOAWAY => {
+                let last_stream_id = u32::from_be_bytes(
+                    payload
+                        .get(0..4)
+                        .ok_or("GOAWAY frame too short")?
+                        .try_into()?,
+                ) & 0x7fff_ffff;
+                let code = payload
+                    .get(4..8)
+                    .map(|bytes| u32::from_be_bytes(bytes.try_into().unwrap()))
+                    .unwrap_or(0);
+                let reason = format!("server sent GOAWAY with error code {code}");
+
+                // streams up to last_stream_id are still answered, the rest
+                // never will be, and new ones need a new connection
+                let mut state = self.shared.lock();
+                for (_, stream) in state
+                    .streams
+                    .iter_mut()
+                    .filter(|(id, stream)| **id > last_stream_id && !stream.ended)
+                {
+                    stream.error = Some(reason.clone());
+                }
+                state.closed.get_or_insert(reason);
+            }
+            FRAME_WINDOW_UPDATE => {
+                let increment = u32::from_be_bytes(
+                    payload
+                        .get(0..4)
+                        .ok_or("WINDOW_UPDATE frame too short")?
+                        .try_into()?,
+                ) as i64
+                    & 0x7fff_ffff;
+
+                if stream_id == 0 {
+                    self.send_window += increment;
+                    if self.send_window > MAX_WINDOW_SIZE {
+                        return Err("connection flow control window overflow".into());
+                    }
+                    let ids: Vec<u32> = self.shared.lock().streams.keys().cloned().collect();
+                    for id in ids {
+                        self.send_pending_data(id)?;
+                    }
+                } else {
+                    if let Some(stream) = self.shared.lock().streams.get_mut(&stream_id) {
+                        stream.send_window += increment;
+                    }
+                    self.send_pending_data(stream_id)?;
+                }
+            }
+            // acks, PRIORITY and unknown frame types are ignored
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    fn apply_settings(&mut self, payload: &[u8]) -> Result<(), Box<dyn Error>> {
+        if !payload.len().is_multiple_of(6) {
+            return Err("SETTINGS frame has invalid length".into());
+        }
+
+        for setting in payload.chunks(6) {
+            let id = u16::from_be_bytes([setting[0], setting[1]]);
+            let value = u32::from_be_bytes([setting[2], setting[3], setting[4], setting[5]]);
+
+            match id {
+                // our encoder never uses the dynamic table, so any size works
+                SETTINGS_HEADER_TABLE_SIZE => {}
+                SETTINGS_INITIAL_WINDOW_SIZE => {
+                    if value as i64 > MAX_WINDOW_SIZE {
+                        return Err("initial window size too large".into());
+                    }
+                    let delta = value as i64 - self.peer_initial_window;
+                    self.peer_initial_window = value as i64;
+                    for stream in self.shared.lock().streams.values_mut() {
+                        stream.send_window += delta;
+                    }
+                }
+                SETTINGS_MAX_FRAME_SIZE => {
+                    if !(16_384..=16_777_215).contains(&value) {
+                        return Err("invalid max frame size".into());
+                    }
+                    self.peer_max_frame_size = value as usize;
+                }
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The next whole frame out of what was read so far.
+    fn next_frame(&mut self) -> Result<Option<Frame>, Box<dyn Error>> {
+        let Some(header) = self.incoming.get(..9) else {
+            return Ok(None);
+        };
+
+        let length = u32::from_be_bytes([0, header[0], header[1], header[2]]) as usize;
+        if length > DEFAULT_MAX_FRAME_SIZE {
+            // we never raise SETTINGS_MAX_FRAME_SIZE
+            return Err(format!("frame of {length} bytes exceeds max frame size").into());
+        }
+        if self.incoming.len() < 9 + length {
+            return Ok(None);
+        }
+
+        let frame = Frame {
+            kind: header[3],
+            flags: header[4],
+            stream_id: u32::from_be_bytes([header[5], header[6], header[7], header[8]])
+                & 0x7fff_ffff,
+            payload: self.incoming[9..9 + length].to_vec(),
+        };
+        self.incoming.drain(..9 + length);
+        Ok(Some(frame))
+    }
+
+    fn write_frame(
+        &mut self,
+        kind: u8,
+        flags: u8,
+        stream_id: u32,
+        payload: &[u8],
+    ) -> Result<(), Box<dyn Error>> {
+        self.io
+            .write_all(&encode_frame(kind, flags, stream_id, payload))?;
+        Ok(())
+    }
+}
+
+/// Body of a streamed response, waits for the connection's thread to sort
+/// more of it into the stream.
+struct BodyReader {
+    shared: Arc<Shared>,
+    stream_id: u32,
+}
+
+impl Read for BodyReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let stream_id = self.stream_id;
+        let mut state = self
+            .shared
+            .changed
+            .wait_while(self.shared.lock(), |state| {
+                let stream = &state.streams[&stream_id];
+                stream.body.is_empty() && !stream.ended && stream.error.is_none()
+            })
+            .unwrap();
+
+        let stream = state.streams.get_mut(&stream_id).expect("removed on drop");
+        if !stream.body.is_empty() {
+            return stream.body.read(buf);
+        }
+        match &stream.error {
+            Some(error) => Err(io::Error::other(error.clone())),
+            None => Ok(0),
+        }
+    }
+}
+
+impl Drop for BodyReader {
+    fn drop(&mut self) {
+        let mut state = self.shared.lock();
+        if let Some(stream) = state.streams.remove(&self.stream_id)
+            && !stream.ended
+            && stream.error.is_none()
+        {
+            // so the server stops sending what nobody is going to read
+            state.cancelled.push(self.stream_id);
+        }
+    }
+}
+
+impl Stream {
+    fn response_headers(&self) -> Headers {
+        self.headers
+            .iter()
+            .filter(|(name, _)| !name.starts_with(':'))
+            .cloned()
+            .collect()
+    }
+}
+
+fn streaming_response(
+    status: Option<u16>,
+    headers: Headers,
+    request: Request,
+    timings: Timings,
+    body: Box<dyn Read + Send>,
+) -> StreamingResponse {
+    StreamingResponse {
+        status: status.unwrap_or_default(),
+        version: "HTTP/2".to_string(),
+        explanation: String::new(),
+        headers,
+        request,
+        body,
+        bytes_read: 0,
+        recording: None,
+        timings,
+    }
+}
+
+fn encode_frame(kind: u8, flags: u8, stream_id: u32, payload: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(9 + payload.len());
+    frame.extend_from_slice(&(payload.len() as u32).to_be_bytes()[1..]);
+    frame.push(kind);
+    frame.push(flags);
+    frame.extend_from_slice(&stream_id.to_be_bytes());
+    frame.extend_from_slice(payload);
+    frame
+}
+
+fn strip_padding(flags: u8, payload: &[u8]) -> Result<&[u8], Box<dyn Error>> {
+    if flags & FLAG_PADDED == 0 {
+        return Ok(payload);
+    }
+
+    let (&pad_length, rest) = payload.split_first().ok_or("padded frame is empty")?;
+    rest.len()
+        .checked_sub(pad_length as usize)
+        .map(|end| &rest[..end])
+        .ok_or("padding longer than frame".into())
+}
+
+fn request_headers(request: &Request) -> HeaderList {
+    let Request {
+        method,
+        url,
+        headers,
+        ..
+    } = request;
+    let URL {
+        scheme,
+        host,
+        port,
+        path,
+        ..
+    } = url;
+
+    let authority = match port {
+        Some(port) => format!("{host}:{port}"),
+        None => host.clone(),
+    };
+
+    let mut list: HeaderList = vec![
+        (":method".to_string(), method.to_string()),
+        (":scheme".to_string(), scheme.clone()),
+        (":authority".to_string(), authority),
+        (":path".to_string(), path.clone()),
+    ];
+
+    for (key, value) in with_default_headers(headers) {
+        let key = key.to_lowercase();
+        if !CONNECTION_HEADERS.contains(&key.as_str()) {
+            list.push((key, value));
+        }
+    }
+
+    list
+}
+
+#[test]
+fn response_bodies_are_streamed() {
+    use super::Method;
+    use std::io::Cursor;
+
+    /// Replays canned server frames once the client sent its request, and
+    /// records what the client writes.
+    struct ScriptedServer {
+        input: Cursor<Vec<u8>>,
+        output: Arc<Mutex<Vec<u8>>>,
+    }
+    impl Read for ScriptedServer {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let output = self.output.lock().unwrap();
+            let mut frames = output.get(PREFACE.len()..).unwrap_or_default();
+            while frames.len() >= 9 && frames[3] != FRAME_HEADERS {
+                let length = u32::from_be_bytes([0, frames[0], frames[1], frames[2]]) as usize;
+                frames = frames.get(9 + length..).unwrap_or_default();
+            }
+            if frames.len() < 9 {
+                // like a socket whose read timed out
+                drop(output);
+                thread::sleep(POLL_INTERVAL);
+                return Err(ErrorKind::WouldBlock.into());
+            }
+            self.input.read(buf)
+        }
+    }
+    impl Write for ScriptedServer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.output.lock().unwrap().write(buf)
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let status = |code: &str| hpack::encode(&[(":status".to_string(), code.to_string())]);
+
+    // an interim response comes first, and the body in two frames
+    let mut script = Vec::new();
+    script.extend(encode_frame(FRAME_SETTINGS, 0, 0, &[]));
+    script.extend(encode_frame(
+        FRAME_HEADERS,
+        FLAG_END_HEADERS,
+        1,
+        &status("103"),
+    ));
+    script.extend(encode_frame(
+        FRAME_HEADERS,
+        FLAG_END_HEADERS,
+        1,
+        &status("200"),
+    ));
+    script.extend(encode_frame(FRAME_DATA, 0, 1, b"hello "));
+    script.extend(encode_frame(FRAME_DATA, FLAG_END_STREAM, 1, b"world"));
+
+    let output = Arc::new(Mutex::new(Vec::new()));
+    let server = ScriptedServer {
+        input: Cursor::new(script),
+        output: output.clone(),
+    };
+
+    let request = Request {
+        method: Method::GET,
+        url: URL::parse("https://example.com/").unwrap(),
+        headers: None,
+        body: None,
+    };
+
+    let mut response = Connection::handshake(server)
+        .unwrap()
+        .stream(request)
+        .unwrap();
+    assert_eq!(response.status, 200);
+    assert_eq!(response.request.url.path, "/");
+
+    let mut body = String::new();
+    response.read_to_string(&mut body).unwrap();
+    assert_eq!(body, "hello world");
+
+    assert!(output.lock().unwrap().starts_with(PREFACE));
+}
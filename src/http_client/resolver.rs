@@ -0,0 +1,246 @@
+//! Turning host names into connected sockets. Lookups are cached for a while,
+//! hosts can be pinned to fixed addresses like curl's `--resolve`, and
+//! connections race the addresses as described in RFC 8305, Happy Eyeballs.
+use std::collections::HashMap;
+use std::error::Error;
+use std::io::{self, ErrorKind};
+use std::net::{IpAddr, SocketAddr, TcpStream, ToSocketAddrs};
+use std::sync::Mutex;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// The system resolver doesn't tell us the record TTLs, so every answer is
+/// kept for the same time.
+const DEFAULT_TTL: Duration = Duration::from_secs(60);
+
+/// How long to wait for an attempt before starting the next one in parallel,
+/// https://www.rfc-editor.org/rfc/rfc8305#section-5
+const CONNECTION_ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// `host:port:address[,address]`, always answered with these addresses.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HostOverride {
+    pub host: String,
+    pub port: u16,
+    pub addresses: Vec<IpAddr>,
+}
+
+impl HostOverride {
+    pub fn parse(value: &str) -> Result<HostOverride, Box<dyn Error>> {
+        let mut parts = value.splitn(3, ':');
+        let (Some(host), Some(port), Some(addresses)) = (parts.next(), parts.next(), parts.next())
+        else {
+            return Err(format!("expected host:port:address, got {value}").into());
+        };
+
+        let addresses = addresses
+            .split(',')
+            .map(|address| {
+                address
+                    .trim()
+                    .trim_start_matches('[')
+                    .trim_end_matches(']')
+                    .parse()
+            })
+            .collect::<Result<Vec<IpAddr>, _>>()
+            .map_err(|e| format!("invalid address in {value}: {e}"))?;
+
+        Ok(HostOverride {
+            host: host.to_lowercase(),
+            port: port
+                .parse()
+                .map_err(|e| format!("invalid port in {value}: {e}"))?,
+            addresses,
+        })
+    }
+}
+
+/// When each host and port was looked up, and the answer.
+type Cache = HashMap<(String, u16), (Instant, Vec<SocketAddr>)>;
+
+#[derive(Debug)]
+pub struct Resolver {
+    ttl: Duration,
+    overrides: Vec<HostOverride>,
+    cache: Mutex<Cache>,
+}
+
+impl Default for Resolver {
+    fn default() -> Resolver {
+        Resolver::new(DEFAULT_TTL)
+    }
+}
+
+impl Resolver {
+    pub fn new(ttl: Duration) -> Resolver {
+        Resolver {
+            ttl,
+            overrides: Vec::new(),
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn with_overrides(mut self, overrides: Vec<HostOverride>) -> Resolver {
+        self.overrides = overrides;
+        self
+    }
+
+    pub fn resolve(&self, host: &str, port: u16) -> io::Result<Vec<SocketAddr>> {
+        let host = host.to_lowercase();
+
+        if let Some(pinned) = self
+            .overrides
+            .iter()
+            .find(|o| o.host == host && o.port == port)
+        {
+            log::debug!("{host}:{port} is pinned to {:?}", pinned.addresses);
+            return Ok(pinned
+                .addresses
+                .iter()
+                .map(|ip| SocketAddr::new(*ip, port))
+                .collect());
+        }
+
+        let key = (host.clone(), port);
+        if let Some((resolved_at, addresses)) = self.cache.lock().unwrap().get(&key)
+            && resolved_at.elapsed() < self.ttl
+        {
+            return Ok(addresses.clone());
+        }
+
+        let addresses: Vec<SocketAddr> = (host.as_str(), port).to_socket_addrs()?.collect();
+        if addresses.is_empty() {
+            return Err(io::Error::new(
+                ErrorKind::NotFound,
+                format!("no addresses for {host}"),
+            ));
+        }
+        log::debug!("resolved {host} to {addresses:?}");
+
+        self.cache
+            .lock()
+            .unwrap()
+            .insert(key, (Instant::now(), addresses.clone()));
+        Ok(addresses)
+    }
+
+    pub fn connect(&self, host: &str, port: u16) -> io::Result<TcpStream> {
+        connect_any(&self.resolve(host, port)?)
+    }
+}
+
+/// Starts an attempt on the next address whenever the previous one fails or
+/// has been going on for `CONNECTION_ATTEMPT_DELAY`, the first to connect wins.
+pub fn connect_any(addresses: &[SocketAddr]) -> io::Result<TcpStream> {
+    let mut queue = interleave(addresses).into_iter();
+    let (results, attempts) = mpsc::channel();
+    let mut running = 0;
+    let mut last_error = io::Error::new(ErrorKind::NotFound, "no addresses to connect to");
+
+    loop {
+        // start the next attempt
+        if let Some(address) = queue.next() {
+            let results = results.clone();
+            running += 1;
+            thread::spawn(move || {
+                let attempt = TcpStream::connect_timeout(&address, CONNECT_TIMEOUT);
+                // a late winner is simply dropped when nobody is listening anymore
+                let _ = results.send((address, attempt));
+            });
+        } else if running == 0 {
+            return Err(last_error);
+        }
+
+        let delay = match queue.len() {
+            0 => CONNECT_TIMEOUT,
+            _ => CONNECTION_ATTEMPT_DELAY,
+        };
+        match attempts.recv_timeout(delay) {
+            Ok((address, Ok(stream))) => {
+                log::debug!("connected to {address}");
+                return Ok(stream);
+            }
+            Ok((address, Err(e))) => {
+                log::debug!("could not connect to {address}: {e}");
+                running -= 1;
+                last_error = e;
+            }
+            Err(RecvTimeoutError::Timeout) if queue.len() == 0 => {
+                return Err(io::Error::new(ErrorKind::TimedOut, "connecting timed out"));
+            }
+            Err(_) => {}
+        }
+    }
+}
+
+/// Alternates between address families, starting with the family of the first
+/// address since the system resolver already sorted by preference,
+/// https://www.rfc-editor.org/rfc/rfc8305#section-4
+fn interleave(addresses: &[SocketAddr]) -> Vec<SocketAddr> {
+    let Some(first) = addresses.first() else {
+        return Vec::new();
+    };
+
+    let (preferred, other): (Vec<SocketAddr>, Vec<SocketAddr>) = addresses
+        .iter()
+        .partition(|address| address.is_ipv6() == first.is_ipv6());
+
+    let mut ordered = Vec::with_capacity(addresses.len());
+    let (mut preferred, mut other) = (preferred.into_iter(), other.into_iter());
+    loop {
+        match (preferred.next(), other.next()) {
+            (None, None) => break,
+            (a, b) => ordered.extend(a.into_iter().chain(b)),
+        }
+    }
+    ordered
+}
+
+#[test]
+fn families_are_interleaved() {
+    let addresses: Vec<SocketAddr> = [
+        "[2001:db8::1]:80",
+        "[2001:db8::2]:80",
+        "[2001:db8::3]:80",
+        "192.0.2.1:80",
+    ]
+    .iter()
+    .map(|a| a.parse().unwrap())
+    .collect();
+
+    assert_eq!(
+        interleave(&addresses),
+        vec![addresses[0], addresses[3], addresses[1], addresses[2]]
+    );
+}
+
+#[test]
+fn overrides_pin_hosts() {
+    let pinned = HostOverride::parse("example.test:443:127.0.0.1,[::1]").unwrap();
+    let resolver = Resolver::default().with_overrides(vec![pinned]);
+
+    assert_eq!(
+        resolver.resolve("Example.test", 443).unwrap(),
+        vec![
+            "127.0.0.1:443".parse().unwrap(),
+            "[::1]:443".parse().unwrap()
+        ]
+    );
+    assert!(HostOverride::parse("example.test:443").is_err());
+}
+
+#[test]
+fn a_refused_address_falls_through_to_the_next() {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let live = listener.local_addr().unwrap();
+    let refused = {
+        let closed = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        closed.local_addr().unwrap()
+    };
+
+    let stream = connect_any(&[refused, live]).unwrap();
+    assert_eq!(stream.peer_addr().unwrap(), live);
+}
@@ -0,0 +1,462 @@
+//! Local HTTP, HTTPS and HTTP/2 servers for exercising the client over real sockets.
+//! Each path is answered with a scripted `Reply`, and every request that comes
+//! in is kept so tests can check what the client actually sent.
+use rcgen::{BasicConstraints, CertificateParams, CertifiedIssuer, DnType, IsCa, KeyPair};
+use rustls::ServerConfig;
+use rustls::pki_types::{PrivateKeyDer, PrivatePkcs8KeyDer};
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use super::hpack::{self, Decoder};
+use crate::tls::TlsConfig;
+
+#[derive(Debug, Clone)]
+pub struct Reply {
+    /// Written one after the other, with `delay` before each.
+    parts: Vec<Vec<u8>>,
+    delay: Duration,
+}
+
+impl Reply {
+    /// Exactly these bytes, however broken they are.
+    pub fn raw(bytes: &str) -> Reply {
+        Reply {
+            parts: vec![bytes.as_bytes().to_vec()],
+            delay: Duration::ZERO,
+        }
+    }
+
+    pub fn ok(body: &str) -> Reply {
+        Reply::status(200, "OK", &[("Content-Type", "text/html")], body)
+    }
+
+    pub fn status(status: u16, explanation: &str, headers: &[(&str, &str)], body: &str) -> Reply {
+        let mut head = format!("HTTP/1.1 {status} {explanation}\r\n");
+        for (name, value) in headers {
+            head.push_str(&format!("{name}: {value}\r\n"));
+        }
+        head.push_str(&format!("Content-Length: {}\r\n\r\n", body.len()));
+
+        Reply {
+            parts: vec![head.into_bytes(), body.as_bytes().to_vec()],
+            delay: Duration::ZERO,
+        }
+    }
+
+    /// Status, headers and body, for answering over HTTP/2. Only replies with
+    /// a `Content-Length` body make sense there.
+    fn parse(&self) -> (String, Vec<(String, String)>, Vec<u8>) {
+        let bytes = self.parts.concat();
+        let split = bytes
+            .windows(4)
+            .position(|window| window == b"\r\n\r\n")
+            .expect("reply without head");
+        let head = String::from_utf8_lossy(&bytes[..split]);
+        let mut lines = head.lines();
+        let status = lines
+            .next()
+            .and_then(|line| line.split_whitespace().nth(1))
+            .expect("reply without status")
+            .to_string();
+        let headers = lines
+            .filter_map(|line| line.split_once(':'))
+            .map(|(name, value)| (name.trim().to_lowercase(), value.trim().to_string()))
+            .collect();
+        (status, headers, bytes[split + 4..].to_vec())
+    }
+
+    pub fn redirect(status: u16, location: &str) -> Reply {
+        Reply::status(status, "Redirect", &[("Location", location)], "")
+    }
+
+    /// `transfer-encoding: chunked`, one chunk per entry.
+    pub fn chunked(chunks: &[&str]) -> Reply {
+        let mut parts = vec![b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n".to_vec()];
+        for chunk in chunks {
+            parts.push(format!("{:x}\r\n{chunk}\r\n", chunk.len()).into_bytes());
+        }
+        parts.push(b"0\r\n\r\n".to_vec());
+
+        Reply {
+            parts,
+            delay: Duration::ZERO,
+        }
+    }
+
+    /// Waits `delay` before sending each part of the reply.
+    pub fn slow(mut self, delay: Duration) -> Reply {
+        self.delay = delay;
+        self
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ReceivedRequest {
+    pub method: String,
+    pub path: String,
+    /// Names are lowercased.
+    pub headers: HashMap<String, String>,
+}
+
+type Handler = Box<dyn Fn(&ReceivedRequest) -> Reply + Send + Sync>;
+
+struct State {
+    routes: HashMap<String, Handler>,
+    received: Mutex<Vec<ReceivedRequest>>,
+    connections: AtomicUsize,
+}
+
+pub struct TestServer {
+    scheme: &'static str,
+    port: u16,
+    state: Arc<State>,
+    stopped: Arc<AtomicBool>,
+    /// PEM file of the root that signed the HTTPS server's certificate.
+    root_cert: Option<PathBuf>,
+}
+
+#[derive(Default)]
+pub struct TestServerBuilder {
+    routes: HashMap<String, Handler>,
+}
+
+impl TestServerBuilder {
+    pub fn route(self, path: &str, reply: Reply) -> TestServerBuilder {
+        self.route_fn(path, move |_| reply.clone())
+    }
+
+    /// Decides the reply from the request, like answering `401` until the
+    /// right credentials are sent.
+    pub fn route_fn(
+        mut self,
+        path: &str,
+        handler: impl Fn(&ReceivedRequest) -> Reply + Send + Sync + 'static,
+    ) -> TestServerBuilder {
+        self.routes.insert(path.to_string(), Box::new(handler));
+        self
+    }
+
+    pub fn http(self) -> TestServer {
+        TestServer::start("http", self.routes, None)
+    }
+
+    /// Serves TLS for `localhost` with a certificate from a freshly made root,
+    /// trust it with `TestServer::tls_config`.
+    pub fn https(self) -> TestServer {
+        let (tls, root_cert) = self_signed_tls();
+        TestServer::start("https", self.routes, Some((Arc::new(tls), root_cert)))
+    }
+
+    /// Like `https`, but settles on `h2` during ALPN. Requests are answered
+    /// in the order they come in, each all at once, `Reply::slow` is ignored.
+    pub fn http2(self) -> TestServer {
+        let (mut tls, root_cert) = self_signed_tls();
+        tls.alpn_protocols = vec![b"h2".to_vec()];
+        TestServer::start("https", self.routes, Some((Arc::new(tls), root_cert)))
+    }
+
+    /// Plain TCP, routed by the selector the client sends. Replies are sent as
+    /// they are, so they are built with `Reply::raw`.
+    pub fn gopher(self) -> TestServer {
+        TestServer::start("gopher", self.routes, None)
+    }
+
+    /// Gemini over TLS, a new certificate every time. Replies are sent as they
+    /// are, so they are built with `Reply::raw`.
+    pub fn gemini(self) -> TestServer {
+        let (tls, root_cert) = self_signed_tls();
+        TestServer::start("gemini", self.routes, Some((Arc::new(tls), root_cert)))
+    }
+}
+
+impl TestServer {
+    pub fn builder() -> TestServerBuilder {
+        TestServerBuilder::default()
+    }
+
+    fn start(
+        scheme: &'static str,
+        routes: HashMap<String, Handler>,
+        tls: Option<(Arc<ServerConfig>, PathBuf)>,
+    ) -> TestServer {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let state = Arc::new(State {
+            routes,
+            received: Mutex::new(Vec::new()),
+            connections: AtomicUsize::new(0),
+        });
+        let stopped = Arc::new(AtomicBool::new(false));
+
+        let (tls, root_cert) = match tls {
+            Some((tls, root_cert)) => (Some(tls), Some(root_cert)),
+            None => (None, None),
+        };
+
+        {
+            let state = state.clone();
+            let stopped = stopped.clone();
+            thread::spawn(move || {
+                for stream in listener.incoming() {
+                    if stopped.load(Ordering::SeqCst) {
+                        break;
+                    }
+                    let Ok(stream) = stream else { continue };
+                    state.connections.fetch_add(1, Ordering::SeqCst);
+                    let state = state.clone();
+                    let tls = tls.clone();
+                    thread::spawn(move || serve(scheme, stream, &state, tls));
+                }
+            });
+        }
+
+        TestServer {
+            scheme,
+            port,
+            state,
+            stopped,
+            root_cert,
+        }
+    }
+
+    /// Absolute url for `path` on this server.
+    pub fn url(&self, path: &str) -> String {
+        format!("{}://localhost:{}{path}", self.scheme, self.port)
+    }
+
+    /// Trusts the root of the HTTPS server, on top of the usual roots.
+    pub fn tls_config(&self) -> TlsConfig {
+        TlsConfig {
+            extra_root_certs: self.root_cert.iter().cloned().collect(),
+            ..TlsConfig::default()
+        }
+    }
+
+    pub fn received(&self) -> Vec<ReceivedRequest> {
+        self.state.received.lock().unwrap().clone()
+    }
+
+    /// How many connections were accepted so far.
+    pub fn connections(&self) -> usize {
+        self.state.connections.load(Ordering::SeqCst)
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        // wake the accept loop up so it sees the flag
+        let _ = TcpStream::connect(("127.0.0.1", self.port));
+        if let Some(root_cert) = &self.root_cert {
+            let _ = std::fs::remove_file(root_cert);
+        }
+    }
+}
+
+fn serve(scheme: &str, mut stream: TcpStream, state: &State, tls: Option<Arc<ServerConfig>>) {
+    match tls {
+        Some(tls) => {
+            let mut connection = rustls::ServerConnection::new(tls).unwrap();
+            while connection.is_handshaking() {
+                if connection.complete_io(&mut stream).is_err() {
+                    return;
+                }
+            }
+            let http2 = connection.alpn_protocol() == Some(b"h2");
+            let stream = rustls::StreamOwned::new(connection, stream);
+            match http2 {
+                true => answer_http2(stream, state),
+                false => answer(scheme, stream, state),
+            }
+        }
+        None => answer(scheme, stream, state),
+    }
+}
+
+fn answer(scheme: &str, stream: impl Read + Write, state: &State) {
+    let mut reader = BufReader::new(stream);
+
+    let mut request_line = String::new();
+    if reader.read_line(&mut request_line).unwrap_or(0) == 0 {
+        return;
+    }
+    // gemini and gopher requests are a single line without headers
+    let (method, path) = match scheme {
+        "gemini" => {
+            let url = request_line.trim_end();
+            let rest = url.strip_prefix("gemini://").unwrap_or(url);
+            let path = rest.find('/').map_or("/", |i| &rest[i..]);
+            ("GEMINI".to_string(), path.to_string())
+        }
+        "gopher" => ("GOPHER".to_string(), request_line.trim_end().to_string()),
+        _ => {
+            let mut parts = request_line.split_whitespace();
+            let method = parts.next().unwrap_or("").to_string();
+            (method, parts.next().unwrap_or("").to_string())
+        }
+    };
+
+    let mut headers = HashMap::new();
+    if matches!(scheme, "http" | "https") {
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line).unwrap_or(0) == 0 {
+                return;
+            }
+            match line.trim_end().split_once(':') {
+                Some((name, value)) => {
+                    headers.insert(name.trim().to_lowercase(), value.trim().to_string());
+                }
+                None => break,
+            }
+        }
+    }
+
+    let request = ReceivedRequest {
+        method,
+        path,
+        headers,
+    };
+    let reply = match state.routes.get(&request.path) {
+        Some(handler) => handler(&request),
+        None => Reply::status(404, "Not Found", &[], "not found"),
+    };
+    state.received.lock().unwrap().push(request);
+
+    let stream = reader.get_mut();
+    for part in &reply.parts {
+        thread::sleep(reply.delay);
+        if stream.write_all(part).and_then(|_| stream.flush()).is_err() {
+            return;
+        }
+    }
+}
+
+/// Answers every request on the connection, each on its stream. Requests are
+/// expected to be a single HEADERS frame without a body, like the client's.
+fn answer_http2(mut stream: impl Read + Write, state: &State) {
+    const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+    const DATA: u8 = 0x0;
+    const HEADERS: u8 = 0x1;
+    const SETTINGS: u8 = 0x4;
+    const END_STREAM: u8 = 0x1;
+    const END_HEADERS: u8 = 0x4;
+
+    let frame = |kind: u8, flags: u8, stream_id: u32, payload: &[u8]| {
+        let mut frame = (payload.len() as u32).to_be_bytes()[1..].to_vec();
+        frame.extend([kind, flags]);
+        frame.extend(stream_id.to_be_bytes());
+        frame.extend(payload);
+        frame
+    };
+
+    let mut preface = [0u8; 24];
+    if stream.read_exact(&mut preface).is_err() || preface != PREFACE {
+        return;
+    }
+    // the defaults are fine
+    if stream.write_all(&frame(SETTINGS, 0, 0, &[])).is_err() {
+        return;
+    }
+
+    let mut decoder = Decoder::new(4096);
+    loop {
+        let mut header = [0u8; 9];
+        if stream.read_exact(&mut header).is_err() {
+            return;
+        }
+        let length = u32::from_be_bytes([0, header[0], header[1], header[2]]) as usize;
+        let mut payload = vec![0u8; length];
+        if stream.read_exact(&mut payload).is_err() {
+            return;
+        }
+        let (kind, flags) = (header[3], header[4]);
+        let stream_id = u32::from_be_bytes([header[5], header[6], header[7], header[8]]);
+
+        let reply = match kind {
+            SETTINGS if flags & 0x1 == 0 => frame(SETTINGS, 0x1, 0, &[]),
+            HEADERS => {
+                let fields = decoder.decode(&payload).unwrap();
+                let field = |name: &str| {
+                    fields
+                        .iter()
+                        .find(|(field, _)| field == name)
+                        .map(|(_, value)| value.clone())
+                        .unwrap_or_default()
+                };
+                let request = ReceivedRequest {
+                    method: field(":method"),
+                    path: field(":path"),
+                    headers: fields
+                        .iter()
+                        .filter(|(name, _)| !name.starts_with(':'))
+                        .cloned()
+                        .collect(),
+                };
+                let reply = match state.routes.get(&request.path) {
+                    Some(handler) => handler(&request),
+                    None => Reply::status(404, "Not Found", &[], "not found"),
+                };
+                state.received.lock().unwrap().push(request);
+
+                let (status, headers, body) = reply.parse();
+                let mut fields = vec![(":status".to_string(), status)];
+                fields.extend(headers);
+                let mut reply = frame(HEADERS, END_HEADERS, stream_id, &hpack::encode(&fields));
+                reply.extend(frame(DATA, END_STREAM, stream_id, &body));
+                reply
+            }
+            // the client's window is large enough for any reply here
+            _ => continue,
+        };
+        if stream
+            .write_all(&reply)
+            .and_then(|_| stream.flush())
+            .is_err()
+        {
+            return;
+        }
+    }
+}
+
+/// A root certificate written to a temporary PEM file, and a server config
+/// with a `localhost` certificate issued by it.
+fn self_signed_tls() -> (ServerConfig, PathBuf) {
+    static COUNT: AtomicUsize = AtomicUsize::new(0);
+
+    let mut root_params = CertificateParams::new(Vec::<String>::new()).unwrap();
+    root_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    root_params
+        .distinguished_name
+        .push(DnType::CommonName, "browser-engineering test root");
+    let root = CertifiedIssuer::self_signed(root_params, KeyPair::generate().unwrap()).unwrap();
+
+    let key = KeyPair::generate().unwrap();
+    let cert = CertificateParams::new(vec!["localhost".to_string()])
+        .unwrap()
+        .signed_by(&key, &root)
+        .unwrap();
+
+    let root_cert = std::env::temp_dir().join(format!(
+        "test-root-{}-{}.pem",
+        std::process::id(),
+        COUNT.fetch_add(1, Ordering::SeqCst)
+    ));
+    std::fs::write(&root_cert, root.pem()).unwrap();
+
+    let config = ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(
+            vec![cert.der().clone()],
+            PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(key.serialize_der())),
+        )
+        .unwrap();
+
+    (config, root_cert)
+}
@@ -0,0 +1,657 @@
+//! How the client gets a byte stream to a server. Normally that is a TCP (and
+//! TLS) connection, but tests can record what servers send into a fixture
+//! directory and replay it later without touching the network.
+use rustls::{ClientConfig, ClientConnection, StreamOwned};
+use std::error::Error;
+use std::fs;
+use std::io::{self, Cursor, ErrorKind, Read, Write};
+use std::net::TcpStream;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use super::gemini::{self, KnownHosts};
+use super::http2;
+use super::resolver::Resolver;
+use crate::tls::{self, TlsConfig};
+use crate::url::URL;
+
+pub trait Stream: Read + Write + Send {}
+
+impl<T: Read + Write + Send> Stream for T {}
+
+pub struct Connection {
+    pub stream: Box<dyn Stream>,
+    /// The server picked HTTP/2 during ALPN.
+    pub http2: bool,
+    /// To be told when the response is complete, for recorded connections.
+    pub recording: Option<Recording>,
+}
+
+pub trait Transport: Send + Sync {
+    /// Opens a connection to the host and port of `url`, with TLS for https.
+    fn connect(&self, url: &URL) -> Result<Connection, Box<dyn Error>>;
+}
+
+pub struct NetworkTransport {
+    tls_config: Arc<ClientConfig>,
+    /// Trusts certificates on first use instead of checking them against roots.
+    gemini_tls_config: Arc<ClientConfig>,
+    resolver: Arc<Resolver>,
+}
+
+impl NetworkTransport {
+    pub fn new(tls: &TlsConfig) -> Result<NetworkTransport, Box<dyn Error>> {
+        let mut tls_config = tls.client_config()?;
+        tls_config.alpn_protocols = vec![http2::ALPN_PROTOCOL.to_vec(), b"http/1.1".to_vec()];
+
+        Ok(NetworkTransport {
+            tls_config: Arc::new(tls_config),
+            gemini_tls_config: Arc::new(gemini::client_config(Arc::default())),
+            resolver: Arc::new(Resolver::default()),
+        })
+    }
+
+    /// Checks gemini certificates against `known_hosts` instead of a fresh store.
+    pub fn with_known_hosts(mut self, known_hosts: KnownHosts) -> NetworkTransport {
+        self.gemini_tls_config = Arc::new(gemini::client_config(Arc::new(known_hosts)));
+        self
+    }
+
+    pub fn with_resolver(mut self, resolver: Resolver) -> NetworkTransport {
+        self.resolver = Arc::new(resolver);
+        self
+    }
+
+    /// Don't offer `h2`, so every response is a plain HTTP/1.1 byte stream.
+    pub fn http1_only(self) -> NetworkTransport {
+        let mut tls_config = (*self.tls_config).clone();
+        tls_config.alpn_protocols = vec![b"http/1.1".to_vec()];
+
+        NetworkTransport {
+            tls_config: Arc::new(tls_config),
+            gemini_tls_config: self.gemini_tls_config,
+            resolver: self.resolver,
+        }
+    }
+
+    fn connect_tcp(&self, url: &URL) -> Result<TcpStream, Box<dyn Error>> {
+        let domain = url.domain();
+        let (host, port) = domain.rsplit_once(':').ok_or("url without port")?;
+        let tcp_stream = self.resolver.connect(host, port.parse()?).map_err(|e| {
+            log::warn!("could not create tcp connection to {domain}: {e}");
+            e
+        })?;
+
+        log::debug!("tcp connected to {domain}");
+
+        Ok(tcp_stream)
+    }
+
+    fn connect_tls(
+        &self,
+        url: &URL,
+        tls_config: Arc<ClientConfig>,
+    ) -> Result<StreamOwned<ClientConnection, TcpStream>, Box<dyn Error>> {
+        let mut tcp_stream = self.connect_tcp(url)?;
+
+        // create TLS connection
+        let server_name = url.host.clone().try_into()?;
+        let mut tls_conn = ClientConnection::new(tls_config, server_name).map_err(|e| {
+            log::warn!("could not create tls connection: {e}");
+            e
+        })?;
+
+        // finish the handshake up front so we know which protocol ALPN picked,
+        // this is also where certificate errors show up
+        while tls_conn.is_handshaking() {
+            tls_conn.complete_io(&mut tcp_stream).map_err(|e| {
+                log::warn!("tls handshake with {} failed: {e}", url.host);
+                tls::map_io_error(&url.host, e)
+            })?;
+        }
+
+        log::debug!("tls established with {}", url.host);
+
+        Ok(StreamOwned::new(tls_conn, tcp_stream))
+    }
+}
+
+impl Transport for NetworkTransport {
+    fn connect(&self, url: &URL) -> Result<Connection, Box<dyn Error>> {
+        match url.scheme.as_str() {
+            "http" => Ok(Connection {
+                stream: Box::new(self.connect_tcp(url)?),
+                http2: false,
+                recording: None,
+            }),
+            "https" => {
+                let tls_stream = self.connect_tls(url, self.tls_config.clone())?;
+                let http2 = tls_stream.conn.alpn_protocol() == Some(http2::ALPN_PROTOCOL);
+                if http2 {
+                    log::debug!("negotiated http2 with {}", url.host);
+                    // the connection's thread looks for requests to send between reads
+                    tls_stream
+                        .sock
+                        .set_read_timeout(Some(http2::POLL_INTERVAL))?;
+                }
+
+                Ok(Connection {
+                    stream: Box::new(tls_stream),
+                    http2,
+                    recording: None,
+                })
+            }
+            // websockets upgrade an HTTP/1.1 connection, so h2 is never offered
+            "ws" => Ok(Connection {
+                stream: Box::new(self.connect_tcp(url)?),
+                http2: false,
+                recording: None,
+            }),
+            "wss" => {
+                let mut tls_config = (*self.tls_config).clone();
+                tls_config.alpn_protocols = vec![b"http/1.1".to_vec()];
+
+                Ok(Connection {
+                    stream: Box::new(self.connect_tls(url, Arc::new(tls_config))?),
+                    http2: false,
+                    recording: None,
+                })
+            }
+            "gopher" => Ok(Connection {
+                stream: Box::new(self.connect_tcp(url)?),
+                http2: false,
+                recording: None,
+            }),
+            "gemini" => Ok(Connection {
+                stream: Box::new(self.connect_tls(url, self.gemini_tls_config.clone())?),
+                http2: false,
+                recording: None,
+            }),
+            scheme => Err(format!("unsupported scheme {scheme}").into()),
+        }
+    }
+}
+
+/// Goes to the network over HTTP/1.1 and saves everything each server sends
+/// into `dir`, one file per request.
+pub struct RecordingTransport {
+    inner: NetworkTransport,
+    dir: PathBuf,
+}
+
+impl RecordingTransport {
+    pub fn new(
+        inner: NetworkTransport,
+        dir: PathBuf,
+    ) -> Result<RecordingTransport, Box<dyn Error>> {
+        fs::create_dir_all(&dir)?;
+
+        Ok(RecordingTransport {
+            inner: inner.http1_only(),
+            dir,
+        })
+    }
+}
+
+impl Transport for RecordingTransport {
+    fn connect(&self, url: &URL) -> Result<Connection, Box<dyn Error>> {
+        let connection = self.inner.connect(url)?;
+        let recording = Recording(Arc::new(Mutex::new(Exchange {
+            url: url.clone(),
+            dir: self.dir.clone(),
+            sent: Vec::new(),
+            received: Vec::new(),
+            saved: false,
+        })));
+
+        Ok(Connection {
+            stream: Box::new(RecordingStream {
+                inner: connection.stream,
+                recording: recording.clone(),
+            }),
+            http2: false,
+            recording: Some(recording),
+        })
+    }
+}
+
+/// What went over one recorded connection.
+struct Exchange {
+    url: URL,
+    dir: PathBuf,
+    sent: Vec<u8>,
+    received: Vec<u8>,
+    saved: bool,
+}
+
+/// Saves a recorded exchange once the client has read the response to the end
+/// its framing gives, so nothing has to wait for the server to close.
+#[derive(Clone)]
+pub struct Recording(Arc<Mutex<Exchange>>);
+
+impl Recording {
+    pub fn complete(&self) {
+        let mut exchange = self.0.lock().unwrap();
+        if exchange.saved {
+            return;
+        }
+        let Some(method) = request_method(&exchange.url, &exchange.sent) else {
+            return;
+        };
+
+        let path = fixture_path(
+            &exchange.dir,
+            &method,
+            &exchange.url,
+            &header_names(&exchange.sent),
+        );
+        match fs::write(&path, &exchange.received) {
+            Ok(()) => log::info!("recorded {method} {} to {}", exchange.url, path.display()),
+            Err(e) => log::error!("could not record {}: {e}", path.display()),
+        }
+        exchange.saved = true;
+    }
+}
+
+struct RecordingStream {
+    inner: Box<dyn Stream>,
+    recording: Recording,
+}
+
+impl Read for RecordingStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        let mut exchange = self.recording.0.lock().unwrap();
+        exchange.received.extend_from_slice(&buf[..read]);
+        Ok(read)
+    }
+}
+
+impl Write for RecordingStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        let mut exchange = self.recording.0.lock().unwrap();
+        exchange.sent.extend_from_slice(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl Drop for RecordingStream {
+    /// A response that was not read to its end would replay truncated, and
+    /// upgrades and event streams have no end, none of those are saved.
+    fn drop(&mut self) {
+        let exchange = self.recording.0.lock().unwrap();
+        if !exchange.saved && !exchange.sent.is_empty() {
+            log::debug!("not recording {}, the response did not end", exchange.url);
+        }
+    }
+}
+
+/// Serves the responses saved by `RecordingTransport`, byte for byte, and never
+/// opens a socket.
+pub struct ReplayTransport {
+    dir: PathBuf,
+}
+
+impl ReplayTransport {
+    pub fn new(dir: PathBuf) -> ReplayTransport {
+        ReplayTransport { dir }
+    }
+}
+
+impl Transport for ReplayTransport {
+    fn connect(&self, url: &URL) -> Result<Connection, Box<dyn Error>> {
+        Ok(Connection {
+            stream: Box::new(ReplayStream {
+                url: url.clone(),
+                dir: self.dir.clone(),
+                sent: Vec::new(),
+                response: None,
+            }),
+            http2: false,
+            recording: None,
+        })
+    }
+}
+
+struct ReplayStream {
+    url: URL,
+    dir: PathBuf,
+    sent: Vec<u8>,
+    /// Loaded on the first read, when the request line is known.
+    response: Option<Cursor<Vec<u8>>>,
+}
+
+impl Read for ReplayStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.response.is_none() {
+            let method = request_method(&self.url, &self.sent).ok_or(io::Error::new(
+                ErrorKind::InvalidInput,
+                "read before a request was sent",
+            ))?;
+            let path = fixture_path(&self.dir, &method, &self.url, &header_names(&self.sent));
+            let bytes = fs::read(&path).map_err(|e| {
+                io::Error::new(
+                    e.kind(),
+                    format!("no fixture for {method} {} at {}", self.url, path.display()),
+                )
+            })?;
+            log::info!("replaying {method} {} from {}", self.url, path.display());
+            self.response = Some(Cursor::new(bytes));
+        }
+
+        self.response.as_mut().expect("loaded above").read(buf)
+    }
+}
+
+impl Write for ReplayStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.sent.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Gemini and gopher requests are a bare line without a method, they get the
+/// same made up ones the test server uses.
+fn request_method(url: &URL, sent: &[u8]) -> Option<String> {
+    if sent.is_empty() {
+        return None;
+    }
+
+    match url.scheme.as_str() {
+        "gemini" => Some("GEMINI".to_string()),
+        "gopher" => Some("GOPHER".to_string()),
+        _ => {
+            let line_end = sent.iter().position(|&b| b == b' ')?;
+            Some(String::from_utf8_lossy(&sent[..line_end]).to_string())
+        }
+    }
+}
+
+/// Lowercased and sorted. The values are left out of fixture names because
+/// some change on every request, like the cnonce of digest authentication.
+fn header_names(sent: &[u8]) -> String {
+    let head = String::from_utf8_lossy(sent);
+    let mut names: Vec<String> = head
+        .lines()
+        .skip(1)
+        .take_while(|line| !line.is_empty())
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, _)| name.trim().to_lowercase())
+        .collect();
+    names.sort();
+    names.join(",")
+}
+
+/// Fixtures are named after the request so they can be found again. The hash
+/// covers the header names too, so a `401` and the retry with `authorization`
+/// get one each, and keeps urls that sanitize to the same name apart.
+pub fn fixture_path(dir: &Path, method: &str, url: &URL, header_names: &str) -> PathBuf {
+    let key = format!("{method} {url}");
+
+    let readable: String = key
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '.' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .take(100)
+        .collect();
+
+    let hash = fnv1a(format!("{key}\n{header_names}").as_bytes());
+    dir.join(format!("{readable}-{hash:016x}.http"))
+}
+
+/// Stable across runs and platforms, unlike `DefaultHasher`.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+#[test]
+fn fixture_names_are_stable() {
+    let url = URL::parse("https://example.com/a/b.html").unwrap();
+    let dir = Path::new("fixtures");
+    let path = fixture_path(dir, "GET", &url, "accept,host");
+
+    assert_eq!(
+        path,
+        dir.join("GET_https___example.com_a_b.html-597160d44cde42d9.http")
+    );
+    assert_ne!(
+        path,
+        fixture_path(dir, "GET", &url, "accept,authorization,host")
+    );
+}
+
+#[test]
+fn header_names_ignore_values_and_order() {
+    assert_eq!(
+        header_names(b"GET / HTTP/1.1\r\nHost: a\r\nAccept: */*\r\n\r\nbody: no\r\n"),
+        "accept,host"
+    );
+    assert_eq!(header_names(b"gemini://example.com/\r\n"), "");
+}
+
+/// A fixture directory for one test, removed when the test ends, whether it passed or not.
+#[cfg(test)]
+struct FixtureDir(PathBuf);
+
+#[cfg(test)]
+impl FixtureDir {
+    fn new(name: &str) -> Self {
+        FixtureDir(std::env::temp_dir().join(format!("fixtures-{name}-{}", std::process::id())))
+    }
+}
+
+#[cfg(test)]
+impl Drop for FixtureDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+#[test]
+fn recorded_responses_replay_offline() {
+    use super::test_server::{Reply, TestServer};
+
+    let dir = FixtureDir::new("replay");
+    let server = TestServer::builder()
+        .route("/page.html", Reply::ok("<p>live</p>"))
+        .http();
+    let url = server.url("/page.html");
+
+    let network = NetworkTransport::new(&TlsConfig::default()).unwrap();
+    let recording =
+        super::Client::with_transport(RecordingTransport::new(network, dir.0.clone()).unwrap());
+    let mut live = recording.get_streaming(url.clone(), None).unwrap();
+    let live_body = super::read_body(&mut live);
+    drop(live);
+    drop(server);
+
+    // nothing listens on the port anymore, only the fixture can answer
+    let replaying = super::Client::with_transport(ReplayTransport::new(dir.0.clone()));
+    let mut replayed = replaying.get_streaming(url, None).unwrap();
+    let replayed_body = super::read_body(&mut replayed);
+
+    assert_eq!(replayed.status, 200);
+    assert_eq!(replayed_body, live_body);
+    assert_eq!(replayed_body, "<p>live</p>");
+}
+
+#[test]
+fn retries_with_credentials_get_their_own_fixture() {
+    use super::test_server::{Reply, TestServer};
+    use super::{AuthenticationRequired, CredentialStore, Credentials};
+
+    let dir = FixtureDir::new("auth");
+    let server = TestServer::builder()
+        .route_fn("/tools", |request| {
+            match request.headers.get("authorization").map(String::as_str) {
+                // alice:secret
+                Some("Basic YWxpY2U6c2VjcmV0") => Reply::ok("<p>welcome</p>"),
+                _ => Reply::status(
+                    401,
+                    "Unauthorized",
+                    &[("WWW-Authenticate", r#"Basic realm="intranet""#)],
+                    "",
+                ),
+            }
+        })
+        .http();
+    let url = server.url("/tools");
+    let origin = URL::parse(&url).unwrap().origin();
+
+    let network = NetworkTransport::new(&TlsConfig::default()).unwrap();
+    let store = Arc::new(CredentialStore::default());
+    store.insert(
+        &origin,
+        "intranet",
+        Credentials::from_userinfo("alice:secret"),
+    );
+    let recording =
+        super::Client::with_transport(RecordingTransport::new(network, dir.0.clone()).unwrap())
+            .with_credential_store(store);
+    let mut live = recording.get_streaming(url.clone(), None).unwrap();
+    assert_eq!(super::read_body(&mut live), "<p>welcome</p>");
+    drop(live);
+    drop(server);
+
+    // without credentials the replayed 401 has to come back, not the page
+    let replaying = super::Client::with_transport(ReplayTransport::new(dir.0.clone()));
+    let error = replaying.get_streaming(url.clone(), None).unwrap_err();
+    assert!(error.downcast_ref::<AuthenticationRequired>().is_some());
+
+    let store = Arc::new(CredentialStore::default());
+    store.insert(
+        &origin,
+        "intranet",
+        Credentials::from_userinfo("alice:secret"),
+    );
+    let replaying = super::Client::with_transport(ReplayTransport::new(dir.0.clone()))
+        .with_credential_store(store);
+    let mut replayed = replaying.get_streaming(url, None).unwrap();
+    let replayed_body = super::read_body(&mut replayed);
+
+    assert_eq!(replayed_body, "<p>welcome</p>");
+}
+
+#[test]
+fn bodies_read_halfway_are_not_recorded() {
+    use super::test_server::{Reply, TestServer};
+
+    let dir = FixtureDir::new("partial");
+    let server = TestServer::builder()
+        .route("/big.txt", Reply::ok(&"x".repeat(100_000)))
+        .http();
+    let url = server.url("/big.txt");
+
+    let network = NetworkTransport::new(&TlsConfig::default()).unwrap();
+    let recording =
+        super::Client::with_transport(RecordingTransport::new(network, dir.0.clone()).unwrap());
+    let mut live = recording.get_streaming(url.clone(), None).unwrap();
+    live.read_exact(&mut [0; 10]).unwrap();
+    drop(live);
+    drop(server);
+
+    let replaying = super::Client::with_transport(ReplayTransport::new(dir.0.clone()));
+    let error = replaying.get_streaming(url, None).unwrap_err();
+
+    assert!(error.to_string().contains("no fixture"), "{error}");
+}
+
+#[test]
+fn responses_are_recorded_without_waiting_for_the_server_to_close() {
+    use std::net::TcpListener;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    // answers, then holds on to the connection instead of closing it
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!(
+        "http://localhost:{}/",
+        listener.local_addr().unwrap().port()
+    );
+    thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let _ = stream.read(&mut [0; 1024]);
+        stream
+            .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello")
+            .unwrap();
+        thread::sleep(Duration::from_secs(30));
+    });
+
+    let dir = FixtureDir::new("open");
+    let network = NetworkTransport::new(&TlsConfig::default()).unwrap();
+    let recording =
+        super::Client::with_transport(RecordingTransport::new(network, dir.0.clone()).unwrap());
+    let started = Instant::now();
+    let mut live = recording.get_streaming(url.clone(), None).unwrap();
+    assert_eq!(super::read_body(&mut live), "hello");
+    drop(live);
+    assert!(started.elapsed() < Duration::from_secs(10));
+
+    let replaying = super::Client::with_transport(ReplayTransport::new(dir.0.clone()));
+    let mut replayed = replaying.get_streaming(url, None).unwrap();
+    let replayed_body = super::read_body(&mut replayed);
+
+    assert_eq!(replayed_body, "hello");
+}
+
+#[cfg(test)]
+fn record_then_replay(server: super::test_server::TestServer, path: &str, name: &str) -> String {
+    let dir = FixtureDir::new(name);
+    let url = server.url(path);
+
+    let network = NetworkTransport::new(&server.tls_config()).unwrap();
+    let recording =
+        super::Client::with_transport(RecordingTransport::new(network, dir.0.clone()).unwrap());
+    let mut live = recording.get_streaming(url.clone(), None).unwrap();
+    let live_body = super::read_body(&mut live);
+    drop(live);
+    drop(server);
+
+    let replaying = super::Client::with_transport(ReplayTransport::new(dir.0.clone()));
+    let mut replayed = replaying.get_streaming(url, None).unwrap();
+    let replayed_body = super::read_body(&mut replayed);
+
+    assert_eq!(replayed_body, live_body);
+    replayed_body
+}
+
+#[test]
+fn gemini_replays_offline() {
+    use super::test_server::{Reply, TestServer};
+
+    let server = TestServer::builder()
+        .route("/", Reply::raw("20 text/gemini\r\n# Hello\n"))
+        .gemini();
+
+    assert_eq!(record_then_replay(server, "/", "gemini"), "# Hello\n");
+}
+
+#[test]
+fn gopher_replays_offline() {
+    use super::test_server::{Reply, TestServer};
+
+    let server = TestServer::builder()
+        .route("/about.txt", Reply::raw("All about us\r\n"))
+        .gopher();
+
+    assert_eq!(
+        record_then_replay(server, "/0/about.txt", "gopher"),
+        "All about us\r\n"
+    );
+}
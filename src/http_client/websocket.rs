@@ -0,0 +1,467 @@
+//! WebSocket client, https://www.rfc-editor.org/rfc/rfc6455. The connection
+//! starts as an HTTP/1.1 request that asks to be upgraded, after that both
+//! sides send frames. Pings are answered while receiving, fragmented messages
+//! are put back together and long messages are split up when sending.
+use std::error::Error;
+use std::io::{BufRead, BufReader, Write};
+
+use super::auth::base64;
+use super::body;
+use super::hash::{random_bytes, sha1};
+use super::transport::Stream;
+use crate::url::URL;
+
+/// Appended to the key before hashing, the server proves it speaks WebSocket
+/// by doing the same.
+const ACCEPT_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// Messages longer than this are sent as several frames.
+const MAX_FRAME_PAYLOAD: usize = 16 * 1024;
+
+/// Anything longer is refused instead of filling up memory.
+const MAX_MESSAGE_SIZE: u64 = 64 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Opcode {
+    Continuation,
+    Text,
+    Binary,
+    Close,
+    Ping,
+    Pong,
+}
+
+impl Opcode {
+    fn from_bits(bits: u8) -> Result<Opcode, Box<dyn Error>> {
+        Ok(match bits {
+            0x0 => Opcode::Continuation,
+            0x1 => Opcode::Text,
+            0x2 => Opcode::Binary,
+            0x8 => Opcode::Close,
+            0x9 => Opcode::Ping,
+            0xa => Opcode::Pong,
+            bits => return Err(format!("unknown websocket opcode {bits:#x}").into()),
+        })
+    }
+
+    fn bits(self) -> u8 {
+        match self {
+            Opcode::Continuation => 0x0,
+            Opcode::Text => 0x1,
+            Opcode::Binary => 0x2,
+            Opcode::Close => 0x8,
+            Opcode::Ping => 0x9,
+            Opcode::Pong => 0xa,
+        }
+    }
+
+    fn is_control(self) -> bool {
+        matches!(self, Opcode::Close | Opcode::Ping | Opcode::Pong)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    pub fin: bool,
+    pub opcode: Opcode,
+    /// Already unmasked.
+    pub payload: Vec<u8>,
+    pub masked: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CloseFrame {
+    pub code: u16,
+    pub reason: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Message {
+    Text(String),
+    Binary(Vec<u8>),
+    /// The server closed the connection, with its reason if it gave one.
+    Close(Option<CloseFrame>),
+}
+
+pub struct WebSocket {
+    /// Reads frames, writes go to the stream underneath.
+    reader: BufReader<Box<dyn Stream>>,
+    protocol: Option<String>,
+    closed: bool,
+}
+
+impl WebSocket {
+    /// Sends the upgrade request over `stream` and checks the server agreed.
+    /// `protocols` are offered as `Sec-WebSocket-Protocol`, the server picks one.
+    pub fn handshake(
+        mut stream: Box<dyn Stream>,
+        url: &URL,
+        protocols: &[&str],
+    ) -> Result<WebSocket, Box<dyn Error>> {
+        let key = base64(&random_bytes()[..16]);
+
+        let host = match &url.port {
+            Some(port) => format!("{}:{port}", url.host),
+            None => url.host.clone(),
+        };
+        let mut request = format!(
+            "GET {} HTTP/1.1\r\n\
+             Host: {host}\r\n\
+             Upgrade: websocket\r\n\
+             Connection: Upgrade\r\n\
+             Sec-WebSocket-Key: {key}\r\n\
+             Sec-WebSocket-Version: 13\r\n",
+            url.path
+        );
+        if !protocols.is_empty() {
+            request.push_str(&format!(
+                "Sec-WebSocket-Protocol: {}\r\n",
+                protocols.join(", ")
+            ));
+        }
+        request.push_str("\r\n");
+        log::trace!("websocket request:\n{request}");
+
+        stream.write_all(request.as_bytes())?;
+        stream.flush()?;
+
+        let mut reader = BufReader::new(stream);
+        let head = body::read_head(&mut reader)?;
+        log::debug!("{} {} {}", head.version, head.status, head.explanation);
+
+        if head.status != 101 {
+            return Err(format!(
+                "websocket upgrade refused with {} {}",
+                head.status, head.explanation
+            )
+            .into());
+        }
+        let has_token = |name: &str, token: &str| {
+            head.headers.get(name).is_some_and(|value| {
+                value
+                    .split(',')
+                    .any(|part| part.trim().eq_ignore_ascii_case(token))
+            })
+        };
+        if !has_token("upgrade", "websocket") || !has_token("connection", "upgrade") {
+            return Err("server did not upgrade the connection to websocket".into());
+        }
+        if head.headers.get("sec-websocket-accept") != Some(&accept(&key)) {
+            return Err("server answered with the wrong sec-websocket-accept".into());
+        }
+        if head.headers.contains_key("sec-websocket-extensions") {
+            return Err("server picked a websocket extension that was not offered".into());
+        }
+
+        let protocol = head.headers.get("sec-websocket-protocol").cloned();
+        if let Some(protocol) = &protocol
+            && !protocols.contains(&protocol.as_str())
+        {
+            return Err(format!("server picked the unknown subprotocol {protocol}").into());
+        }
+
+        log::debug!("websocket open to {url}");
+        Ok(WebSocket {
+            reader,
+            protocol,
+            closed: false,
+        })
+    }
+
+    /// The subprotocol the server picked.
+    pub fn protocol(&self) -> Option<&str> {
+        self.protocol.as_deref()
+    }
+
+    pub fn send(&mut self, message: Message) -> Result<(), Box<dyn Error>> {
+        let (opcode, payload) = match message {
+            Message::Text(text) => (Opcode::Text, text.into_bytes()),
+            Message::Binary(bytes) => (Opcode::Binary, bytes),
+            Message::Close(close) => {
+                let close = close.unwrap_or(CloseFrame {
+                    code: 1000,
+                    reason: String::new(),
+                });
+                return self.close(close.code, &close.reason);
+            }
+        };
+        self.check_open()?;
+
+        // an empty message is still one frame
+        let mut chunks: Vec<&[u8]> = payload.chunks(MAX_FRAME_PAYLOAD).collect();
+        if chunks.is_empty() {
+            chunks.push(&[]);
+        }
+
+        let last = chunks.len() - 1;
+        for (i, chunk) in chunks.into_iter().enumerate() {
+            self.write(Frame {
+                fin: i == last,
+                opcode: if i == 0 { opcode } else { Opcode::Continuation },
+                payload: chunk.to_vec(),
+                masked: true,
+            })?;
+        }
+        Ok(())
+    }
+
+    pub fn ping(&mut self, payload: &[u8]) -> Result<(), Box<dyn Error>> {
+        self.check_open()?;
+        if payload.len() > 125 {
+            return Err("ping payload is longer than 125 bytes".into());
+        }
+        self.write(Frame {
+            fin: true,
+            opcode: Opcode::Ping,
+            payload: payload.to_vec(),
+            masked: true,
+        })
+    }
+
+    /// Waits for the next whole message. Pings are answered on the way, and
+    /// when the server closes the close is echoed back before returning it.
+    pub fn receive(&mut self) -> Result<Message, Box<dyn Error>> {
+        self.check_open()?;
+
+        let mut message: Option<(Opcode, Vec<u8>)> = None;
+        loop {
+            let frame = read_frame(&mut self.reader)?;
+            if frame.masked {
+                return Err("server sent a masked frame".into());
+            }
+
+            match frame.opcode {
+                Opcode::Ping => {
+                    log::trace!("websocket ping, answering");
+                    self.write(Frame {
+                        fin: true,
+                        opcode: Opcode::Pong,
+                        payload: frame.payload,
+                        masked: true,
+                    })?;
+                }
+                Opcode::Pong => log::trace!("websocket pong"),
+                Opcode::Close => {
+                    let close = parse_close(&frame.payload)?;
+                    // echo the code back, that finishes the closing handshake
+                    self.write(Frame {
+                        fin: true,
+                        opcode: Opcode::Close,
+                        payload: frame.payload[..frame.payload.len().min(2)].to_vec(),
+                        masked: true,
+                    })?;
+                    self.closed = true;
+                    log::debug!("websocket closed by the server: {close:?}");
+                    return Ok(Message::Close(close));
+                }
+                Opcode::Text | Opcode::Binary => {
+                    if message.is_some() {
+                        return Err("new websocket message before the last one ended".into());
+                    }
+                    message = Some((frame.opcode, frame.payload));
+                }
+                Opcode::Continuation => match &mut message {
+                    Some((_, payload)) => {
+                        if (payload.len() + frame.payload.len()) as u64 > MAX_MESSAGE_SIZE {
+                            return Err("websocket message is too long".into());
+                        }
+                        payload.extend(frame.payload);
+                    }
+                    None => return Err("websocket continuation without a message".into()),
+                },
+            }
+
+            if frame.fin
+                && !frame.opcode.is_control()
+                && let Some((opcode, payload)) = message.take()
+            {
+                return match opcode {
+                    Opcode::Text => Ok(Message::Text(String::from_utf8(payload)?)),
+                    _ => Ok(Message::Binary(payload)),
+                };
+            }
+        }
+    }
+
+    /// Sends a close frame and waits for the server to close too. Messages
+    /// that are still on the way are dropped.
+    pub fn close(&mut self, code: u16, reason: &str) -> Result<(), Box<dyn Error>> {
+        self.check_open()?;
+
+        let mut payload = code.to_be_bytes().to_vec();
+        payload.extend(reason.as_bytes());
+        if payload.len() > 125 {
+            return Err("close reason is longer than 123 bytes".into());
+        }
+        self.write(Frame {
+            fin: true,
+            opcode: Opcode::Close,
+            payload,
+            masked: true,
+        })?;
+        self.closed = true;
+
+        loop {
+            let frame = read_frame(&mut self.reader)?;
+            if frame.opcode == Opcode::Close {
+                log::debug!("websocket closed: {:?}", parse_close(&frame.payload)?);
+                return Ok(());
+            }
+        }
+    }
+
+    fn check_open(&self) -> Result<(), Box<dyn Error>> {
+        match self.closed {
+            true => Err("websocket is closed".into()),
+            false => Ok(()),
+        }
+    }
+
+    fn write(&mut self, frame: Frame) -> Result<(), Box<dyn Error>> {
+        let stream = self.reader.get_mut();
+        stream.write_all(&encode_frame(&frame))?;
+        stream.flush()?;
+        Ok(())
+    }
+}
+
+/// `base64(sha1(key + GUID))`, what the server has to send back as
+/// `Sec-WebSocket-Accept`.
+pub fn accept(key: &str) -> String {
+    base64(&sha1(format!("{key}{ACCEPT_GUID}").as_bytes()))
+}
+
+/// The frame as it goes on the wire. Masked frames get a fresh masking key,
+/// clients have to mask everything they send.
+pub fn encode_frame(frame: &Frame) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(frame.payload.len() + 14);
+    bytes.push(if frame.fin { 0x80 } else { 0 } | frame.opcode.bits());
+
+    let mask_bit = if frame.masked { 0x80 } else { 0 };
+    match frame.payload.len() {
+        length @ 0..=125 => bytes.push(mask_bit | length as u8),
+        length @ 126..=0xffff => {
+            bytes.push(mask_bit | 126);
+            bytes.extend((length as u16).to_be_bytes());
+        }
+        length => {
+            bytes.push(mask_bit | 127);
+            bytes.extend((length as u64).to_be_bytes());
+        }
+    }
+
+    if frame.masked {
+        let key: [u8; 4] = random_bytes()[..4].try_into().expect("four bytes");
+        bytes.extend(key);
+        bytes.extend(apply_mask(&frame.payload, key));
+    } else {
+        bytes.extend(&frame.payload);
+    }
+    bytes
+}
+
+pub fn read_frame(reader: &mut impl BufRead) -> Result<Frame, Box<dyn Error>> {
+    let mut header = [0u8; 2];
+    reader.read_exact(&mut header)?;
+
+    if header[0] & 0x70 != 0 {
+        return Err("websocket frame uses reserved bits".into());
+    }
+    let fin = header[0] & 0x80 != 0;
+    let opcode = Opcode::from_bits(header[0] & 0x0f)?;
+    let masked = header[1] & 0x80 != 0;
+
+    let length = match header[1] & 0x7f {
+        126 => {
+            let mut length = [0u8; 2];
+            reader.read_exact(&mut length)?;
+            u16::from_be_bytes(length) as u64
+        }
+        127 => {
+            let mut length = [0u8; 8];
+            reader.read_exact(&mut length)?;
+            u64::from_be_bytes(length)
+        }
+        length => length as u64,
+    };
+
+    if opcode.is_control() && (!fin || length > 125) {
+        return Err("websocket control frame is fragmented or too long".into());
+    }
+    if length > MAX_MESSAGE_SIZE {
+        return Err(format!("websocket frame of {length} bytes is too long").into());
+    }
+
+    let mut key = [0u8; 4];
+    if masked {
+        reader.read_exact(&mut key)?;
+    }
+
+    let mut payload = vec![0u8; length as usize];
+    reader.read_exact(&mut payload)?;
+    if masked {
+        payload = apply_mask(&payload, key);
+    }
+
+    Ok(Frame {
+        fin,
+        opcode,
+        payload,
+        masked,
+    })
+}
+
+/// Masking and unmasking are the same xor.
+fn apply_mask(payload: &[u8], key: [u8; 4]) -> Vec<u8> {
+    payload
+        .iter()
+        .zip(key.iter().cycle())
+        .map(|(byte, mask)| byte ^ mask)
+        .collect()
+}
+
+/// A close payload is empty, or a two byte code followed by a UTF-8 reason.
+fn parse_close(payload: &[u8]) -> Result<Option<CloseFrame>, Box<dyn Error>> {
+    match payload {
+        [] => Ok(None),
+        [_] => Err("websocket close frame with a one byte payload".into()),
+        [high, low, reason @ ..] => Ok(Some(CloseFrame {
+            code: u16::from_be_bytes([*high, *low]),
+            reason: String::from_utf8(reason.to_vec())?,
+        })),
+    }
+}
+
+#[test]
+fn accept_key_from_the_rfc() {
+    // https://www.rfc-editor.org/rfc/rfc6455#section-1.3
+    assert_eq!(
+        accept("dGhlIHNhbXBsZSBub25jZQ=="),
+        "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+    );
+}
+
+#[test]
+fn frames_roundtrip() {
+    for length in [0, 125, 126, 0xffff, 0x10000] {
+        let frame = Frame {
+            fin: length != 126,
+            opcode: Opcode::Binary,
+            payload: vec![7; length],
+            masked: true,
+        };
+        let encoded = encode_frame(&frame);
+        assert_eq!(
+            read_frame(&mut BufReader::new(encoded.as_slice())).unwrap(),
+            frame
+        );
+    }
+
+    // single-frame masked text message, https://www.rfc-editor.org/rfc/rfc6455#section-5.7
+    let hello = [
+        0x81, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58,
+    ];
+    let frame = read_frame(&mut BufReader::new(hello.as_slice())).unwrap();
+    assert_eq!(frame.payload, b"Hello");
+    assert!(read_frame(&mut BufReader::new([0xc1, 0x00].as_slice())).is_err());
+}
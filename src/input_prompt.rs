@@ -0,0 +1,77 @@
+//! The one line text field shown when a server asks for input, like a gemini
+//! search. What is typed is sent back as the query of the same url.
+use winit::keyboard::{Key, NamedKey};
+
+use browser_engineering::html::escape;
+
+use crate::http_client::InputRequired;
+use crate::url::percent_encode;
+
+#[derive(Debug)]
+pub struct InputPrompt {
+    pub required: InputRequired,
+    text: String,
+}
+
+impl InputPrompt {
+    pub fn new(required: InputRequired) -> InputPrompt {
+        InputPrompt {
+            required,
+            text: String::new(),
+        }
+    }
+
+    /// Returns the url to load once Enter is pressed.
+    pub fn key(&mut self, key: &Key, text: Option<&str>) -> Option<String> {
+        match key {
+            Key::Named(NamedKey::Enter) => {
+                let url = match self.required.url.split_once('?') {
+                    Some((url, _)) => url,
+                    None => &self.required.url,
+                };
+                return Some(format!("{url}?{}", percent_encode(&self.text)));
+            }
+            Key::Named(NamedKey::Backspace) => {
+                self.text.pop();
+            }
+            _ => {
+                if let Some(text) = text {
+                    self.text.extend(text.chars().filter(|c| !c.is_control()));
+                }
+            }
+        }
+
+        None
+    }
+
+    pub fn to_html(&self) -> String {
+        let shown = match self.required.sensitive {
+            true => "*".repeat(self.text.chars().count()),
+            false => escape(&self.text, false),
+        };
+
+        format!(
+            "<b>{}</b> {} {shown}_ <i>Enter to send</i>",
+            escape(&self.required.prompt, false),
+            escape(&self.required.url, false),
+        )
+    }
+}
+
+#[test]
+fn typed_text_becomes_the_query() {
+    let mut prompt = InputPrompt::new(InputRequired {
+        url: "gemini://example.org/search?old".to_string(),
+        prompt: "Search".to_string(),
+        sensitive: true,
+    });
+
+    for c in ["a", " ", "b"] {
+        assert_eq!(prompt.key(&Key::Character(c.into()), Some(c)), None);
+    }
+    assert!(prompt.to_html().contains("***_"));
+    assert_eq!(
+        prompt.key(&Key::Named(NamedKey::Enter), None),
+        Some("gemini://example.org/search?a%20b".to_string())
+    );
+}
@@ -0,0 +1,4 @@
+pub mod html;
+pub mod http_client;
+pub mod tls;
+pub mod url;
@@ -0,0 +1,258 @@
+//! Fetches pages on a background thread so the window stays responsive while
+//! requests are in flight. Results are posted back to the winit event loop.
+use std::error::Error;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::mpsc::{self, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use winit::event_loop::EventLoopProxy;
+
+use browser_engineering::html::{self, Diagnostics, Document};
+
+use crate::http_client::{AuthenticationRequired, Client, GOPHER_MENU_MIME_TYPE, InputRequired};
+use crate::url::URL;
+use crate::{gemtext, gophermap, tls};
+
+/// Don't flood the event loop with a redraw for every chunk.
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Debug)]
+pub enum BrowserEvent {
+    /// `url` is where the page is coming from, after any redirects.
+    Progress {
+        url: String,
+        bytes_read: u64,
+        content_length: Option<u64>,
+        /// The page as far as it has arrived, when it is HTML.
+        document: Option<Document>,
+    },
+    /// The page, or an error page if it could not be fetched. `url` is where
+    /// the page ended up after any redirects, what its links are relative to.
+    Loaded { url: String, document: Document },
+    /// Loading `url` needs a username and password nobody has given yet.
+    AuthRequired {
+        url: String,
+        required: AuthenticationRequired,
+    },
+    /// The server asked for a line of text before it shows `required.url`.
+    InputRequired { required: InputRequired },
+}
+
+pub struct Loader {
+    jobs: Sender<String>,
+}
+
+impl Loader {
+    /// Pages are shown, anything else is saved into `downloads`.
+    pub fn spawn(
+        client: Client,
+        proxy: EventLoopProxy<BrowserEvent>,
+        downloads: PathBuf,
+    ) -> Loader {
+        let (jobs, queue) = mpsc::channel::<String>();
+
+        thread::Builder::new()
+            .name("network".to_string())
+            .spawn(move || {
+                for url in queue {
+                    let progress =
+                        |from: &URL, bytes_read, content_length, document: Option<&Document>| {
+                            let _ = proxy.send_event(BrowserEvent::Progress {
+                                url: from.to_string(),
+                                bytes_read,
+                                content_length,
+                                document: document.cloned(),
+                            });
+                        };
+                    let event = match download(&client, &url, &downloads, None, progress) {
+                        Ok((from, document)) => BrowserEvent::Loaded {
+                            url: from.to_string(),
+                            document,
+                        },
+                        Err(e) => match e.downcast::<AuthenticationRequired>() {
+                            Ok(required) => BrowserEvent::AuthRequired {
+                                url,
+                                required: *required,
+                            },
+                            Err(e) => match e.downcast::<InputRequired>() {
+                                Ok(required) => BrowserEvent::InputRequired {
+                                    required: *required,
+                                },
+                                Err(e) => BrowserEvent::Loaded {
+                                    document: html::parse(&error_page(&url, e.as_ref())),
+                                    url,
+                                },
+                            },
+                        },
+                    };
+
+                    if proxy.send_event(event).is_err() {
+                        // the event loop is gone, nobody is waiting for more pages
+                        break;
+                    }
+                }
+            })
+            .expect("could not start network thread");
+
+        Loader { jobs }
+    }
+
+    pub fn load(&self, url: String) {
+        self.jobs
+            .send(url)
+            .expect("network thread stopped unexpectedly");
+    }
+}
+
+/// The page at `url`, or a note about where it was saved if it is not a page,
+/// along with where it ended up after any redirects. `progress` hears about
+/// the bytes read every so often, and for HTML about the page as far as it is
+/// parsed, less often the longer it gets. The parse errors of an HTML page go
+/// into `diagnostics`, markup made from gemtext and gopher has none worth
+/// reporting.
+pub fn download(
+    client: &Client,
+    url: &str,
+    downloads: &Path,
+    diagnostics: Option<Arc<Diagnostics>>,
+    mut progress: impl FnMut(&URL, u64, Option<u64>, Option<&Document>),
+) -> Result<(URL, Document), Box<dyn Error>> {
+    let mut response = client.get_streaming(url.to_string(), None)?;
+    log::debug!("response: {:?}", response);
+
+    if response.is_download() {
+        let from = response.url().clone();
+        let mut last_progress = Instant::now();
+        let saved = client.save(response, downloads, |bytes_read, content_length| {
+            if last_progress.elapsed() >= PROGRESS_INTERVAL {
+                last_progress = Instant::now();
+                progress(&from, bytes_read, content_length, None);
+            }
+        })?;
+        log::info!("saved {url} to {}", saved.display());
+        let document = html::parse(&format!(
+            "<b>Downloaded</b> {url} to <i>{}</i>",
+            saved.display()
+        ));
+        return Ok((from, document));
+    }
+
+    let mime = response
+        .headers
+        .get("content-type")
+        .and_then(|content_type| content_type.split(';').next())
+        .map(|mime| mime.trim().to_lowercase())
+        .unwrap_or_default();
+
+    // everything that isn't html is turned into markup the layout understands,
+    // once all of it is there
+    let to_html: Option<fn(&str, &URL) -> String> =
+        match (mime.as_str(), response.url().scheme.as_str()) {
+            ("text/gemini", _) => Some(gemtext::to_html),
+            (GOPHER_MENU_MIME_TYPE, "gopher") => Some(|body, _| gophermap::menu_to_html(body)),
+            ("text/plain", "gopher") => Some(|body, _| gophermap::text_to_html(body)),
+            _ => None,
+        };
+
+    // html is parsed as it arrives, so the page can be shown before it is complete
+    let mut parser = html::Parser::default();
+    if let Some(diagnostics) = diagnostics {
+        parser = parser.with_diagnostics(diagnostics);
+    }
+    let mut body = Vec::new();
+    let mut chunk = [0u8; 16 * 1024];
+    let mut last_progress = Instant::now();
+    // every partial page shown is a copy of the whole tree, so one is only
+    // made once the page has doubled since the last, which keeps all the
+    // copies together linear in the size of the page instead of quadratic
+    let mut shown_at = 0;
+
+    loop {
+        let read = response.read(&mut chunk)?;
+        if read == 0 {
+            break;
+        }
+        match to_html {
+            Some(_) => body.extend_from_slice(&chunk[..read]),
+            None => parser.feed_bytes(&chunk[..read]),
+        }
+
+        if last_progress.elapsed() >= PROGRESS_INTERVAL {
+            last_progress = Instant::now();
+            let bytes_read = response.bytes_read();
+            match to_html {
+                Some(_) => progress(response.url(), bytes_read, response.content_length(), None),
+                None if bytes_read >= 2 * shown_at => {
+                    shown_at = bytes_read;
+                    progress(
+                        response.url(),
+                        bytes_read,
+                        response.content_length(),
+                        Some(parser.document()),
+                    );
+                }
+                // the page shown so far stays up, there is no loading page to update
+                None => {}
+            }
+        }
+    }
+
+    let document = match to_html {
+        Some(to_html) => html::parse(&to_html(&String::from_utf8_lossy(&body), response.url())),
+        None => parser.finish(),
+    };
+    Ok((response.url().clone(), document))
+}
+
+/// `~/Downloads` when there is one, otherwise the current directory.
+pub fn default_downloads_dir() -> PathBuf {
+    std::env::var_os("HOME")
+        .map(|home| PathBuf::from(home).join("Downloads"))
+        .filter(|dir| dir.is_dir())
+        .unwrap_or(PathBuf::from("."))
+}
+
+pub fn loading_page(url: &str, bytes_read: u64, content_length: Option<u64>) -> String {
+    let url = html::escape(url, false);
+    match (bytes_read, content_length) {
+        (0, _) => format!("<b>Loading</b> {url}"),
+        (read, Some(total)) => format!("<b>Loading</b> {url} <i>{read} of {total} bytes</i>"),
+        (read, None) => format!("<b>Loading</b> {url} <i>{read} bytes</i>"),
+    }
+}
+
+fn error_page(url: &str, error: &(dyn Error + 'static)) -> String {
+    log::warn!("could not load {url}: {error}");
+    let url = html::escape(url, false);
+
+    match error.downcast_ref::<tls::CertificateError>() {
+        Some(certificate_error) => format!(
+            "<b>Your connection is not private</b> \
+             Could not load {url} because {}. \
+             Pass the issuing CA with <i>--ca-cert</i> or <i>--system-roots</i>, \
+             or use <i>--insecure</i> if you are testing locally.",
+            html::escape(&certificate_error.to_string(), false)
+        ),
+        None => format!(
+            "<b>Could not load page</b> {url}: {}",
+            html::escape(&error.to_string(), false)
+        ),
+    }
+}
+
+#[test]
+fn loading_pages_escape_the_url() {
+    let document = html::parse(&loading_page("http://example.com/<i>", 10, None));
+    assert_eq!(document.query_selector_all("i").unwrap().len(), 1);
+}
+
+#[test]
+fn error_pages_escape_what_they_show() {
+    let error: Box<dyn Error> = "bad status line \"<script>\"".into();
+    let document = html::parse(&error_page("http://example.com/<b>", error.as_ref()));
+    assert_eq!(document.query_selector_all("b").unwrap().len(), 1);
+    assert!(document.query_selector("script").unwrap().is_none());
+}
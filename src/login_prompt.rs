@@ -0,0 +1,123 @@
+//! The username and password form shown in the window when a page needs
+//! credentials. Keys are fed in from the event loop, the form is drawn by
+//! rendering `to_html` like any other page.
+use winit::keyboard::{Key, NamedKey};
+
+use browser_engineering::html::escape;
+
+use crate::http_client::{AuthenticationRequired, Credentials};
+
+#[derive(Debug, PartialEq)]
+enum Field {
+    Username,
+    Password,
+}
+
+#[derive(Debug)]
+pub struct LoginPrompt {
+    /// The page to load again once credentials are in.
+    pub url: String,
+    pub required: AuthenticationRequired,
+    username: String,
+    password: String,
+    field: Field,
+}
+
+impl LoginPrompt {
+    pub fn new(url: String, required: AuthenticationRequired) -> LoginPrompt {
+        LoginPrompt {
+            url,
+            required,
+            username: String::new(),
+            password: String::new(),
+            field: Field::Username,
+        }
+    }
+
+    /// Enter on the password field submits, Tab and Enter on the username
+    /// field move on to the password.
+    pub fn key(&mut self, key: &Key, text: Option<&str>) -> Option<Credentials> {
+        let field = match self.field {
+            Field::Username => &mut self.username,
+            Field::Password => &mut self.password,
+        };
+
+        match key {
+            Key::Named(NamedKey::Enter) if self.field == Field::Password => {
+                return Some(Credentials {
+                    username: self.username.clone(),
+                    password: self.password.clone(),
+                });
+            }
+            Key::Named(NamedKey::Enter | NamedKey::Tab) => {
+                self.field = match self.field {
+                    Field::Username => Field::Password,
+                    Field::Password => Field::Username,
+                };
+            }
+            Key::Named(NamedKey::Backspace) => {
+                field.pop();
+            }
+            _ => {
+                if let Some(text) = text {
+                    field.extend(text.chars().filter(|c| !c.is_control()));
+                }
+            }
+        }
+
+        None
+    }
+
+    pub fn to_html(&self) -> String {
+        let cursor = |field: Field| if self.field == field { "_" } else { "" };
+
+        let heading = match self.required.rejected {
+            true => "Wrong username or password",
+            false => "Sign in",
+        };
+
+        format!(
+            "<b>{heading}</b> {} asks for a username and password for <i>{}</i> \
+             <b>Username</b> {}{} <b>Password</b> {}{} \
+             <i>Tab to switch fields, Enter to sign in</i>",
+            escape(&self.required.url, false),
+            escape(&self.required.realm, false),
+            escape(&self.username, false),
+            cursor(Field::Username),
+            "*".repeat(self.password.chars().count()),
+            cursor(Field::Password),
+        )
+    }
+}
+
+#[test]
+fn type_username_and_password() {
+    let mut prompt = LoginPrompt::new(
+        "http://intranet/".to_string(),
+        AuthenticationRequired {
+            url: "http://intranet/".to_string(),
+            realm: "tools".to_string(),
+            rejected: false,
+        },
+    );
+
+    let character = |c: &str| Key::Character(c.into());
+    assert_eq!(prompt.key(&character("a"), Some("a")), None);
+    assert_eq!(prompt.key(&character("x"), Some("x")), None);
+    assert_eq!(prompt.key(&Key::Named(NamedKey::Backspace), None), None);
+    assert_eq!(prompt.key(&Key::Named(NamedKey::Enter), None), None);
+    assert_eq!(prompt.key(&character("p"), Some("p")), None);
+    assert!(
+        prompt
+            .to_html()
+            .contains("<b>Username</b> a <b>Password</b> *_")
+    );
+
+    assert_eq!(
+        prompt.key(&Key::Named(NamedKey::Enter), None),
+        Some(Credentials {
+            username: "a".to_string(),
+            password: "p".to_string(),
+        })
+    );
+}
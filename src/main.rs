@@ -1,5 +1,9 @@
-mod http_client;
-mod url;
+mod cli;
+mod gemtext;
+mod gophermap;
+mod input_prompt;
+mod loader;
+mod login_prompt;
 
 #[path = "utils/winit_app.rs"]
 mod winit_app;
@@ -7,40 +11,152 @@ mod winit_app;
 #[path = "utils/fonts.rs"]
 mod fonts;
 
+use browser_engineering::html::{self, Document, Element, NodeData, NodeId};
+use browser_engineering::{http_client, tls, url};
+use cli::Args;
 use fonts::{BrowserFont, FontAndMetadata, FontStyle, FontWeight};
-use http_client::get;
+use http_client::{
+    Client, CredentialStore, HarRecorder, HstsStore, KnownHosts, NetworkTransport,
+    RecordingTransport, ReplayTransport, Resolver,
+};
+use input_prompt::InputPrompt;
+use loader::{BrowserEvent, Loader};
+use login_prompt::LoginPrompt;
 use rusttype::{PositionedGlyph, Scale, point};
 use softbuffer::{Context, Surface};
-use std::{env, num::NonZeroU32};
+use std::{env, num::NonZeroU32, path::PathBuf, process::ExitCode, sync::Arc};
 use winit::{
-    dpi::PhysicalSize,
-    event::{Event, KeyEvent, WindowEvent},
+    dpi::{PhysicalPosition, PhysicalSize},
+    event::{ElementState, Event, KeyEvent, MouseButton, WindowEvent},
     event_loop::{ControlFlow, EventLoop},
     keyboard::{Key, NamedKey},
 };
 
-fn main() {
+fn main() -> ExitCode {
     env_logger::init();
 
     // parse args
 
     let args: Vec<String> = env::args().collect();
-    println!("args: {:?}", args);
-    let url_from_commandline = args.get(1);
+    log::debug!("args: {:?}", args);
+    let args = match Args::parse(&args) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{e}");
+            return ExitCode::from(2);
+        }
+    };
+
+    let Some(url) = args.url else {
+        println!("no url passed to args");
+        return ExitCode::SUCCESS;
+    };
+
+    // fetch page in the background, the window shows a loading state meanwhile
+    let mut network = match NetworkTransport::new(&args.tls) {
+        Ok(network) => network.with_resolver(Resolver::default().with_overrides(args.resolve)),
+        Err(e) => {
+            eprintln!("invalid tls configuration: {e}");
+            return ExitCode::from(2);
+        }
+    };
+    if let Some(path) = &args.known_hosts {
+        match KnownHosts::default().with_file(path) {
+            Ok(known_hosts) => network = network.with_known_hosts(known_hosts),
+            Err(e) => {
+                eprintln!("invalid known hosts file: {e}");
+                return ExitCode::from(2);
+            }
+        }
+    }
+    let mut client = match (args.record, args.replay) {
+        (Some(dir), _) => match RecordingTransport::new(network, dir.clone()) {
+            Ok(recording) => Client::with_transport(recording),
+            Err(e) => {
+                eprintln!("could not create fixture directory {}: {e}", dir.display());
+                return ExitCode::from(2);
+            }
+        },
+        (_, Some(dir)) => Client::with_transport(ReplayTransport::new(dir)),
+        (None, None) => Client::with_transport(network),
+    };
+    if let Some(path) = &args.hsts_preload {
+        match HstsStore::default().with_preload_list(path) {
+            Ok(hsts) => client = client.with_hsts_store(hsts),
+            Err(e) => {
+                eprintln!("invalid hsts preload list: {e}");
+                return ExitCode::from(2);
+            }
+        }
+    }
+    let credentials = Arc::new(CredentialStore::default());
+    client = client.with_credential_store(credentials.clone());
+    let recorder = args
+        .har
+        .map(|path| (path, Arc::new(HarRecorder::default())));
+    if let Some((_, recorder)) = &recorder {
+        client = client.with_har_recorder(recorder.clone());
+    }
+    let downloads = args.downloads.unwrap_or_else(loader::default_downloads_dir);
+
+    // print the parsed page instead of showing it
+    if args.dump_dom {
+        let loaded = loader::download(&client, &url, &downloads, None, |_, _, _, _| {});
+        write_har(recorder.as_ref());
+        return match loaded {
+            Ok((_, document)) => {
+                match args.pretty {
+                    true => print!("{}", document.to_pretty_html()),
+                    false => println!("{}", document.to_html()),
+                }
+                ExitCode::SUCCESS
+            }
+            Err(e) => {
+                eprintln!("could not load {url}: {e}");
+                ExitCode::FAILURE
+            }
+        };
+    }
 
-    if let None = url_from_commandline {
-        println!("no url passed to args: {:?}", args);
+    // check the page's markup instead of showing it
+    if args.parse_errors {
+        let diagnostics = Arc::new(html::Diagnostics::default());
+        let loaded = loader::download(
+            &client,
+            &url,
+            &downloads,
+            Some(diagnostics.clone()),
+            |_, _, _, _| {},
+        );
+        write_har(recorder.as_ref());
+        if let Err(e) = loaded {
+            eprintln!("could not load {url}: {e}");
+            return ExitCode::FAILURE;
+        }
+        let errors = diagnostics.errors();
+        for error in &errors {
+            println!("{url}:{error}");
+        }
+        return match errors.is_empty() {
+            true => ExitCode::SUCCESS,
+            false => ExitCode::FAILURE,
+        };
     }
 
-    // fetch page
-    let response = get(url_from_commandline.unwrap().clone(), None).unwrap();
-    println!("response: {:?}", response);
+    let event_loop = EventLoop::<BrowserEvent>::with_user_event()
+        .build()
+        .unwrap();
+    let loader = Loader::spawn(client, event_loop.create_proxy(), downloads);
+    loader.load(url.clone());
 
-    // render page
-    let tokens = lex(response.body);
-    println!("tokens: {:?}", tokens);
+    let mut document = html::parse(&loader::loading_page(&url, 0, None));
+    let mut login: Option<LoginPrompt> = None;
+    let mut input: Option<InputPrompt> = None;
+    // the page on screen, links on it are relative to this
+    let mut page_url = url.clone();
+    let mut links: Vec<(i32, i32, i32, i32, String)> = Vec::new();
+    let mut mouse = PhysicalPosition::new(0.0, 0.0);
 
-    let event_loop = EventLoop::new().unwrap();
     let softbuffer_context = Context::new(event_loop.owned_display_handle()).unwrap();
 
     let scale = Scale::uniform(50.0);
@@ -63,16 +179,26 @@ fn main() {
                     return;
                 };
                 let size = window.inner_size();
-                let display_list = layout(&tokens, size, &browser_font, scale);
+                let display_list = layout(&document, size, &browser_font, scale);
+                let ascent = browser_font.roman.v_metrics.ascent.ceil() as i32;
+                links = display_list
+                    .iter()
+                    .filter_map(|item| {
+                        let href = item.link.clone()?;
+                        Some((item.x, item.y - ascent, item.width, ascent, href))
+                    })
+                    .collect();
 
                 println!("{}, {}", size.width, size.height);
                 let mut buffer = surface.buffer_mut().unwrap();
+                buffer.fill(0);
 
                 for display_item in display_list {
                     let DisplayItem {
                         x: item_x,
                         y: item_y,
                         glyphs,
+                        ..
                     } = display_item;
 
                     for glyph in glyphs {
@@ -130,139 +256,518 @@ fn main() {
                     },
                 window_id,
             } if window_id == window.id() => {
+                write_har(recorder.as_ref());
                 elwt.exit();
             }
-            _ => {}
-        }
-    });
-
-    winit_app::run_app(event_loop, app);
-}
-
-#[derive(Debug, PartialEq)]
-enum Node {
-    Text(String),
-    Tag(String),
-}
-
-#[test]
-fn test_lex() {
-    assert_eq!(
-        lex(Some("<p>hej</p>".into())),
-        vec![
-            Node::Tag("p".to_string()),
-            Node::Text("hej".to_string()),
-            Node::Tag("/p".to_string())
-        ]
-    )
-}
-fn lex(body: Option<String>) -> Vec<Node> {
-    let mut out: Vec<Node> = Vec::new();
-    if let Some(body) = body {
-        let mut buffer = String::new();
-        let mut in_tag = false;
-
-        for char in body.chars() {
-            match (char, in_tag) {
-                ('<', _) => {
-                    in_tag = true;
-                    if !buffer.is_empty() {
-                        out.push(Node::Text(buffer));
+            Event::WindowEvent {
+                event:
+                    WindowEvent::KeyboardInput {
+                        event:
+                            KeyEvent {
+                                logical_key,
+                                text,
+                                state: ElementState::Pressed,
+                                ..
+                            },
+                        ..
+                    },
+                window_id,
+            } if window_id == window.id() && login.is_some() => {
+                let prompt = login.as_mut().expect("checked in guard");
+                match prompt.key(&logical_key, text.as_deref()) {
+                    Some(typed) => {
+                        match crate::url::URL::parse(&prompt.required.url) {
+                            Ok(protected) => credentials.insert(
+                                &protected.origin(),
+                                &prompt.required.realm,
+                                typed,
+                            ),
+                            Err(e) => log::error!("could not store credentials: {e}"),
+                        }
+                        loader.load(prompt.url.clone());
+                        document = html::parse(&loader::loading_page(&prompt.url, 0, None));
+                        login = None;
                     }
-                    buffer = String::new();
+                    None => document = html::parse(&prompt.to_html()),
                 }
-                ('>', _) => {
-                    in_tag = false;
-                    out.push(Node::Tag(buffer));
-                    buffer = String::new();
+                window.request_redraw();
+            }
+            Event::WindowEvent {
+                event:
+                    WindowEvent::KeyboardInput {
+                        event:
+                            KeyEvent {
+                                logical_key,
+                                text,
+                                state: ElementState::Pressed,
+                                ..
+                            },
+                        ..
+                    },
+                window_id,
+            } if window_id == window.id() && input.is_some() => {
+                let prompt = input.as_mut().expect("checked in guard");
+                match prompt.key(&logical_key, text.as_deref()) {
+                    Some(url) => {
+                        loader.load(url.clone());
+                        document = html::parse(&loader::loading_page(&url, 0, None));
+                        input = None;
+                    }
+                    None => document = html::parse(&prompt.to_html()),
+                }
+                window.request_redraw();
+            }
+            Event::WindowEvent {
+                event:
+                    WindowEvent::KeyboardInput {
+                        event:
+                            KeyEvent {
+                                logical_key: Key::Named(NamedKey::F5),
+                                state: ElementState::Pressed,
+                                ..
+                            },
+                        ..
+                    },
+                window_id,
+            } if window_id == window.id() => {
+                loader.load(page_url.clone());
+            }
+            Event::WindowEvent {
+                event: WindowEvent::CursorMoved { position, .. },
+                window_id,
+            } if window_id == window.id() => mouse = position,
+            Event::WindowEvent {
+                event:
+                    WindowEvent::MouseInput {
+                        state: ElementState::Pressed,
+                        button: MouseButton::Left,
+                        ..
+                    },
+                window_id,
+            } if window_id == window.id() => {
+                let clicked = links.iter().find(|(x, y, width, height, _)| {
+                    (*x as f64..(x + width) as f64).contains(&mouse.x)
+                        && (*y as f64..=(y + height) as f64).contains(&mouse.y)
+                });
+                if let Some((.., href)) = clicked {
+                    match crate::url::URL::parse(&page_url).and_then(|page| page.resolve(href)) {
+                        Ok(target) => {
+                            let target = target.to_string();
+                            loader.load(target.clone());
+                            document = html::parse(&loader::loading_page(&target, 0, None));
+                            window.request_redraw();
+                        }
+                        Err(e) => log::warn!("could not follow link {href}: {e}"),
+                    }
                 }
-                (c, _) => {
-                    buffer.push(c);
+            }
+            Event::UserEvent(BrowserEvent::Progress {
+                url,
+                bytes_read,
+                content_length,
+                document: partial,
+            }) => {
+                match partial {
+                    // show what there is of the page while the rest is downloading
+                    Some(partial) => {
+                        page_url = url;
+                        document = partial;
+                    }
+                    None => {
+                        document =
+                            html::parse(&loader::loading_page(&url, bytes_read, content_length));
+                    }
                 }
+                window.request_redraw();
             }
+            Event::UserEvent(BrowserEvent::AuthRequired { url, required }) => {
+                let prompt = LoginPrompt::new(url, required);
+                document = html::parse(&prompt.to_html());
+                login = Some(prompt);
+                window.request_redraw();
+            }
+            Event::UserEvent(BrowserEvent::InputRequired { required }) => {
+                let prompt = InputPrompt::new(required);
+                document = html::parse(&prompt.to_html());
+                input = Some(prompt);
+                window.request_redraw();
+            }
+            Event::UserEvent(BrowserEvent::Loaded {
+                url,
+                document: loaded,
+            }) => {
+                page_url = url;
+                // render page
+                document = loaded;
+                window.request_redraw();
+            }
+            _ => {}
         }
+    });
 
-        if in_tag == false && !buffer.is_empty() {
-            out.push(Node::Text(buffer));
+    winit_app::run_app(event_loop, app);
+    ExitCode::SUCCESS
+}
+
+/// Saves the requests made so far, if `--har` asked for them.
+fn write_har(recorder: Option<&(PathBuf, Arc<HarRecorder>)>) {
+    if let Some((path, recorder)) = recorder {
+        match recorder.write_to(path) {
+            Ok(()) => log::info!("wrote HAR to {}", path.display()),
+            Err(e) => log::error!("could not write HAR to {}: {e}", path.display()),
         }
     }
-
-    return out;
 }
 
 struct DisplayItem<'a> {
     x: i32,
     y: i32,
     glyphs: Vec<PositionedGlyph<'a>>,
+    width: i32,
+    /// Where clicking the word goes, for words inside `<a href>`.
+    link: Option<String>,
 }
 
 type DisplayList<'a> = Vec<DisplayItem<'a>>;
 
+/// Tab stops in preformatted text are this many spaces apart.
+const TAB_SIZE: i32 = 8;
+
 fn layout<'a>(
-    tokens: &Vec<Node>,
+    document: &Document,
     size: PhysicalSize<u32>,
     browser_font: &'a BrowserFont,
     scale: Scale,
 ) -> DisplayList<'a> {
-    let line_height = 1.5;
-
-    let mut display_list = DisplayList::new();
-
-    let mut cursor_x: i32 = 0;
-    let mut cursor_y: i32 = browser_font.roman.v_metrics.ascent.floor() as i32;
-
-    let mut font_style = FontStyle::Roman;
-    let mut font_weight = FontWeight::Normal;
-
-    for token in tokens {
-        match token {
-            Node::Text(text) => {
-                let FontAndMetadata {
-                    font,
-                    v_metrics,
-                    space_width,
-                } = match (&font_style, &font_weight) {
-                    (FontStyle::Roman, FontWeight::Normal) => &browser_font.roman,
-                    (FontStyle::Roman, FontWeight::Bold) => &browser_font.bold,
-                    (FontStyle::Italic, FontWeight::Normal) => &browser_font.italic,
-                    (FontStyle::Italic, FontWeight::Bold) => &browser_font.bold_italic,
-                };
+    let mut layout = Layout {
+        display_list: DisplayList::new(),
+        size,
+        browser_font,
+        scale,
+        line_height: 1.5,
+        cursor_x: 0,
+        cursor_y: browser_font.roman.v_metrics.ascent.floor() as i32,
+        font_style: FontStyle::Roman,
+        font_weight: FontWeight::Normal,
+        monospace: false,
+        white_space: WhiteSpace::Normal,
+        pending_space: false,
+        link: None,
+    };
+    layout.recurse(document, document.root());
+
+    layout.display_list
+}
 
-                for word in text.split_whitespace() {
-                    let glyphs: Vec<_> = font.layout(word, scale, point(0.0, 0.0)).collect();
+/// How text lays out its whitespace, the CSS `white-space` property.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum WhiteSpace {
+    /// Runs of spaces, tabs and newlines are a single space, lines wrap.
+    Normal,
+    /// Like `Normal`, but lines do not wrap.
+    Nowrap,
+    /// All whitespace is kept and lines only break at newlines.
+    Pre,
+    /// All whitespace is kept and lines wrap as well.
+    PreWrap,
+    /// Newlines are kept, other whitespace collapses, lines wrap.
+    PreLine,
+}
 
-                    let word_width = glyphs
-                        .iter()
-                        .rev()
-                        .map(|g| g.position().x as f32 + g.unpositioned().h_metrics().advance_width)
-                        .next()
-                        .unwrap_or(0.0)
-                        .floor() as i32;
+impl WhiteSpace {
+    /// The value in an inline style like `white-space: pre`, the last one
+    /// if there are several.
+    fn from_style(style: &str) -> Option<WhiteSpace> {
+        style.split(';').rev().find_map(|declaration| {
+            let (property, value) = declaration.split_once(':')?;
+            if !property.trim().eq_ignore_ascii_case("white-space") {
+                return None;
+            }
+            match value.trim().to_ascii_lowercase().as_str() {
+                "normal" => Some(WhiteSpace::Normal),
+                "nowrap" => Some(WhiteSpace::Nowrap),
+                "pre" => Some(WhiteSpace::Pre),
+                "pre-wrap" => Some(WhiteSpace::PreWrap),
+                "pre-line" => Some(WhiteSpace::PreLine),
+                _ => None,
+            }
+        })
+    }
 
-                    if cursor_x + word_width >= (size.width as i32) {
-                        cursor_x = 0;
-                        cursor_y = cursor_y + (v_metrics.ascent.ceil() * line_height) as i32;
-                    }
+    fn collapses(self) -> bool {
+        matches!(
+            self,
+            WhiteSpace::Normal | WhiteSpace::Nowrap | WhiteSpace::PreLine
+        )
+    }
 
-                    display_list.push(DisplayItem {
-                        x: cursor_x,
-                        y: cursor_y,
-                        glyphs,
-                    });
+    fn wraps(self) -> bool {
+        !matches!(self, WhiteSpace::Nowrap | WhiteSpace::Pre)
+    }
+}
+
+/// Where the next word goes and how it looks, while walking the document.
+struct Layout<'a> {
+    display_list: DisplayList<'a>,
+    size: PhysicalSize<u32>,
+    browser_font: &'a BrowserFont<'a>,
+    scale: Scale,
+    line_height: f32,
+    cursor_x: i32,
+    cursor_y: i32,
+    font_style: FontStyle,
+    font_weight: FontWeight,
+    /// Inside `<pre>`, `<code>` and the like.
+    monospace: bool,
+    white_space: WhiteSpace,
+    /// Collapsed whitespace was seen since the last word, the next word on
+    /// the same line gets a space before it.
+    pending_space: bool,
+    link: Option<String>,
+}
 
-                    cursor_x = cursor_x + word_width + space_width;
+impl<'a> Layout<'a> {
+    fn recurse(&mut self, document: &Document, id: NodeId) {
+        match document.data(id) {
+            NodeData::Text(text) => self.text(text),
+            NodeData::Element(element) => {
+                // nothing in these is shown
+                if matches!(element.name.as_str(), "head" | "script" | "style") {
+                    return;
+                }
+                // these only last until the end of the element
+                let (monospace, white_space) = (self.monospace, self.white_space);
+                self.open_tag(element);
+                for child in document.children(id) {
+                    self.recurse(document, *child);
                 }
+                self.close_tag(&element.name);
+                (self.monospace, self.white_space) = (monospace, white_space);
             }
-            Node::Tag(val) if val == "i" => font_style = FontStyle::Italic,
+            NodeData::Document => {
+                for child in document.children(id) {
+                    self.recurse(document, *child);
+                }
+            }
+            NodeData::Comment(_) | NodeData::Doctype { .. } => {}
+        }
+    }
 
-            Node::Tag(val) if val == "/i" => font_style = FontStyle::Roman,
+    fn open_tag(&mut self, element: &Element) {
+        match element.name.as_str() {
+            "i" => self.font_style = FontStyle::Italic,
+            "b" => self.font_weight = FontWeight::Bold,
+            "br" => self.new_line(),
+            "a" => self.link = element.attribute("href").map(String::from),
+            "pre" | "listing" | "xmp" | "plaintext" => {
+                self.end_line();
+                self.monospace = true;
+                self.white_space = WhiteSpace::Pre;
+            }
+            "code" | "kbd" | "samp" | "tt" => self.monospace = true,
+            "html" | "body" => {}
+            name => println!("unknown tag {name}"),
+        }
 
-            Node::Tag(val) if val == "b" => font_weight = FontWeight::Bold,
-            Node::Tag(val) if val == "/b" => font_weight = FontWeight::Normal,
+        if let Some(white_space) = element.attribute("style").and_then(WhiteSpace::from_style) {
+            self.white_space = white_space;
+        }
+    }
 
-            Node::Tag(_) => println!("unknown tag"),
+    fn close_tag(&mut self, name: &str) {
+        match name {
+            "i" => self.font_style = FontStyle::Roman,
+            "b" => self.font_weight = FontWeight::Normal,
+            "a" => self.link = None,
+            "pre" | "listing" | "xmp" | "plaintext" => self.end_line(),
+            _ => {}
         }
     }
 
-    display_list
+    fn new_line(&mut self) {
+        self.cursor_x = 0;
+        self.cursor_y +=
+            (self.browser_font.roman.v_metrics.ascent.ceil() * self.line_height) as i32;
+        self.pending_space = false;
+    }
+
+    /// Blocks like `<pre>` start and end on a line of their own.
+    fn end_line(&mut self) {
+        if self.cursor_x > 0 {
+            self.new_line();
+        }
+    }
+
+    fn font(&self) -> &'a FontAndMetadata<'a> {
+        let browser_font = self.browser_font;
+        if self.monospace {
+            return &browser_font.monospace;
+        }
+        match (&self.font_style, &self.font_weight) {
+            (FontStyle::Roman, FontWeight::Normal) => &browser_font.roman,
+            (FontStyle::Roman, FontWeight::Bold) => &browser_font.bold,
+            (FontStyle::Italic, FontWeight::Normal) => &browser_font.italic,
+            (FontStyle::Italic, FontWeight::Bold) => &browser_font.bold_italic,
+        }
+    }
+
+    fn text(&mut self, text: &str) {
+        match self.white_space.collapses() {
+            true => self.collapsed_text(text),
+            false => self.preserved_text(text),
+        }
+    }
+
+    /// Whitespace between words is a single space, also across elements,
+    /// and none at the start or end of a line.
+    fn collapsed_text(&mut self, text: &str) {
+        let mut rest = text;
+        while !rest.is_empty() {
+            let word_end = rest
+                .find(|c: char| c.is_ascii_whitespace())
+                .unwrap_or(rest.len());
+            if word_end > 0 {
+                self.word(&rest[..word_end]);
+            }
+            rest = &rest[word_end..];
+
+            let space_end = rest
+                .find(|c: char| !c.is_ascii_whitespace())
+                .unwrap_or(rest.len());
+            let space = &rest[..space_end];
+            if self.white_space == WhiteSpace::PreLine && space.contains('\n') {
+                for _ in space.matches('\n') {
+                    self.new_line();
+                }
+            } else if !space.is_empty() {
+                self.pending_space = true;
+            }
+            rest = &rest[space_end..];
+        }
+    }
+
+    /// Every space moves the cursor, tabs to the next tab stop, and newlines
+    /// start a new line.
+    fn preserved_text(&mut self, text: &str) {
+        let space_width = self.font().space_width;
+        for (i, line) in text.split('\n').enumerate() {
+            if i > 0 {
+                self.new_line();
+            }
+            let mut rest = line;
+            while let Some(c) = rest.chars().next() {
+                match c {
+                    ' ' => {
+                        self.cursor_x += space_width;
+                        rest = &rest[1..];
+                    }
+                    '\t' => {
+                        let tab = TAB_SIZE * space_width;
+                        self.cursor_x = (self.cursor_x / tab + 1) * tab;
+                        rest = &rest[1..];
+                    }
+                    _ => {
+                        let end = rest.find([' ', '\t']).unwrap_or(rest.len());
+                        self.word(&rest[..end]);
+                        rest = &rest[end..];
+                    }
+                }
+            }
+        }
+    }
+
+    fn word(&mut self, word: &str) {
+        let font = self.font();
+        let glyphs: Vec<_> = font
+            .font
+            .layout(word, self.scale, point(0.0, 0.0))
+            .collect();
+
+        let word_width = glyphs
+            .iter()
+            .rev()
+            .map(|g| g.position().x + g.unpositioned().h_metrics().advance_width)
+            .next()
+            .unwrap_or(0.0)
+            .floor() as i32;
+
+        let space = match self.pending_space && self.cursor_x > 0 {
+            true => font.space_width,
+            false => 0,
+        };
+        if self.white_space.wraps()
+            && self.cursor_x > 0
+            && self.cursor_x + space + word_width >= (self.size.width as i32)
+        {
+            self.new_line();
+        } else {
+            self.cursor_x += space;
+        }
+
+        self.display_list.push(DisplayItem {
+            x: self.cursor_x,
+            y: self.cursor_y,
+            glyphs,
+            width: word_width,
+            link: self.link.clone(),
+        });
+
+        self.cursor_x += word_width;
+        self.pending_space = false;
+    }
+}
+
+#[test]
+fn whitespace_processing() {
+    let scale = Scale::uniform(20.0);
+    let font = BrowserFont::load(scale).unwrap();
+    let positions = |html: &str| -> Vec<(i32, i32)> {
+        layout(
+            &html::parse(html),
+            PhysicalSize::new(10_000, 1000),
+            &font,
+            scale,
+        )
+        .iter()
+        .map(|item| (item.x, item.y))
+        .collect()
+    };
+    let advance = |font: &FontAndMetadata, c| {
+        font.font.glyph(c).scaled(scale).h_metrics().advance_width as i32
+    };
+    let top = font.roman.v_metrics.ascent.floor() as i32;
+    let line = (font.roman.v_metrics.ascent.ceil() * 1.5) as i32;
+    let space = font.roman.space_width;
+    let mono = font.monospace.space_width;
+
+    // runs collapse to one space, also across elements, none at the start
+    let a_b = vec![(0, top), (advance(&font.roman, 'a') + space, top)];
+    assert_eq!(positions(" a  \n\t b"), a_b);
+    assert_eq!(positions("a <i> </i> b"), a_b);
+    assert_eq!(
+        positions("<b>a</b>b"),
+        [(0, top), (advance(&font.bold, 'a'), top)]
+    );
+    assert_eq!(positions("a \n<br> \nb")[1], (0, top + line));
+
+    // preformatted text keeps spaces, tabs and newlines
+    assert_eq!(
+        positions("x <pre>\n  a\n\tb c\n</pre>y"),
+        [
+            (0, top),
+            (2 * mono, top + line),
+            (TAB_SIZE * mono, top + 2 * line),
+            ((TAB_SIZE + 2) * mono, top + 2 * line),
+            (0, top + 3 * line),
+        ]
+    );
+
+    assert_eq!(
+        positions("<span style=\"color: red; white-space: pre-line\">a  b\nc</span>"),
+        [
+            (0, top),
+            (advance(&font.roman, 'a') + space, top),
+            (0, top + line)
+        ]
+    );
 }
@@ -0,0 +1,200 @@
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::{CryptoProvider, verify_tls12_signature, verify_tls13_signature};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use rustls::{ClientConfig, DigitallySignedStruct, RootCertStore, SignatureScheme};
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::io;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+#[derive(Debug, Clone, Default)]
+pub struct TlsConfig {
+    /// PEM files with root certificates to trust on top of the bundled webpki roots.
+    pub extra_root_certs: Vec<PathBuf>,
+    /// Also trust the roots in the operating system's trust store.
+    pub use_system_roots: bool,
+    /// Presented to servers that ask for a client certificate (mutual TLS).
+    pub client_cert: Option<ClientCert>,
+    /// Skip certificate validation entirely. Only meant for local testing.
+    pub accept_invalid_certs: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct ClientCert {
+    pub cert_chain: PathBuf,
+    pub private_key: PathBuf,
+}
+
+impl TlsConfig {
+    pub fn client_config(&self) -> Result<ClientConfig, Box<dyn Error>> {
+        let builder = ClientConfig::builder();
+
+        let builder = if self.accept_invalid_certs {
+            log::warn!("certificate validation is disabled");
+            builder
+                .dangerous()
+                .with_custom_certificate_verifier(Arc::new(AcceptAnyCertificate::new()))
+        } else {
+            builder.with_root_certificates(self.root_store()?)
+        };
+
+        let config = match &self.client_cert {
+            Some(ClientCert {
+                cert_chain,
+                private_key,
+            }) => {
+                let certs = CertificateDer::pem_file_iter(cert_chain)
+                    .map_err(|e| format!("could not read {}: {e}", cert_chain.display()))?
+                    .collect::<Result<Vec<_>, _>>()?;
+                let key = PrivateKeyDer::from_pem_file(private_key)
+                    .map_err(|e| format!("could not read {}: {e}", private_key.display()))?;
+
+                builder.with_client_auth_cert(certs, key)?
+            }
+            None => builder.with_no_client_auth(),
+        };
+
+        Ok(config)
+    }
+
+    fn root_store(&self) -> Result<RootCertStore, Box<dyn Error>> {
+        let mut root_store =
+            RootCertStore::from_iter(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+
+        if self.use_system_roots {
+            let native = rustls_native_certs::load_native_certs();
+            for e in native.errors {
+                log::warn!("could not load system certificate: {e}");
+            }
+            let (added, ignored) = root_store.add_parsable_certificates(native.certs);
+            log::debug!("loaded {added} system certificates, ignored {ignored}");
+        }
+
+        for path in &self.extra_root_certs {
+            let certs = CertificateDer::pem_file_iter(path)
+                .map_err(|e| format!("could not read {}: {e}", path.display()))?;
+            for cert in certs {
+                root_store.add(cert?)?;
+            }
+        }
+
+        Ok(root_store)
+    }
+}
+
+/// The server presented a certificate we don't trust.
+#[derive(Debug)]
+pub struct CertificateError {
+    pub host: String,
+    pub reason: rustls::CertificateError,
+}
+
+impl Display for CertificateError {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "the certificate for {} is not trusted: {}",
+            self.host,
+            rustls::Error::InvalidCertificate(self.reason.clone())
+        )
+    }
+}
+
+impl Error for CertificateError {}
+
+/// rustls reports handshake failures as io errors wrapping a `rustls::Error`,
+/// pull out the certificate ones so they can be shown to the user.
+pub fn map_io_error(host: &str, e: io::Error) -> Box<dyn Error> {
+    let reason = e
+        .get_ref()
+        .and_then(|inner| inner.downcast_ref::<rustls::Error>())
+        .and_then(|tls_error| match tls_error {
+            rustls::Error::InvalidCertificate(reason) => Some(reason.clone()),
+            _ => None,
+        });
+
+    match reason {
+        Some(reason) => Box::new(CertificateError {
+            host: host.to_string(),
+            reason,
+        }),
+        None => e.into(),
+    }
+}
+
+/// Verifier used for `accept_invalid_certs`. Signatures are still checked so the
+/// handshake is sound, but the chain and host name are not.
+#[derive(Debug)]
+struct AcceptAnyCertificate(CryptoProvider);
+
+impl AcceptAnyCertificate {
+    fn new() -> Self {
+        AcceptAnyCertificate(rustls::crypto::aws_lc_rs::default_provider())
+    }
+}
+
+impl ServerCertVerifier for AcceptAnyCertificate {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+#[test]
+fn certificate_errors_are_recognized() {
+    let io_error = io::Error::new(
+        io::ErrorKind::InvalidData,
+        rustls::Error::InvalidCertificate(rustls::CertificateError::UnknownIssuer),
+    );
+
+    let mapped = map_io_error("internal.example", io_error);
+    let certificate_error = mapped
+        .downcast_ref::<CertificateError>()
+        .expect("should be a certificate error");
+
+    assert_eq!(certificate_error.host, "internal.example");
+    assert_eq!(
+        certificate_error.reason,
+        rustls::CertificateError::UnknownIssuer
+    );
+}
@@ -25,6 +25,8 @@ pub struct BrowserFont<'a> {
     pub italic: FontAndMetadata<'a>,
     pub bold: FontAndMetadata<'a>,
     pub bold_italic: FontAndMetadata<'a>,
+    /// For `<pre>` and `<code>`, in one weight and style.
+    pub monospace: FontAndMetadata<'a>,
     pub font_weight: FontWeight,
     pub font_style: FontStyle,
 }
@@ -66,6 +68,9 @@ impl BrowserFont<'static> {
         let bold_italic =
             Font::try_from_bytes(bold_italic_data).ok_or("Error loading bold italic")?;
 
+        let monospace_data = include_bytes!("../../assets/dejavu/DejaVuSansMono.ttf");
+        let monospace = Font::try_from_bytes(monospace_data).ok_or("Error loading monospace")?;
+
         return Ok(BrowserFont {
             font_weight: FontWeight::Normal,
             font_style: FontStyle::Roman,
@@ -109,6 +114,16 @@ impl BrowserFont<'static> {
                     .floor() as i32,
                 font: bold_italic,
             },
+            monospace: FontAndMetadata {
+                v_metrics: monospace.v_metrics(scale),
+                space_width: monospace
+                    .glyph(' ')
+                    .scaled(scale)
+                    .h_metrics()
+                    .advance_width
+                    .floor() as i32,
+                font: monospace,
+            },
         });
     }
 }
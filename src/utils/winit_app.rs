@@ -10,7 +10,10 @@ use winit::window::{Window, WindowAttributes, WindowId};
 
 /// Run a Winit application.
 #[allow(unused_mut)]
-pub(crate) fn run_app(event_loop: EventLoop<()>, mut app: impl ApplicationHandler<()> + 'static) {
+pub(crate) fn run_app<E: 'static>(
+    event_loop: EventLoop<E>,
+    mut app: impl ApplicationHandler<E> + 'static,
+) {
     #[cfg(not(any(target_arch = "wasm32", target_arch = "wasm64")))]
     event_loop.run_app(&mut app).unwrap();
 
@@ -32,7 +35,7 @@ pub(crate) fn make_window(
 }
 
 /// Easily constructable winit application.
-pub(crate) struct WinitApp<T, S, Init, InitSurface, Handler> {
+pub(crate) struct WinitApp<T, S, E, Init, InitSurface, Handler> {
     /// Closure to initialize `state`.
     init: Init,
 
@@ -47,6 +50,9 @@ pub(crate) struct WinitApp<T, S, Init, InitSurface, Handler> {
 
     /// Contained surface state.
     surface_state: Option<S>,
+
+    /// User events that arrived before the window was created.
+    pending_events: Vec<E>,
 }
 
 /// Builder that makes it so we don't have to name `T`.
@@ -76,19 +82,22 @@ where
     }
 
     /// Build a new application.
-    pub(crate) fn with_event_handler<F>(self, handler: F) -> WinitApp<T, S, Init, InitSurface, F>
+    pub(crate) fn with_event_handler<E, F>(
+        self,
+        handler: F,
+    ) -> WinitApp<T, S, E, Init, InitSurface, F>
     where
-        F: FnMut(&mut T, Option<&mut S>, Event<()>, &ActiveEventLoop),
+        F: FnMut(&mut T, Option<&mut S>, Event<E>, &ActiveEventLoop),
     {
         WinitApp::new(self.init, self.init_surface, handler)
     }
 }
 
-impl<T, S, Init, InitSurface, Handler> WinitApp<T, S, Init, InitSurface, Handler>
+impl<T, S, E, Init, InitSurface, Handler> WinitApp<T, S, E, Init, InitSurface, Handler>
 where
     Init: FnMut(&ActiveEventLoop) -> T,
     InitSurface: FnMut(&ActiveEventLoop, &mut T) -> S,
-    Handler: FnMut(&mut T, Option<&mut S>, Event<()>, &ActiveEventLoop),
+    Handler: FnMut(&mut T, Option<&mut S>, Event<E>, &ActiveEventLoop),
 {
     /// Create a new application.
     pub(crate) fn new(init: Init, init_surface: InitSurface, event: Handler) -> Self {
@@ -98,22 +107,27 @@ where
             event,
             state: None,
             surface_state: None,
+            pending_events: Vec::new(),
         }
     }
 }
 
-impl<T, S, Init, InitSurface, Handler> ApplicationHandler
-    for WinitApp<T, S, Init, InitSurface, Handler>
+impl<T, S, E: 'static, Init, InitSurface, Handler> ApplicationHandler<E>
+    for WinitApp<T, S, E, Init, InitSurface, Handler>
 where
     Init: FnMut(&ActiveEventLoop) -> T,
     InitSurface: FnMut(&ActiveEventLoop, &mut T) -> S,
-    Handler: FnMut(&mut T, Option<&mut S>, Event<()>, &ActiveEventLoop),
+    Handler: FnMut(&mut T, Option<&mut S>, Event<E>, &ActiveEventLoop),
 {
     fn resumed(&mut self, el: &ActiveEventLoop) {
         debug_assert!(self.state.is_none());
         let mut state = (self.init)(el);
         self.surface_state = Some((self.init_surface)(el, &mut state));
         self.state = Some(state);
+
+        for event in std::mem::take(&mut self.pending_events) {
+            self.user_event(el, event);
+        }
     }
 
     fn suspended(&mut self, _event_loop: &ActiveEventLoop) {
@@ -138,6 +152,21 @@ where
         );
     }
 
+    fn user_event(&mut self, event_loop: &ActiveEventLoop, event: E) {
+        let Some(state) = self.state.as_mut() else {
+            // hold on to it until there is a window to show it in
+            self.pending_events.push(event);
+            return;
+        };
+
+        (self.event)(
+            state,
+            self.surface_state.as_mut(),
+            Event::UserEvent(event),
+            event_loop,
+        );
+    }
+
     fn about_to_wait(&mut self, event_loop: &ActiveEventLoop) {
         if let Some(state) = self.state.as_mut() {
             (self.event)(
@@ -0,0 +1,555 @@
+//! Runs the html5lib-tests snapshot in `tests/html5lib`, see the README there:
+//! the tree-construction `.dat` files through the whole parser and the
+//! tokenizer `.test` files through the tokenizer alone. Cases that fail go on
+//! the list in `tests/html5lib/expected-failures.txt`.
+use std::{collections::BTreeSet, fs, iter::Peekable, path::PathBuf, str::Chars, sync::Arc};
+
+use browser_engineering::html::{
+    self,
+    tokenizer::{State, Token, Tokenizer},
+};
+
+fn html5lib_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/html5lib")
+}
+
+/// The files in `directory` with their names relative to `tests/html5lib`,
+/// which is how the expected failures refer to them.
+fn data_files(directory: &str, extension: &str) -> Vec<(String, PathBuf)> {
+    let mut files: Vec<_> = fs::read_dir(html5lib_dir().join(directory))
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|e| e == extension))
+        .map(|path| {
+            let name = path.file_name().unwrap().to_string_lossy();
+            (format!("{directory}/{name}"), path)
+        })
+        .collect();
+    files.sort();
+    files
+}
+
+/// The cases of one kind that are known to fail: `tree` and `errors` for tree
+/// construction, `tokens` and `token-errors` for the tokenizer.
+/// Each line of the list is the file, the number of the test in it counted
+/// from 1 and the kind, like `tree-construction/tests1.dat:12 errors`.
+fn expected_failures(kind: &str) -> BTreeSet<String> {
+    let list = fs::read_to_string(html5lib_dir().join("expected-failures.txt")).unwrap();
+    list.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| {
+            let (case, listed_kind) = line.split_once(' ').expect("case and kind");
+            (listed_kind.trim() == kind).then(|| case.to_string())
+        })
+        .collect()
+}
+
+/// Every case has to pass unless it is on the list of expected failures, and
+/// every case on the list has to still fail so the list stays accurate.
+/// `results` has the failure of each case that failed, by case.
+fn assert_expected(kind: &str, results: Vec<(String, Option<String>)>) {
+    let expected = expected_failures(kind);
+    let mut problems = Vec::new();
+    for (case, failure) in &results {
+        match (failure, expected.contains(case)) {
+            (Some(failure), false) => problems.push(format!("{case} failed\n{failure}")),
+            (None, true) => problems.push(format!(
+                "{case} passes now, take it off the expected failures"
+            )),
+            _ => {}
+        }
+    }
+    for case in &expected {
+        if !results.iter().any(|(run, _)| run == case) {
+            problems.push(format!(
+                "{case} is an expected failure, but there is no such test"
+            ));
+        }
+    }
+
+    assert!(
+        problems.is_empty(),
+        "{} problems in {} {kind} cases\n\n{}",
+        problems.len(),
+        results.len(),
+        problems.join("\n\n")
+    );
+}
+
+#[derive(Default)]
+struct TreeTest {
+    data: String,
+    /// How many parse errors the data has, `#errors` and `#new-errors` together.
+    errors: usize,
+    document: String,
+    /// Fragment parsing and scripting are not supported, those are skipped.
+    skip: bool,
+}
+
+/// Sections start with a `#name` line, a test with `#data`.
+fn tree_tests(source: &str) -> Vec<TreeTest> {
+    let mut tests: Vec<TreeTest> = Vec::new();
+    let mut section = "";
+    for line in source.split('\n') {
+        if let Some(name) = line.strip_prefix('#')
+            && [
+                "data",
+                "errors",
+                "new-errors",
+                "document-fragment",
+                "script-off",
+                "script-on",
+                "document",
+            ]
+            .contains(&name)
+        {
+            match name {
+                "data" => tests.push(TreeTest::default()),
+                "document-fragment" | "script-on" => tests.last_mut().unwrap().skip = true,
+                _ => {}
+            }
+            section = name;
+            continue;
+        }
+
+        let Some(test) = tests.last_mut() else {
+            continue;
+        };
+        if (section == "errors" || section == "new-errors") && !line.is_empty() {
+            test.errors += 1;
+        }
+        let text = match section {
+            "data" => &mut test.data,
+            "document" => &mut test.document,
+            _ => continue,
+        };
+        text.push_str(line);
+        text.push('\n');
+    }
+
+    // the data has no newline of its own, the document is followed by a blank line
+    for test in &mut tests {
+        test.data.pop();
+        while test.document.ends_with("\n\n") {
+            test.document.pop();
+        }
+    }
+    tests
+}
+
+/// The tests of every tree construction file that are not skipped, with the
+/// case they are listed as in the expected failures.
+fn tree_cases() -> Vec<(String, TreeTest)> {
+    let mut cases = Vec::new();
+    let files = [
+        data_files("tree-construction", "dat"),
+        data_files("tree-construction/scripted", "dat"),
+    ];
+    for (name, path) in files.into_iter().flatten() {
+        let source = fs::read_to_string(&path).unwrap();
+        for (number, test) in tree_tests(&source).into_iter().enumerate() {
+            if !test.skip {
+                cases.push((format!("{name}:{}", number + 1), test));
+            }
+        }
+    }
+    assert!(!cases.is_empty());
+    cases
+}
+
+/// Checks `parse` turns the data of every test into its document.
+fn check_trees(parse: impl Fn(&str) -> String) {
+    let results = tree_cases()
+        .into_iter()
+        .map(|(case, test)| {
+            let document = parse(&test.data);
+            let failure = (document != test.document).then(|| {
+                format!(
+                    "#data\n{}\n#expected\n{}#got\n{}",
+                    test.data, test.document, document
+                )
+            });
+            (case, failure)
+        })
+        .collect();
+    assert_expected("tree", results);
+}
+
+#[test]
+fn tree_construction() {
+    check_trees(|data| html::parse(data).dump());
+}
+
+/// Every state the tokenizer and tree builder can be in has to pick up where
+/// it was when more input arrives.
+#[test]
+fn tree_construction_byte_by_byte() {
+    check_trees(|data| {
+        let mut parser = html::Parser::default();
+        for byte in data.as_bytes() {
+            parser.feed_bytes(&[*byte]);
+        }
+        parser.finish().dump()
+    });
+}
+
+/// The parser has to report as many errors as the data lists. Only the
+/// number is compared, the data has the messages of html5lib's Python parser
+/// while the parser reports the spec's codes.
+#[test]
+fn parse_errors() {
+    let results = tree_cases()
+        .into_iter()
+        .map(|(case, test)| {
+            let diagnostics = Arc::new(html::Diagnostics::default());
+            let mut parser = html::Parser::default().with_diagnostics(diagnostics.clone());
+            parser.feed(&test.data);
+            parser.finish();
+            let errors = diagnostics.errors();
+            let failure = (errors.len() != test.errors).then(|| {
+                format!(
+                    "#data\n{}\n#expected {} errors, got {}: {errors:?}",
+                    test.data,
+                    test.errors,
+                    errors.len()
+                )
+            });
+            (case, failure)
+        })
+        .collect();
+    assert_expected("errors", results);
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(fields) => fields
+                .iter()
+                .find(|(name, _)| name == key)
+                .map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::String(string) => Some(string),
+            _ => None,
+        }
+    }
+
+    fn as_array(&self) -> &[Json] {
+        match self {
+            Json::Array(items) => items,
+            _ => &[],
+        }
+    }
+}
+
+/// Just enough JSON for the test files, which are known to be valid.
+struct JsonParser<'a> {
+    chars: Peekable<Chars<'a>>,
+}
+
+impl JsonParser<'_> {
+    fn parse(source: &str) -> Json {
+        JsonParser {
+            chars: source.chars().peekable(),
+        }
+        .value()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.chars.next_if(|c| c.is_whitespace()).is_some() {}
+    }
+
+    fn value(&mut self) -> Json {
+        self.skip_whitespace();
+        match self.chars.next().unwrap() {
+            '{' => {
+                let mut fields = Vec::new();
+                loop {
+                    self.skip_whitespace();
+                    match self.chars.next().unwrap() {
+                        '}' => break,
+                        ',' => continue,
+                        '"' => {
+                            let name = self.string();
+                            self.skip_whitespace();
+                            assert_eq!(self.chars.next(), Some(':'));
+                            fields.push((name, self.value()));
+                        }
+                        c => panic!("unexpected {c:?} in object"),
+                    }
+                }
+                Json::Object(fields)
+            }
+            '[' => {
+                let mut items = Vec::new();
+                loop {
+                    self.skip_whitespace();
+                    match self.chars.peek().unwrap() {
+                        ']' => {
+                            self.chars.next();
+                            break;
+                        }
+                        ',' => {
+                            self.chars.next();
+                        }
+                        _ => items.push(self.value()),
+                    }
+                }
+                Json::Array(items)
+            }
+            '"' => Json::String(self.string()),
+            't' => self.keyword("rue", Json::Bool(true)),
+            'f' => self.keyword("alse", Json::Bool(false)),
+            'n' => self.keyword("ull", Json::Null),
+            c => {
+                let mut number = c.to_string();
+                while let Some(c) = self
+                    .chars
+                    .next_if(|c| c.is_ascii_digit() || matches!(c, '.' | 'e' | 'E' | '+' | '-'))
+                {
+                    number.push(c);
+                }
+                Json::Number(number.parse().unwrap())
+            }
+        }
+    }
+
+    fn keyword(&mut self, rest: &str, value: Json) -> Json {
+        for expected in rest.chars() {
+            assert_eq!(self.chars.next(), Some(expected));
+        }
+        value
+    }
+
+    fn string(&mut self) -> String {
+        let mut string = String::new();
+        let mut units = Vec::new();
+        loop {
+            let c = self.chars.next().unwrap();
+            if c == '\\' && self.chars.next_if_eq(&'u').is_some() {
+                let hex: String = (0..4).map(|_| self.chars.next().unwrap()).collect();
+                units.push(u16::from_str_radix(&hex, 16).unwrap());
+                continue;
+            }
+            // `😀` is one character, lone surrogates become U+FFFD
+            string.extend(char::decode_utf16(units.drain(..)).map(|c| c.unwrap_or('\u{FFFD}')));
+            match c {
+                '"' => return string,
+                '\\' => string.push(match self.chars.next().unwrap() {
+                    'n' => '\n',
+                    't' => '\t',
+                    'r' => '\r',
+                    'b' => '\u{8}',
+                    'f' => '\u{C}',
+                    c => c,
+                }),
+                c => string.push(c),
+            }
+        }
+    }
+}
+
+/// `doubleEscaped` tests have their `\uXXXX` escaped once more, for lone
+/// surrogates a Rust string cannot hold. Those tests are skipped.
+fn unescape(text: &str) -> Option<String> {
+    let mut units = Vec::new();
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        if let Some(hex) = rest.strip_prefix("\\u") {
+            units.push(u16::from_str_radix(&hex[..4], 16).ok()?);
+            rest = &hex[4..];
+        } else {
+            units.extend(c.encode_utf16(&mut [0; 2]).iter());
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+    String::from_utf16(&units).ok()
+}
+
+fn unescape_json(value: &Json) -> Option<Json> {
+    Some(match value {
+        Json::String(string) => Json::String(unescape(string)?),
+        Json::Array(items) => Json::Array(items.iter().map(unescape_json).collect::<Option<_>>()?),
+        Json::Object(fields) => Json::Object(
+            fields
+                .iter()
+                .map(|(name, value)| Some((unescape(name)?, unescape_json(value)?)))
+                .collect::<Option<_>>()?,
+        ),
+        value => value.clone(),
+    })
+}
+
+/// The tokens of a test's output without its `"ParseError"` entries, with the
+/// characters those were between merged, and how many of them there were.
+fn expected_tokens(output: &Json) -> (Json, usize) {
+    let mut tokens: Vec<Json> = Vec::new();
+    let mut errors = 0;
+    for token in output.as_array() {
+        if token.as_str() == Some("ParseError") {
+            errors += 1;
+            continue;
+        }
+        if let [Json::String(kind), Json::String(text)] = token.as_array()
+            && kind == "Character"
+            && let Some(Json::Array(last)) = tokens.last_mut()
+            && last.first().and_then(Json::as_str) == Some("Character")
+            && let Some(Json::String(before)) = last.get_mut(1)
+        {
+            before.push_str(text);
+            continue;
+        }
+        tokens.push(token.clone());
+    }
+    (Json::Array(tokens), errors)
+}
+
+/// The tokens in the form of the test files, with adjacent characters merged,
+/// and the codes of the errors found on the way.
+fn tokens(input: &str, state: State, last_start_tag: Option<&str>) -> (Vec<Json>, Vec<String>) {
+    let string = |s: &str| Json::String(s.to_string());
+    let optional = |s: &Option<String>| s.as_deref().map_or(Json::Null, string);
+
+    let mut tokenizer = Tokenizer::default();
+    tokenizer.state = state;
+    tokenizer.last_start_tag = last_start_tag.unwrap_or_default().to_string();
+    tokenizer.feed(input);
+    tokenizer.end();
+
+    let mut out = Vec::new();
+    let mut text = String::new();
+    while let Some(token) = tokenizer.next_token() {
+        if let Token::Character(c) = token {
+            text.push(c);
+            continue;
+        }
+        if !text.is_empty() {
+            out.push(Json::Array(vec![string("Character"), string(&text)]));
+            text.clear();
+        }
+        out.push(Json::Array(match token {
+            Token::Doctype(doctype) => vec![
+                string("DOCTYPE"),
+                optional(&doctype.name),
+                optional(&doctype.public_id),
+                optional(&doctype.system_id),
+                Json::Bool(!doctype.force_quirks),
+            ],
+            Token::StartTag(tag) => {
+                let attributes = tag
+                    .attributes
+                    .iter()
+                    .map(|(name, value)| (name.clone(), string(value)))
+                    .collect();
+                let mut start_tag = vec![
+                    string("StartTag"),
+                    string(&tag.name),
+                    Json::Object(attributes),
+                ];
+                if tag.self_closing {
+                    start_tag.push(Json::Bool(true));
+                }
+                start_tag
+            }
+            Token::EndTag(tag) => vec![string("EndTag"), string(&tag.name)],
+            Token::Comment(comment) => vec![string("Comment"), string(&comment)],
+            Token::Character(_) | Token::Eof => continue,
+        }));
+    }
+    let errors = tokenizer
+        .take_errors()
+        .into_iter()
+        .map(|error| error.code.to_string())
+        .collect();
+    (out, errors)
+}
+
+/// The tokens have to be the ones the data lists, and the tokenizer has to
+/// report as many errors as there are `"ParseError"` entries among them.
+#[test]
+fn tokenizer() {
+    let mut token_results = Vec::new();
+    let mut error_results = Vec::new();
+    for (name, path) in data_files("tokenizer", "test") {
+        let json = JsonParser::parse(&fs::read_to_string(&path).unwrap());
+        // `xmlViolation.test` only has `xmlViolationTests`, for tokenizers
+        // that coerce their output to XML, which this one doesn't
+        let Some(tests) = json.get("tests") else {
+            continue;
+        };
+        for (number, test) in tests.as_array().iter().enumerate() {
+            let case = format!("{name}:{}", number + 1);
+            let description = test.get("description").and_then(Json::as_str).unwrap();
+            let mut input = test.get("input").unwrap().clone();
+            let mut output = test.get("output").unwrap().clone();
+            if test.get("doubleEscaped") == Some(&Json::Bool(true)) {
+                let (Some(i), Some(o)) = (unescape_json(&input), unescape_json(&output)) else {
+                    continue;
+                };
+                (input, output) = (i, o);
+            }
+            let input = input.as_str().unwrap();
+            let last_start_tag = test.get("lastStartTag").and_then(Json::as_str);
+            let (output, expected_errors) = expected_tokens(&output);
+
+            let states = match test.get("initialStates") {
+                Some(states) => states
+                    .as_array()
+                    .iter()
+                    .map(|state| state.as_str().unwrap())
+                    .collect(),
+                None => vec!["Data state"],
+            };
+            let mut token_failures = Vec::new();
+            let mut error_failures = Vec::new();
+            for state_name in states {
+                let state = match state_name {
+                    "Data state" => State::Data,
+                    "PLAINTEXT state" => State::Plaintext,
+                    "RCDATA state" => State::Rcdata,
+                    "RAWTEXT state" => State::Rawtext,
+                    "Script data state" => State::ScriptData,
+                    "CDATA section state" => State::CdataSection,
+                    state_name => panic!("unknown state {state_name}"),
+                };
+                let (got, errors) = tokens(input, state, last_start_tag);
+                let got = Json::Array(got);
+                if got != output {
+                    token_failures.push(format!(
+                        "{description} ({state_name})\ninput: {input:?}\nexpected: {output:?}\ngot: {got:?}"
+                    ));
+                }
+                if errors.len() != expected_errors {
+                    error_failures.push(format!(
+                        "{description} ({state_name})\ninput: {input:?}\nexpected {expected_errors} errors, got {}: {errors:?}",
+                        errors.len()
+                    ));
+                }
+            }
+
+            let failure =
+                |failures: Vec<String>| (!failures.is_empty()).then(|| failures.join("\n"));
+            token_results.push((case.clone(), failure(token_failures)));
+            error_results.push((case, failure(error_failures)));
+        }
+    }
+
+    assert!(!token_results.is_empty());
+    assert_expected("tokens", token_results);
+    assert_expected("token-errors", error_results);
+}